@@ -0,0 +1,217 @@
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::Path;
+
+use crate::perms;
+
+/// Previous-record hash of the first record of a chain
+const GENESIS: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// Number of records and hash of the last one; anchors a chain against truncation
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Head {
+    pub records: usize,
+    pub hash: String,
+}
+
+impl Head {
+    /// `<records> <hash>`
+    pub fn parse(contents: &str) -> Option<Head> {
+        let (records, hash) = contents.trim().split_once(' ')?;
+        Some(Head { records: records.parse().ok()?, hash: hash.to_string() })
+    }
+
+    /// Replace the head at `path` in one step, so a crash leaves either the old or the new one
+    pub fn write(&self, path: &Path) -> Result<(), String> {
+        let tmp = path.with_extension("head.tmp");
+        perms::write(&tmp, format!("{} {}\n", self.records, self.hash))
+            .and_then(|()| fs::rename(&tmp, path))
+            .map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+    }
+
+    /// This head, or the one after `last` if `last` was appended after it and the head was not
+    /// updated, as after a crash in between. Any other last record is left for `verify` to report.
+    pub fn catch_up(self, last: Option<&str>) -> Head {
+        match last {
+            Some(last) if line_hash(last) != self.hash && split(last).1 == Some(self.hash.as_str()) => {
+                Head { records: self.records + 1, hash: line_hash(last) }
+            }
+            _ => self,
+        }
+    }
+}
+
+/// One record of a chain, as exported
+#[derive(Serialize, Debug)]
+pub struct Record {
+    pub line: usize,
+    pub text: String,
+    /// hash of the previous record the line carries, None for records from before chaining
+    pub prev: Option<String>,
+    /// sha256 of the whole line
+    pub hash: String,
+}
+
+/// Result of checking a chain
+#[derive(Debug, Default)]
+pub struct Report {
+    pub records: Vec<Record>,
+    /// records from before chaining, at the start of the file
+    pub unchained: usize,
+    pub problems: Vec<String>,
+}
+
+/// sha256 of a record line
+pub fn line_hash(line: &str) -> String {
+    format!("{:x}", Sha256::digest(line.as_bytes()))
+}
+
+/// `<text>\t<hash of the previous record>`; a record is always a single line
+pub fn link(prev: &Head, text: &str) -> String {
+    format!("{}\t{}", text.replace('\n', "\\n"), prev.hash)
+}
+
+/// Text and previous-record hash of a line; lines from before chaining have no hash
+fn split(line: &str) -> (&str, Option<&str>) {
+    match line.rsplit_once('\t') {
+        Some((text, hash)) if hash.len() == 64 && hash.bytes().all(|b| b.is_ascii_hexdigit()) => (text, Some(hash)),
+        _ => (line, None),
+    }
+}
+
+/// A record without its chain hash, for display
+pub fn text(line: &str) -> &str {
+    split(line).0
+}
+
+/// Head of the chain in `contents`
+pub fn head(contents: &str) -> Head {
+    match contents.lines().last() {
+        Some(last) => Head { records: contents.lines().count(), hash: line_hash(last) },
+        None => Head { records: 0, hash: GENESIS.to_string() },
+    }
+}
+
+/// Append `texts` to the chain in `contents`
+pub fn extend(contents: &str, texts: &[&str]) -> String {
+    let mut contents = contents.to_string();
+    if !contents.is_empty() && !contents.ends_with('\n') {
+        contents.push('\n');
+    }
+    let mut head = head(&contents);
+    for text in texts {
+        let line = link(&head, text);
+        head = Head { records: head.records + 1, hash: line_hash(&line) };
+        contents.push_str(&line);
+        contents.push('\n');
+    }
+    contents
+}
+
+/// Check that every record carries the hash of the one before it, and that the chain
+/// ends at `anchor` when one is known. Records from before chaining may only come first.
+pub fn verify(contents: &str, anchor: Option<&Head>) -> Report {
+    let mut report = Report::default();
+    let mut prev = GENESIS.to_string();
+    for (i, line) in contents.lines().enumerate() {
+        let (text, linked) = split(line);
+        match linked {
+            None if report.records.iter().all(|record| record.prev.is_none()) => report.unchained += 1,
+            None => report.problems.push(format!("record {} has no chain hash", i + 1)),
+            Some(linked) if linked != prev && i == 0 => {
+                report.problems.push("record 1 is not the start of the chain: earlier records were removed".to_string())
+            }
+            Some(linked) if linked != prev => {
+                report.problems.push(format!("record {} does not follow record {}: records were edited, removed or inserted", i + 1, i))
+            }
+            Some(_) => {}
+        }
+        let hash = line_hash(line);
+        report.records.push(Record { line: i + 1, text: text.to_string(), prev: linked.map(str::to_string), hash: hash.clone() });
+        prev = hash;
+    }
+    if let Some(anchor) = anchor {
+        let records = report.records.len();
+        if records < anchor.records {
+            report.problems.push(format!("{} records, but the chain ended at record {}: the end was truncated", records, anchor.records));
+        } else if anchor.records > 0 && report.records[anchor.records - 1].hash != anchor.hash {
+            report.problems.push(format!("record {} differs from the recorded end of the chain", anchor.records));
+        } else if records > anchor.records {
+            report.problems.push(format!("{} records were appended after the recorded end of the chain", records - anchor.records));
+        }
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chain(texts: &[&str]) -> String {
+        extend("", texts)
+    }
+
+    #[test]
+    fn clean_chain_verifies() {
+        let contents = chain(&["one", "two", "three"]);
+        let report = verify(&contents, Some(&head(&contents)));
+        assert!(report.problems.is_empty(), "{:?}", report.problems);
+        assert_eq!(report.records.len(), 3);
+        assert_eq!(text(contents.lines().next().unwrap()), "one");
+    }
+
+    #[test]
+    fn extending_keeps_the_chain() {
+        let contents = extend(&chain(&["one"]), &["two", "three"]);
+        assert_eq!(contents, chain(&["one", "two", "three"]));
+    }
+
+    #[test]
+    fn edited_record_is_detected() {
+        let contents = chain(&["one", "two", "three"]).replace("two", "tw0");
+        assert_eq!(verify(&contents, None).problems.len(), 1);
+    }
+
+    #[test]
+    fn removed_and_inserted_records_are_detected() {
+        let contents = chain(&["one", "two", "three"]);
+        let lines: Vec<&str> = contents.lines().collect();
+        let removed = format!("{}\n{}\n", lines[0], lines[2]);
+        assert!(!verify(&removed, None).problems.is_empty());
+        let first_removed = format!("{}\n{}\n", lines[1], lines[2]);
+        assert!(!verify(&first_removed, None).problems.is_empty());
+        let inserted = format!("{}\nextra\n{}\n{}\n", lines[0], lines[1], lines[2]);
+        assert!(!verify(&inserted, None).problems.is_empty());
+    }
+
+    #[test]
+    fn truncation_is_detected_against_the_head() {
+        let contents = chain(&["one", "two", "three"]);
+        let anchor = head(&contents);
+        let truncated: String = contents.lines().take(2).map(|line| format!("{}\n", line)).collect();
+        assert!(verify(&truncated, None).problems.is_empty());
+        assert!(!verify(&truncated, Some(&anchor)).problems.is_empty());
+        assert!(!verify("", Some(&anchor)).problems.is_empty());
+    }
+
+    #[test]
+    fn unchained_records_may_only_come_first() {
+        let contents = extend("old one\nold two\n", &["new"]);
+        let report = verify(&contents, None);
+        assert!(report.problems.is_empty());
+        assert_eq!(report.unchained, 2);
+        assert!(!verify(&format!("{}stray\n", contents), None).problems.is_empty());
+    }
+
+    #[test]
+    fn head_catches_up_with_a_record_appended_before_a_crash() {
+        let before = chain(&["one", "two"]);
+        let after = extend(&before, &["three"]);
+        let head = head(&before).catch_up(after.lines().last());
+        assert_eq!(head, super::head(&after));
+        // an unrelated last line is not adopted
+        let forged = head.clone().catch_up(Some("forged"));
+        assert_eq!(forged, head);
+    }
+}
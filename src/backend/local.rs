@@ -0,0 +1,96 @@
+use std::fs;
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+use super::Backend;
+use crate::perms;
+
+/// Objects as files under a directory
+pub struct LocalBackend {
+    root: PathBuf,
+}
+
+impl LocalBackend {
+    pub fn new(root: PathBuf) -> Self {
+        Self { root }
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.root.join(key)
+    }
+}
+
+impl Backend for LocalBackend {
+    fn name(&self) -> &'static str {
+        "local"
+    }
+
+    fn put(&self, key: &str, data: &[u8]) -> Result<(), String> {
+        let path = self.path(key);
+        if let Some(parent) = path.parent() {
+            perms::create_dir(parent).map_err(|e| format!("Failed to create directory: {}", e))?;
+        }
+        perms::write(&path, data).map_err(|e| format!("Failed to write {}: {}", key, e))
+    }
+
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>, String> {
+        match fs::read(self.path(key)) {
+            Ok(data) => Ok(Some(data)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(format!("Failed to read {}: {}", key, e)),
+        }
+    }
+
+    fn list(&self, prefix: &str) -> Result<Vec<String>, String> {
+        let mut keys = Vec::new();
+        walk(&self.root, &self.root, &mut keys)?;
+        keys.retain(|key| key.starts_with(prefix));
+        keys.sort();
+        Ok(keys)
+    }
+
+    fn delete(&self, key: &str) -> Result<(), String> {
+        match fs::remove_file(self.path(key)) {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(format!("Failed to delete {}: {}", key, e)),
+        }
+    }
+
+    fn append_line(&self, key: &str, line: &str) -> Result<(), String> {
+        let path = self.path(key);
+        if let Some(parent) = path.parent() {
+            perms::create_dir(parent).map_err(|e| format!("Failed to create directory: {}", e))?;
+        }
+        let mut file = perms::append(&path)
+            .map_err(|e| format!("Failed to open {}: {}", key, e))?;
+        writeln!(file, "{}", line).map_err(|e| format!("Failed to write {}: {}", key, e))
+    }
+
+    fn local_root(&self) -> Option<&Path> {
+        Some(&self.root)
+    }
+}
+
+/// Collect the keys of all files below `dir`, skipping hidden entries (`.git`, `.lock`)
+fn walk(root: &Path, dir: &Path, keys: &mut Vec<String>) -> Result<(), String> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(format!("Failed to list {}: {}", dir.display(), e)),
+    };
+    for entry in entries {
+        let entry = entry.map_err(|e| format!("Failed to list {}: {}", dir.display(), e))?;
+        if entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+        let path = entry.path();
+        if path.is_dir() {
+            walk(root, &path, keys)?;
+        } else if let Ok(relative) = path.strip_prefix(root) {
+            let key: Vec<String> = relative.components().map(|c| c.as_os_str().to_string_lossy().to_string()).collect();
+            keys.push(key.join("/"));
+        }
+    }
+    Ok(())
+}
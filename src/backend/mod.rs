@@ -0,0 +1,213 @@
+use directories::ProjectDirs;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+use crate::audit;
+use crate::config::load_config;
+use crate::vault;
+
+pub mod local;
+pub mod s3;
+
+pub use local::LocalBackend;
+pub use s3::S3Backend;
+
+/// Index of backup messages, one line per backup
+pub const HISTORY_LOG: &str = "history.log";
+
+/// Where the objects of a profile (alias index, stored contents, histories) live.
+/// Keys are `/`-separated paths relative to the profile, e.g. `store/zsh/content`.
+pub trait Backend: Send + Sync {
+    /// Short name used in messages
+    fn name(&self) -> &'static str;
+
+    fn put(&self, key: &str, data: &[u8]) -> Result<(), String>;
+
+    /// Contents of an object, or None if it does not exist
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>, String>;
+
+    /// Keys starting with `prefix`, sorted
+    fn list(&self, prefix: &str) -> Result<Vec<String>, String>;
+
+    /// Remove an object; missing objects are not an error
+    fn delete(&self, key: &str) -> Result<(), String>;
+
+    /// Replace an object with `change` applied to its current contents (None if missing).
+    /// Backends shared by several hosts only write if the object did not change since it was
+    /// read, and run `change` again on the newer contents otherwise.
+    fn update(&self, key: &str, change: &mut dyn FnMut(Option<Vec<u8>>) -> Result<Vec<u8>, String>) -> Result<(), String> {
+        let data = change(self.get(key)?)?;
+        self.put(key, &data)
+    }
+
+    /// Append a line to an object, creating it if needed.
+    /// Backends that cannot append in place read, extend and rewrite it.
+    fn append_line(&self, key: &str, line: &str) -> Result<(), String> {
+        self.update(key, &mut |data| {
+            let mut data = data.unwrap_or_default();
+            data.extend_from_slice(line.as_bytes());
+            data.push(b'\n');
+            Ok(data)
+        })
+    }
+
+    /// Lines of the history index, without their chain hashes
+    fn history(&self) -> Result<Vec<String>, String> {
+        let data = self.get(HISTORY_LOG)?.unwrap_or_default();
+        Ok(String::from_utf8_lossy(&data).lines().map(|line| audit::text(line).to_string()).collect())
+    }
+
+    /// Add a backup message to the history index, linked to the previous one
+    fn record_history(&self, text: &str) -> Result<(), String> {
+        self.update(HISTORY_LOG, &mut |data| {
+            let data = data.unwrap_or_default();
+            Ok(audit::extend(&String::from_utf8_lossy(&data), &[text]).into_bytes())
+        })
+    }
+
+    /// Directory holding the objects, for backends that keep them on this machine.
+    /// Git versioning, remotes and migrations only work on such a directory.
+    fn local_root(&self) -> Option<&Path> {
+        None
+    }
+}
+
+/// Backend of a profile, set in `[backends.<profile>]` of config.toml
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum BackendConfig {
+    /// the profile's git repository in the data directory
+    #[default]
+    Local,
+    /// an S3-compatible bucket (AWS, MinIO, ...)
+    S3 {
+        /// e.g. `https://s3.eu-west-1.amazonaws.com` or `http://127.0.0.1:9000`
+        endpoint: String,
+        bucket: String,
+        /// key prefix in the bucket; the profile name is appended
+        #[serde(default, skip_serializing_if = "String::is_empty")]
+        prefix: String,
+        #[serde(default = "default_region")]
+        region: String,
+        /// environment variable holding the access key
+        #[serde(default = "default_access_key_env")]
+        access_key_env: String,
+        /// environment variable holding the secret key
+        #[serde(default = "default_secret_key_env")]
+        secret_key_env: String,
+    },
+}
+
+fn default_region() -> String {
+    "us-east-1".to_string()
+}
+
+fn default_access_key_env() -> String {
+    "AWS_ACCESS_KEY_ID".to_string()
+}
+
+fn default_secret_key_env() -> String {
+    "AWS_SECRET_ACCESS_KEY".to_string()
+}
+
+/// Data directory of the profile: the repository for local profiles,
+/// logs and locks for the others
+pub fn data_dir(profile: &str) -> Result<PathBuf, String> {
+    let project_dirs = ProjectDirs::from("", "", "confsync")
+        .ok_or_else(|| "Failed to find config directory".to_string())?;
+    Ok(project_dirs.data_dir().join(profile))
+}
+
+/// Configured backend of the profile
+pub fn config_for(profile: &str) -> Result<BackendConfig, String> {
+    Ok(load_config()?.backends.get(profile).cloned().unwrap_or_default())
+}
+
+/// True if the profile keeps its objects in its local git repository
+pub fn is_local(profile: &str) -> Result<bool, String> {
+    Ok(matches!(config_for(profile)?, BackendConfig::Local))
+}
+
+/// Fail for profiles whose backups are not in a local git repository
+pub fn require_local(profile: &str, what: &str) -> Result<(), String> {
+    match config_for(profile)? {
+        BackendConfig::Local => Ok(()),
+        BackendConfig::S3 { .. } => Err(format!("{} needs the local backend; profile {} uses s3", what, profile)),
+    }
+}
+
+/// Open the backend of the profile, unlocking it if the repository is passphrase-encrypted
+pub fn open(profile: &str) -> Result<Box<dyn Backend>, String> {
+    vault::wrap(open_raw(profile)?)
+}
+
+/// Open the backend of the profile without decrypting anything
+pub fn open_raw(profile: &str) -> Result<Box<dyn Backend>, String> {
+    match config_for(profile)? {
+        BackendConfig::Local => Ok(Box::new(LocalBackend::new(data_dir(profile)?))),
+        BackendConfig::S3 { endpoint, bucket, prefix, region, access_key_env, secret_key_env } => {
+            let access_key = std::env::var(&access_key_env)
+                .map_err(|_| format!("Set {} to the access key of the s3 backend", access_key_env))?;
+            let secret_key = std::env::var(&secret_key_env)
+                .map_err(|_| format!("Set {} to the secret key of the s3 backend", secret_key_env))?;
+            let prefix = [prefix.trim_matches('/'), profile]
+                .iter()
+                .filter(|part| !part.is_empty())
+                .copied()
+                .collect::<Vec<_>>()
+                .join("/");
+            Ok(Box::new(S3Backend::new(endpoint, bucket, prefix, region, access_key, secret_key)))
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// What every backend must do; run against each implementation
+    pub fn contract(backend: &dyn Backend) {
+        assert_eq!(backend.get("store/zsh/content").unwrap(), None);
+        backend.put("store/zsh/content", b"one").unwrap();
+        backend.put("store/git/content", b"two").unwrap();
+        backend.put("aliases.toml", b"").unwrap();
+        assert_eq!(backend.get("store/zsh/content").unwrap().as_deref(), Some(&b"one"[..]));
+        assert_eq!(backend.list("store/").unwrap(), ["store/git/content", "store/zsh/content"]);
+
+        backend.append_line("store/zsh/history.cmt", "first").unwrap();
+        backend.append_line("store/zsh/history.cmt", "second").unwrap();
+        assert_eq!(backend.get("store/zsh/history.cmt").unwrap().as_deref(), Some(&b"first\nsecond\n"[..]));
+
+        backend.record_history("[1] one").unwrap();
+        backend.record_history("[2] two").unwrap();
+        assert_eq!(backend.history().unwrap(), ["[1] one", "[2] two"]);
+
+        backend
+            .update("store/zsh/content", &mut |data| {
+                assert_eq!(data.as_deref(), Some(&b"one"[..]));
+                Ok(b"three".to_vec())
+            })
+            .unwrap();
+        assert_eq!(backend.get("store/zsh/content").unwrap().as_deref(), Some(&b"three"[..]));
+        backend
+            .update("manifest.toml", &mut |data| {
+                assert_eq!(data, None);
+                Ok(b"new".to_vec())
+            })
+            .unwrap();
+        assert_eq!(backend.get("manifest.toml").unwrap().as_deref(), Some(&b"new"[..]));
+
+        backend.delete("store/zsh/content").unwrap();
+        backend.delete("store/zsh/content").unwrap();
+        assert_eq!(backend.get("store/zsh/content").unwrap(), None);
+        assert!(!backend.list("store/").unwrap().contains(&"store/zsh/content".to_string()));
+    }
+
+    #[test]
+    fn local_backend_keeps_the_contract() {
+        let root = std::env::temp_dir().join(format!("confsync-local-backend-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        contract(&LocalBackend::new(root.clone()));
+        let _ = std::fs::remove_dir_all(&root);
+    }
+}
@@ -0,0 +1,423 @@
+use directories::ProjectDirs;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::Backend;
+use crate::perms;
+
+/// Temp files of concurrent requests need distinct names
+static REQUEST: AtomicUsize = AtomicUsize::new(0);
+
+/// Conditional writes tried before giving up on an object other hosts keep changing
+const UPDATE_ATTEMPTS: u32 = 8;
+
+/// Objects in an S3-compatible bucket, addressed path style (`<endpoint>/<bucket>/<key>`).
+/// Requests are signed with SigV4 by curl, so no SDK is needed.
+pub struct S3Backend {
+    endpoint: String,
+    bucket: String,
+    /// key prefix of the profile, without slashes around it
+    prefix: String,
+    region: String,
+    access_key: String,
+    secret_key: String,
+}
+
+/// Status code, body and ETag of a response
+struct Response {
+    status: u16,
+    body: Vec<u8>,
+    etag: Option<String>,
+}
+
+impl S3Backend {
+    pub fn new(endpoint: String, bucket: String, prefix: String, region: String, access_key: String, secret_key: String) -> Self {
+        Self { endpoint: endpoint.trim_end_matches('/').to_string(), bucket, prefix, region, access_key, secret_key }
+    }
+
+    fn object_key(&self, key: &str) -> String {
+        if self.prefix.is_empty() { key.to_string() } else { format!("{}/{}", self.prefix, key) }
+    }
+
+    fn object_url(&self, key: &str) -> String {
+        format!("{}/{}/{}", self.endpoint, self.bucket, encode(&self.object_key(key), false))
+    }
+
+    /// Send a signed request; `body` is uploaded with PUT, `headers` are added as is
+    fn request(&self, method: &str, url: &str, body: Option<&[u8]>, headers: &[String]) -> Result<Response, String> {
+        let dir = temp_dir()?;
+        let id = format!("{}-{}", std::process::id(), REQUEST.fetch_add(1, Ordering::Relaxed));
+        let output_path = dir.join(format!("{}.out", id));
+        let input_path = dir.join(format!("{}.in", id));
+        let headers_path = dir.join(format!("{}.headers", id));
+
+        let payload_hash = format!("{:x}", Sha256::digest(body.unwrap_or_default()));
+        let mut command = Command::new("curl");
+        command
+            .args(["--silent", "--show-error", "--config", "-"])
+            .args(["--aws-sigv4", &format!("aws:amz:{}:s3", self.region)])
+            .args(["--header", &format!("x-amz-content-sha256: {}", payload_hash)])
+            .args(["--request", method, "--output"])
+            .arg(&output_path)
+            .arg("--dump-header")
+            .arg(&headers_path)
+            .args(["--write-out", "%{http_code}"]);
+        for header in headers {
+            command.args(["--header", header]);
+        }
+        if let Some(body) = body {
+            perms::write(&input_path, body).map_err(|e| format!("Failed to prepare upload: {}", e))?;
+            command.arg("--upload-file").arg(&input_path);
+        }
+        command.arg(url);
+
+        // credentials go through stdin so they never show up in the process list
+        let result = (|| {
+            let mut child = command
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .spawn()
+                .map_err(|e| format!("Failed to run curl: {}", e))?;
+            if let Some(mut stdin) = child.stdin.take() {
+                writeln!(stdin, "user = \"{}:{}\"", quote(&self.access_key), quote(&self.secret_key))
+                    .map_err(|e| format!("Failed to pass credentials to curl: {}", e))?;
+            }
+            let output = child.wait_with_output().map_err(|e| format!("Failed to run curl: {}", e))?;
+            if !output.status.success() {
+                return Err(format!("s3 {} failed: {}", method, String::from_utf8_lossy(&output.stderr).trim()));
+            }
+            let status = String::from_utf8_lossy(&output.stdout)
+                .trim()
+                .parse()
+                .map_err(|e| format!("Failed to read s3 response status: {}", e))?;
+            let body = fs::read(&output_path).unwrap_or_default();
+            let etag = fs::read_to_string(&headers_path).ok().and_then(|headers| header_value(&headers, "etag"));
+            Ok(Response { status, body, etag })
+        })();
+        let _ = fs::remove_file(&output_path);
+        let _ = fs::remove_file(&input_path);
+        let _ = fs::remove_file(&headers_path);
+        result
+    }
+
+    /// Response holding an object and its ETag, or None if it does not exist
+    fn get_tagged(&self, key: &str) -> Result<Option<Response>, String> {
+        let response = self.request("GET", &self.object_url(key), None, &[])?;
+        match response.status {
+            200..=299 => Ok(Some(response)),
+            404 => Ok(None),
+            _ => Err(self.error("GET", key, &response)),
+        }
+    }
+
+    fn error(&self, method: &str, key: &str, response: &Response) -> String {
+        let body = String::from_utf8_lossy(&response.body);
+        let code = tag_values(&body, "Code").into_iter().next().unwrap_or_default();
+        format!("s3 {} {} failed with {} {}", method, key, response.status, code).trim_end().to_string()
+    }
+}
+
+impl Backend for S3Backend {
+    fn name(&self) -> &'static str {
+        "s3"
+    }
+
+    fn put(&self, key: &str, data: &[u8]) -> Result<(), String> {
+        let response = self.request("PUT", &self.object_url(key), Some(data), &[])?;
+        match response.status {
+            200..=299 => Ok(()),
+            _ => Err(self.error("PUT", key, &response)),
+        }
+    }
+
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>, String> {
+        Ok(self.get_tagged(key)?.map(|response| response.body))
+    }
+
+    /// Written with `If-Match` on the ETag that was read (`If-None-Match: *` for a new object),
+    /// so a host never overwrites what another one wrote in between
+    fn update(&self, key: &str, change: &mut dyn FnMut(Option<Vec<u8>>) -> Result<Vec<u8>, String>) -> Result<(), String> {
+        for attempt in 1..=UPDATE_ATTEMPTS {
+            let (current, condition) = match self.get_tagged(key)? {
+                Some(Response { body, etag: Some(etag), .. }) => (Some(body), format!("If-Match: {}", etag)),
+                Some(_) => return Err(format!("s3 GET {} returned no ETag", key)),
+                None => (None, "If-None-Match: *".to_string()),
+            };
+            let data = change(current)?;
+            let response = self.request("PUT", &self.object_url(key), Some(&data), &[condition])?;
+            match response.status {
+                200..=299 => return Ok(()),
+                // changed by another host since it was read
+                409 | 412 => std::thread::sleep(backoff(attempt)),
+                _ => return Err(self.error("PUT", key, &response)),
+            }
+        }
+        Err(format!("{} kept changing on s3 while writing it, try again", key))
+    }
+
+    fn list(&self, prefix: &str) -> Result<Vec<String>, String> {
+        let full_prefix = self.object_key(prefix);
+        let strip = if self.prefix.is_empty() { String::new() } else { format!("{}/", self.prefix) };
+        let mut keys = Vec::new();
+        let mut token: Option<String> = None;
+        loop {
+            let mut url = format!("{}/{}?list-type=2&prefix={}", self.endpoint, self.bucket, encode(&full_prefix, true));
+            if let Some(token) = &token {
+                url.push_str(&format!("&continuation-token={}", encode(token, true)));
+            }
+            let response = self.request("GET", &url, None, &[])?;
+            if !(200..=299).contains(&response.status) {
+                return Err(self.error("LIST", prefix, &response));
+            }
+            let body = String::from_utf8_lossy(&response.body);
+            for key in tag_values(&body, "Key") {
+                if let Some(key) = key.strip_prefix(&strip) {
+                    keys.push(key.to_string());
+                }
+            }
+            let truncated = tag_values(&body, "IsTruncated").first().is_some_and(|t| t == "true");
+            token = tag_values(&body, "NextContinuationToken").into_iter().next();
+            if !truncated || token.is_none() {
+                break;
+            }
+        }
+        keys.sort();
+        Ok(keys)
+    }
+
+    fn delete(&self, key: &str) -> Result<(), String> {
+        let response = self.request("DELETE", &self.object_url(key), None, &[])?;
+        match response.status {
+            200..=299 | 404 => Ok(()),
+            _ => Err(self.error("DELETE", key, &response)),
+        }
+    }
+}
+
+/// Scratch directory for request and response bodies
+fn temp_dir() -> Result<PathBuf, String> {
+    let project_dirs = ProjectDirs::from("", "", "confsync")
+        .ok_or_else(|| "Failed to find cache directory".to_string())?;
+    let dir = project_dirs.cache_dir().join("s3");
+    perms::create_dir(&dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+    Ok(dir)
+}
+
+/// Wait before retrying a conditional write, longer each attempt and jittered
+/// so hosts that collided do not collide again
+fn backoff(attempt: u32) -> Duration {
+    let jitter = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.subsec_nanos() % 50).unwrap_or(0);
+    Duration::from_millis(u64::from(attempt * 50 + jitter))
+}
+
+/// Value of the last `name:` header in a header dump (the final response after redirects)
+fn header_value(headers: &str, name: &str) -> Option<String> {
+    headers
+        .lines()
+        .rev()
+        .filter_map(|line| line.split_once(':'))
+        .find(|(key, _)| key.trim().eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.trim().to_string())
+}
+
+/// Percent-encode for a URL path (`/` kept) or a query value
+fn encode(value: &str, query: bool) -> String {
+    let mut encoded = String::new();
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => encoded.push(byte as char),
+            b'/' if !query => encoded.push('/'),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+/// Escape a value for a double-quoted curl config string
+fn quote(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Text of every `<tag>…</tag>` in an XML document, unescaped
+fn tag_values(xml: &str, tag: &str) -> Vec<String> {
+    let open = format!("<{}>", tag);
+    let close = format!("</{}>", tag);
+    let mut values = Vec::new();
+    let mut rest = xml;
+    while let Some(start) = rest.find(&open) {
+        rest = &rest[start + open.len()..];
+        let Some(end) = rest.find(&close) else {
+            break;
+        };
+        values.push(
+            rest[..end]
+                .replace("&lt;", "<")
+                .replace("&gt;", ">")
+                .replace("&quot;", "\"")
+                .replace("&apos;", "'")
+                .replace("&amp;", "&"),
+        );
+        rest = &rest[end + close.len()..];
+    }
+    values
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+    use std::io::{BufRead, BufReader, Read};
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::{Arc, Mutex};
+
+    /// Objects of the stub by key, with the version their ETag is made of
+    type Objects = Arc<Mutex<(u64, BTreeMap<String, (u64, Vec<u8>)>)>>;
+
+    /// A bucket served over HTTP from memory, honouring `If-Match` and `If-None-Match` on PUT
+    fn stub() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let objects = Objects::default();
+        std::thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let _ = serve(stream, &objects);
+            }
+        });
+        format!("http://{}", address)
+    }
+
+    fn serve(stream: TcpStream, objects: &Objects) -> std::io::Result<()> {
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut request = String::new();
+        reader.read_line(&mut request)?;
+        let mut headers = BTreeMap::new();
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line)?;
+            match line.trim_end().split_once(':') {
+                Some((name, value)) => headers.insert(name.to_ascii_lowercase(), value.trim().to_string()),
+                None => break,
+            };
+        }
+        let mut stream = stream;
+        if headers.get("expect").is_some_and(|value| value.eq_ignore_ascii_case("100-continue")) {
+            stream.write_all(b"HTTP/1.1 100 Continue\r\n\r\n")?;
+        }
+        let mut body = vec![0; headers.get("content-length").and_then(|n| n.parse().ok()).unwrap_or(0)];
+        reader.read_exact(&mut body)?;
+
+        let mut parts = request.split_whitespace();
+        let (method, target) = (parts.next().unwrap_or_default(), parts.next().unwrap_or_default());
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        let key = decode(path.trim_start_matches("/bucket/"));
+        let mut objects = objects.lock().unwrap();
+        let (status, etag, body) = match method {
+            "GET" if query.contains("list-type=2") => {
+                let prefix = query.split('&').find_map(|pair| pair.strip_prefix("prefix=")).map(decode).unwrap_or_default();
+                let keys: String = objects.1.keys().filter(|k| k.starts_with(&prefix)).map(|k| format!("<Key>{}</Key>", k)).collect();
+                (200, None, format!("<ListBucketResult>{}<IsTruncated>false</IsTruncated></ListBucketResult>", keys).into_bytes())
+            }
+            "GET" => match objects.1.get(&key) {
+                Some((version, data)) => (200, Some(*version), data.clone()),
+                None => (404, None, b"<Error><Code>NoSuchKey</Code></Error>".to_vec()),
+            },
+            "PUT" => {
+                let current = objects.1.get(&key).map(|(version, _)| format!("\"{}\"", version));
+                let matches = match (headers.get("if-match"), headers.get("if-none-match")) {
+                    (Some(etag), _) => current.as_ref() == Some(etag),
+                    (_, Some(_)) => current.is_none(),
+                    _ => true,
+                };
+                if matches {
+                    objects.0 += 1;
+                    let version = objects.0;
+                    objects.1.insert(key, (version, body));
+                    (200, Some(version), Vec::new())
+                } else {
+                    (412, None, b"<Error><Code>PreconditionFailed</Code></Error>".to_vec())
+                }
+            }
+            "DELETE" => {
+                objects.1.remove(&key);
+                (204, None, Vec::new())
+            }
+            _ => (405, None, Vec::new()),
+        };
+        let etag = etag.map(|version| format!("ETag: \"{}\"\r\n", version)).unwrap_or_default();
+        write!(stream, "HTTP/1.1 {} Stub\r\n{}Content-Length: {}\r\nConnection: close\r\n\r\n", status, etag, body.len())?;
+        stream.write_all(&body)
+    }
+
+    fn decode(value: &str) -> String {
+        let bytes = value.as_bytes();
+        let mut decoded = Vec::new();
+        let mut i = 0;
+        while i < bytes.len() {
+            match (bytes[i], value.get(i + 1..i + 3).and_then(|hex| u8::from_str_radix(hex, 16).ok())) {
+                (b'%', Some(byte)) => {
+                    decoded.push(byte);
+                    i += 3;
+                }
+                (byte, _) => {
+                    decoded.push(byte);
+                    i += 1;
+                }
+            }
+        }
+        String::from_utf8_lossy(&decoded).to_string()
+    }
+
+    fn backend(endpoint: &str) -> S3Backend {
+        S3Backend::new(endpoint.to_string(), "bucket".into(), "team/work".into(), "us-east-1".into(), "key".into(), "secret".into())
+    }
+
+    #[test]
+    fn s3_backend_keeps_the_contract() {
+        crate::backend::tests::contract(&backend(&stub()));
+    }
+
+    #[test]
+    fn update_runs_again_when_another_host_wrote_in_between() {
+        let endpoint = stub();
+        let (mine, other) = (backend(&endpoint), backend(&endpoint));
+        mine.append_line("history.log", "first").unwrap();
+        let mut runs = 0;
+        mine.update("history.log", &mut |data| {
+            runs += 1;
+            if runs == 1 {
+                other.append_line("history.log", "theirs")?;
+            }
+            let mut data = data.unwrap_or_default();
+            data.extend_from_slice(b"mine\n");
+            Ok(data)
+        })
+        .unwrap();
+        assert_eq!(runs, 2);
+        assert_eq!(mine.get("history.log").unwrap().as_deref(), Some(&b"first\ntheirs\nmine\n"[..]));
+    }
+
+    #[test]
+    fn new_object_is_not_created_over_one_another_host_created() {
+        let endpoint = stub();
+        let (mine, other) = (backend(&endpoint), backend(&endpoint));
+        let mut runs = 0;
+        mine.update("aliases.toml", &mut |data| {
+            runs += 1;
+            if runs == 1 {
+                assert_eq!(data, None);
+                other.put("aliases.toml", b"theirs\n")?;
+            }
+            let mut data = data.unwrap_or_default();
+            data.extend_from_slice(b"mine\n");
+            Ok(data)
+        })
+        .unwrap();
+        assert_eq!(mine.get("aliases.toml").unwrap().as_deref(), Some(&b"theirs\nmine\n"[..]));
+    }
+}
@@ -41,12 +41,51 @@ pub enum Commands {
         force: bool,
     },
 
+    /// Set up this machine from the backups on a remote repository
+    Clone {
+        /// URL of the remote repository
+        remote: String,
+
+        /// Host whose backups to start from [default: this host, or the only one]
+        #[arg(long)]
+        host: Option<String>,
+
+        /// Rewrite tracked paths starting with FROM to start with TO (repeatable)
+        #[arg(long = "map", value_name = "FROM=TO")]
+        maps: Vec<String>,
+
+        /// Show the restore plan without restoring files
+        #[arg(long)]
+        dry_run: bool,
+
+        /// Restore without asking
+        #[arg(short, long)]
+        yes: bool,
+
+        /// Replace an existing configuration
+        #[arg(long)]
+        force: bool,
+
+        /// Only accept backups signed by this public key (repeatable)
+        #[arg(long, value_name = "KEY")]
+        trust: Vec<String>,
+    },
+
     /// Track a configuration file for backup
     Add {
         /// Unique name / alias for the configuration file
         name:String,
         /// Path to the configuration file to track
         path: String,
+        /// Store the file encrypted with age (creates a key on first use)
+        #[arg(long)]
+        encrypt: bool,
+        /// Store a file that looks sensitive (private keys, credentials) unencrypted anyway
+        #[arg(long, conflicts_with = "encrypt")]
+        allow_sensitive: bool,
+        /// Store the file as a template rendered for each host (see `confsync template`)
+        #[arg(long)]
+        template: bool,
     },
 
     /// Untrack a configuration file
@@ -72,27 +111,47 @@ pub enum Commands {
         #[arg(long)]
         push: bool,
 
-        /// Force push changes to the remote repository
+        /// Push and overwrite the remote even if it has diverged. ** Remote-only backups are lost! **
         #[arg(short, long)]
         force: bool,
 
         /// Track environmental variables
-        #[arg(long, default_value_t = false,required_unless_present = "alias")]
+        #[arg(long, default_value_t = false)]
         env: bool,
 
     },
 
+    /// Fetch backups from the remote repository
+    Pull {
+        /// Keep the local version of every alias changed on both machines
+        #[arg(long, conflicts_with = "theirs")]
+        mine: bool,
+
+        /// Take the remote version of every alias changed on both machines
+        #[arg(long)]
+        theirs: bool,
+
+        /// Merge the backups another host pushed instead of this host's (see `confsync hosts`)
+        #[arg(long)]
+        host: Option<String>,
+    },
+
     /// Restore a configuration file 
     Restore {
-        /// Commit hash or tag (e.g., @latest)
-        target: String,
+        /// Alias to restore. [default: all]
+        target: Option<String>,
 
+        /// Show what would be restored, without writing anything
         #[arg(short, long)]
         dry_run: bool,
 
         /// Overwrite if file exists
         #[arg(short, long)]
         overwrite: bool,
+
+        /// Restore from the latest backup another host pushed (see `confsync hosts`)
+        #[arg(long)]
+        host: Option<String>,
     },
 
     /// Show backup history
@@ -110,6 +169,18 @@ pub enum Commands {
         debounce: u64,
     },
 
+    /// Manage mirrors the backups are replicated to
+    Mirror {
+        #[command(subcommand)]
+        command: MirrorCommands,
+    },
+
+    /// Manage the keys encrypted files are stored with
+    Encrypt {
+        #[command(subcommand)]
+        command: EncryptCommands,
+    },
+
     /// Manage multiple profiles (Phase 2)
     Profile {
         #[command(subcommand)]
@@ -122,10 +193,10 @@ pub enum Commands {
         command: ConfigCommands,
     },
 
-    /// Forward git commands
+    /// Run git inside the profile repository
     Git {
         /// Git command to execute
-        #[arg(required = true)]
+        #[arg(required = true, trailing_var_arg = true, allow_hyphen_values = true)]
         args: Vec<String>,
     },
 
@@ -135,8 +206,39 @@ pub enum Commands {
         target: DeleteTarget,
     },
 
+    /// List the hosts with backups on the remote
+    Hosts,
+
     /// Show changed/untracked files
     Status,
+
+    /// Check the stored backups for likely secrets (keys, tokens, passwords)
+    Scan {
+        /// Only check this alias
+        alias: Option<String>,
+    },
+
+    /// Upgrade the repository to the current storage format
+    Migrate,
+
+    /// Check or export the tamper-evident history and log
+    Audit {
+        #[command(subcommand)]
+        command: AuditCommands,
+    },
+
+    /// Edit and preview files stored as per-host templates
+    Template {
+        #[command(subcommand)]
+        command: TemplateCommands,
+    },
+
+    /// Check for data, logs and keys other users can read, and offer to fix them
+    Doctor {
+        /// Fix what is found without asking
+        #[arg(short, long)]
+        yes: bool,
+    },
 }
 
 #[derive(Subcommand, Debug)]
@@ -153,11 +255,19 @@ pub enum DeleteTarget {
         #[arg(long, required = true)]
         force: bool,
     },
-    /// Delete the remote repository
+    /// Delete the backups on the remote repository (local backups are kept)
     Remote {
-        /// Confirm deletion operation. ( only deletes main branch ) ** There is no undo! **
+        /// Confirm deletion operation. ( on git remotes only deletes this host's branch ) ** There is no undo! **
         #[arg(long, required = true)]
         force: bool,
+
+        /// Skip the interactive confirmation
+        #[arg(short, long)]
+        yes: bool,
+
+        /// Required for s3 profiles: every host shares the bucket prefix, so all their backups are deleted
+        #[arg(long)]
+        all_hosts: bool,
     },
     /// Delete everything
     All {
@@ -167,6 +277,82 @@ pub enum DeleteTarget {
     },
 }
 
+#[derive(Subcommand, Debug)]
+pub enum MirrorCommands {
+    /// Replicate every push to another git remote or local directory
+    Add {
+        /// Name of the mirror
+        name: String,
+        /// Git URL or local directory (USB drive, NAS mount)
+        url: String,
+        /// A backup is only complete once this mirror has it
+        #[arg(long)]
+        required: bool,
+    },
+    /// Stop replicating to a mirror (its contents are kept)
+    Remove { name: String },
+    /// Show the last push and pending snapshots of every target
+    Status,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum EncryptCommands {
+    /// Create this machine's identity and print its public key
+    Init,
+    /// Encrypt to another public key too, so its owner can restore
+    AddKey {
+        /// An age public key (age1...) or a file holding public keys
+        key: String,
+    },
+    /// Re-encrypt every stored version of the encrypted aliases to the current recipients, with a
+    /// new identity. Git history is rewritten and force pushed to the remote and mirrors.
+    /// An interrupted rotation continues where it stopped when run again
+    Rotate {
+        /// Keep the current identity; only re-encrypt (e.g. after add-key)
+        #[arg(long)]
+        keep_identity: bool,
+    },
+    /// Encrypt the whole repository, metadata included, with a passphrase
+    Passphrase,
+    /// Change the repository passphrase without re-encrypting the backups
+    ChangePassphrase,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum TemplateCommands {
+    /// Edit the stored template in $EDITOR, store it and render it to the tracked file
+    Edit {
+        /// Alias of a template
+        alias: String,
+    },
+    /// Print the output of a template
+    Render {
+        /// Alias of a template
+        alias: String,
+        /// Render for this host's variables instead of this machine's
+        #[arg(long)]
+        host: Option<String>,
+    },
+    /// List the variables templates can use
+    Vars {
+        /// Variables of this host instead of this machine
+        #[arg(long)]
+        host: Option<String>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum AuditCommands {
+    /// Check that history.log and log.txt were not edited or truncated
+    Verify,
+    /// Write both hash chains as JSON, for archiving elsewhere
+    Export {
+        /// Write to this file instead of stdout
+        #[arg(short, long)]
+        output: Option<std::path::PathBuf>,
+    },
+}
+
 #[derive(Subcommand, Debug)]
 pub enum ProfileCommands {
     Create { name: String, repo_url: Option<String> },
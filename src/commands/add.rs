@@ -1,9 +1,10 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use crate::ops::write_log;
+use crate::ui::{self, printer};
 
 
-pub fn handle_add(path: String, name: String, profile: &str) {
+pub fn handle_add(path: String, name: String, encrypt: bool, allow_sensitive: bool, template: bool, profile: &str) {
        // check if config file exists
        if !crate::config::check_config_exists() {
         println!(" Please run `confsync init` to initialize.");
@@ -11,7 +12,8 @@ pub fn handle_add(path: String, name: String, profile: &str) {
         return;
     }
     // path to PathBuf
-    let path = match PathBuf::from(path).canonicalize() {
+    let given = PathBuf::from(path);
+    let path = match given.canonicalize() {
         Ok(p) => p,
         Err(e) => {
             write_log("error", "ADD", &format!("Error resolving path: {}", e), None).unwrap();
@@ -20,16 +22,97 @@ pub fn handle_add(path: String, name: String, profile: &str) {
         }
     };
 
+    // private keys and credentials are stored encrypted unless explicitly allowed
+    let sensitive = match sensitive_pattern(&given, &path) {
+        Ok(pattern) if encrypt => pattern.is_some(),
+        Ok(Some(pattern)) if !allow_sensitive => {
+            write_log("warn", "ADD", &format!("Refused to add sensitive file {} ({})", given.display(), pattern), None).unwrap();
+            printer(
+                format!("{} looks sensitive ({}). Add it with --encrypt, or --allow-sensitive to store it as it is.", given.display(), pattern).as_str(),
+                ui::MessageType::Error,
+            );
+            return;
+        }
+        Ok(Some(pattern)) => {
+            printer(format!("Storing {} unencrypted although it looks sensitive ({})", given.display(), pattern).as_str(), ui::MessageType::Warning);
+            true
+        }
+        Ok(None) => false,
+        Err(e) => {
+            write_log("error", "ADD", &format!("Error checking path: {}", e), None).unwrap();
+            eprintln!("Error checking path: {}", e);
+            return;
+        }
+    };
+
+    // an alias that is already tracked becomes a template of its stored content
+    if template && crate::config::get_path_from_alias(&name).is_ok_and(|tracked| tracked == path) {
+        if let Err(e) = crate::config::set_template(&name) {
+            write_log("error", "ADD", &format!("Error making {} a template: {}", name, e), None).unwrap();
+            eprintln!("Error making {} a template: {}", name, e);
+            return;
+        }
+        write_log("info", "ADD", &format!("{} is now a template", name), None).unwrap();
+        printer(format!("{} is now a template. Change it with `confsync template edit {}`", name, name).as_str(), ui::MessageType::Success);
+        if !encrypt {
+            return;
+        }
+    }
+
+    if encrypt {
+        if let Err(e) = create_identity() {
+            write_log("error", "ADD", &format!("Error creating identity: {}", e), None).unwrap();
+            eprintln!("Error creating identity: {}", e);
+            return;
+        }
+        // an alias that is already tracked is stored again, encrypted
+        if crate::config::get_path_from_alias(&name).is_ok_and(|tracked| tracked == path) {
+            if let Err(e) = crate::config::set_encrypted(&name) {
+                eprintln!("Error enabling encryption: {}", e);
+                return;
+            }
+            match crate::ops::copy_file_to_repo(path, name.as_str(), profile, true) {
+                Ok(()) => printer(format!("{} is now stored encrypted", name).as_str(), ui::MessageType::Success),
+                Err(e) => {
+                    write_log("error", "ADD", &format!("Error copying file to repo: {}", e), None).unwrap();
+                    eprintln!("Error copying file to repo: {}", e);
+                }
+            }
+            return;
+        }
+    }
+
     // add to tracking
     match crate::config::add_tracking_file(path.clone(),name.clone()) {
         Ok(()) => {
             write_log("info", "ADD", &format!("Added {} to tracking as {}", path.display(), name), None).unwrap();
             println!("Added {} to tracking as {}", path.display(), name);
+            if encrypt {
+                if let Err(e) = crate::config::set_encrypted(&name) {
+                    write_log("error", "ADD", &format!("Error enabling encryption: {}", e), None).unwrap();
+                    eprintln!("Error enabling encryption: {}", e);
+                    return;
+                }
+            } else if sensitive {
+                // status stops flagging it
+                if let Err(e) = crate::config::allow_sensitive(&name) {
+                    write_log("error", "ADD", &format!("Error allowing sensitive file: {}", e), None).unwrap();
+                    eprintln!("Error allowing sensitive file: {}", e);
+                    return;
+                }
+                write_log("warn", "ADD", &format!("Stored sensitive file {} unencrypted as allowed", path.display()), None).unwrap();
+            }
+            if template {
+                if let Err(e) = crate::config::set_template(&name) {
+                    write_log("error", "ADD", &format!("Error making {} a template: {}", name, e), None).unwrap();
+                    eprintln!("Error making {} a template: {}", name, e);
+                    return;
+                }
+            }
             // copy the file to the repo
-            if let Err(e) = crate::ops::copy_file_to_repo(path.clone(), name.as_str(), &profile,true) {
+            if let Err(e) = crate::ops::copy_file_to_repo(path.clone(), name.as_str(), profile,true) {
                 write_log("error", "ADD", &format!("Error copying file to repo: {}", e), None).unwrap();
                 eprintln!("Error copying file to repo: {}", e);
-                return;
             } else {
                 write_log("info", "ADD", &format!("File {} copied to repo successfully", name), None).unwrap();
             }
@@ -37,7 +120,29 @@ pub fn handle_add(path: String, name: String, profile: &str) {
         Err(e) => {
             write_log("error", "ADD", &format!("Error adding tracking: {}", e), None).unwrap();
             eprintln!("Error adding tracking: {}", e);
-            return;
         }
     }
 }
+
+/// Catalog pattern the path matches, as given (e.g. a symlink into ~/.ssh) or resolved
+fn sensitive_pattern(given: &Path, path: &Path) -> Result<Option<String>, String> {
+    let config = crate::config::load_config()?;
+    let given = std::path::absolute(given).map_err(|e| format!("Failed to resolve {}: {}", given.display(), e))?;
+    match crate::sensitive::matches(&given, &config)? {
+        Some(pattern) => Ok(Some(pattern)),
+        None => crate::sensitive::matches(path, &config),
+    }
+}
+
+/// Create this machine's identity on first use of encryption
+fn create_identity() -> Result<(), String> {
+    if let Some(public_key) = crate::crypto::ensure_identity()? {
+        let identity = crate::crypto::identity_path()?;
+        write_log("info", "ADD", &format!("Created identity {}", identity.display()), None)?;
+        printer(
+            format!("Created identity {} (public key {}). Keep a copy of it: without it encrypted backups cannot be restored.", identity.display(), public_key).as_str(),
+            ui::MessageType::Warning,
+        );
+    }
+    Ok(())
+}
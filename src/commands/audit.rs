@@ -0,0 +1,152 @@
+use serde::Serialize;
+use std::fs;
+
+use crate::audit::{self, Head, Record, Report};
+use crate::backend::{self, HISTORY_LOG};
+use crate::cli::AuditCommands;
+use crate::config::{self, check_config_exists, load_config};
+use crate::ops::{log_head_path, log_path, write_log};
+use crate::ui::{self, printer};
+use crate::{perms, signing, store};
+
+/// One hash chain in an export
+#[derive(Serialize)]
+struct Chain {
+    /// recorded end of the chain, if there is one
+    anchor: Option<Head>,
+    intact: bool,
+    problems: Vec<String>,
+    records: Vec<Record>,
+}
+
+#[derive(Serialize)]
+struct Export {
+    profile: String,
+    host: Option<String>,
+    exported: String,
+    history: Chain,
+    log: Chain,
+}
+
+pub fn handle_audit(command: AuditCommands, profile: &str) {
+    if !check_config_exists() {
+        println!(" Please run `confsync init` to initialize.");
+        return;
+    }
+    let result = match command {
+        AuditCommands::Verify => verify(profile),
+        AuditCommands::Export { output } => export(output.as_deref(), profile),
+    };
+    match result {
+        Ok(true) => {}
+        Ok(false) => std::process::exit(1),
+        Err(e) => {
+            write_log("error", "AUDIT", &format!("Error reading the audit trail: {}", e), Some(profile.to_string())).unwrap();
+            printer(format!("Error reading the audit trail: {}", e).as_str(), ui::MessageType::Error);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// The history index, checked against the end recorded in the signed snapshot
+fn history_chain(profile: &str) -> Result<(Report, Option<Head>), String> {
+    let backend = backend::open(profile)?;
+    let contents = backend.get(HISTORY_LOG)?.unwrap_or_default();
+    let mut problems = Vec::new();
+    let anchor = match backend.get(signing::MANIFEST_FILE)? {
+        Some(manifest) => {
+            let index = store::read_index(&*backend)?;
+            match signing::verify(Some(&manifest), &index, &load_config()?.signing.trusted) {
+                Ok(_) => signing::parse_manifest(&manifest)?.history(),
+                Err(e) => {
+                    problems.push(format!("signed snapshot: {}", e));
+                    None
+                }
+            }
+        }
+        None => None,
+    };
+    let mut report = audit::verify(&String::from_utf8_lossy(&contents), anchor.as_ref());
+    report.problems.splice(0..0, problems);
+    Ok((report, anchor))
+}
+
+/// The log, checked against the end recorded next to it
+fn log_chain(profile: &str) -> (Report, Option<Head>) {
+    let contents = fs::read_to_string(log_path(profile)).unwrap_or_default();
+    // a record whose head update was cut short is taken up by the next write; it is not tampering
+    let anchor = fs::read_to_string(log_head_path(profile))
+        .ok()
+        .and_then(|head| Head::parse(&head))
+        .map(|head| head.catch_up(contents.lines().last()));
+    (audit::verify(&contents, anchor.as_ref()), anchor)
+}
+
+/// Check both chains; false if either was changed
+fn verify(profile: &str) -> Result<bool, String> {
+    // read before this run logs anything of its own
+    let (log, log_anchor) = log_chain(profile);
+    let (history, history_anchor) = history_chain(profile)?;
+
+    let history_end = if history_anchor.is_some() { "signed end" } else { "no signed end" };
+    let log_end = if log_anchor.is_some() { "recorded end" } else { "no recorded end" };
+    let intact = print_chain("history", &history, history_end) & print_chain("log", &log, log_end);
+
+    if intact {
+        write_log("info", "AUDIT", "Audit trail verified", Some(profile.to_string()))?;
+        printer("Audit trail intact", ui::MessageType::Success);
+    } else {
+        let count = history.problems.len() + log.problems.len();
+        write_log("warn", "AUDIT", &format!("Audit trail verification found {} problems", count), Some(profile.to_string()))?;
+        printer("Audit trail was changed: records were edited, removed or added", ui::MessageType::Error);
+    }
+    Ok(intact)
+}
+
+/// One line per chain and one per problem; true if the chain is intact
+fn print_chain(name: &str, report: &Report, end: &str) -> bool {
+    let legacy = match report.unchained {
+        0 => String::new(),
+        count => format!(", {} from before chaining", count),
+    };
+    if report.problems.is_empty() {
+        ui::print_table(name, &format!("{} records, intact ({}{})", report.records.len(), end, legacy), None);
+        return true;
+    }
+    let noun = if report.problems.len() == 1 { "problem" } else { "problems" };
+    ui::print_table(name, &format!("{} records, {} {}", report.records.len(), report.problems.len(), noun), None);
+    for problem in &report.problems {
+        printer(format!("    {}", problem).as_str(), ui::MessageType::Default);
+    }
+    false
+}
+
+/// Write both chains as JSON to `output`, or stdout
+fn export(output: Option<&std::path::Path>, profile: &str) -> Result<bool, String> {
+    let (log, log_anchor) = log_chain(profile);
+    let (history, history_anchor) = history_chain(profile)?;
+    let chain = |report: Report, anchor: Option<Head>| Chain {
+        anchor,
+        intact: report.problems.is_empty(),
+        problems: report.problems,
+        records: report.records,
+    };
+    let export = Export {
+        profile: profile.to_string(),
+        host: config::host_name().ok(),
+        exported: chrono::Local::now().format("%Y-%m-%dT%H:%M:%S%:z").to_string(),
+        history: chain(history, history_anchor),
+        log: chain(log, log_anchor),
+    };
+    let json = serde_json::to_string_pretty(&export).map_err(|e| format!("Failed to serialize audit trail: {}", e))?;
+
+    match output {
+        Some(path) => {
+            perms::write(path, json + "\n").map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+            write_log("info", "AUDIT", &format!("Audit trail exported to {}", path.display()), Some(profile.to_string()))?;
+            printer(format!("Audit trail exported to {}", path.display()).as_str(), ui::MessageType::Success);
+        }
+        None => println!("{}", json),
+    }
+    Ok(true)
+}
@@ -0,0 +1,258 @@
+use std::path::PathBuf;
+
+use crate::config::{self, check_config_exists, is_tracked};
+use crate::ops::{self, copy_file_to_repo, write_log, Transfer};
+use crate::{backend, lock, mirror, remote, repo};
+use crate::ui::{self, printer};
+
+pub fn handle_backup(
+    alias: Option<String>,
+    message: Option<String>,
+    push: bool,
+    force: bool,
+    env: bool,
+    quiet: bool,
+    profile: &str,
+) {
+    if !check_config_exists() {
+        println!(" Please run `confsync init` to initialize.");
+        write_log("warn", "BACKUP", "Attempt to backup without config", None).unwrap();
+        return;
+    }
+    //if env is true, save env variables into a new file in repo
+    if env {
+        if let Err(e) = ops::save_env_vars(profile) {
+            write_log("error", "BACKUP", &format!("Error saving env vars: {}", e), None).unwrap();
+            eprintln!("Error saving env vars: {}", e);
+            return;
+        } else {
+            ui::printer("Env saved successfully", ui::MessageType::Success);
+            write_log("info", "BACKUP", "Env vars saved successfully", None).unwrap();
+        }
+    }
+
+    let ok = match alias {
+        Some(alias) => backup_alias(&alias, message, profile),
+        None => backup_all(message, quiet, profile),
+    };
+    // --force only makes sense as a push that overwrites the remote
+    if ok && (push || force) {
+        if backend::is_local(profile).unwrap_or(true) {
+            push_backup(profile, force);
+        } else {
+            printer("This profile stores its backups remotely; nothing to push", ui::MessageType::Info);
+        }
+    }
+}
+
+/// Push to the remote and every mirror. The backup is complete once all required targets have it.
+pub fn push_backup(profile: &str, force: bool) {
+    let mirrors = config::load_config().map(|config| config.storage.mirrors).unwrap_or_default();
+    // the remote is always required, when there is one
+    let mut missing = Vec::new();
+    if remote::remote_url().is_ok() || mirrors.is_empty() {
+        match remote::push(profile, force) {
+            Ok(remote::Push::Pushed) => {
+                write_log("info", "BACKUP", "Backup pushed to remote", Some(profile.to_string())).unwrap();
+                printer("Pushed to remote", ui::MessageType::Success);
+            }
+            Ok(remote::Push::Queued(e)) => {
+                write_log("warn", "BACKUP", &format!("Push queued: {}", e), Some(profile.to_string())).unwrap();
+                printer("Remote unreachable; the backup is saved locally and will be pushed later", ui::MessageType::Warning);
+                missing.push(remote::REMOTE.to_string());
+            }
+            Err(e) => {
+                write_log("error", "BACKUP", &format!("Error pushing backup: {}", e), Some(profile.to_string())).unwrap();
+                printer(format!("Error pushing backup: {}", e).as_str(), ui::MessageType::Error);
+                missing.push(remote::REMOTE.to_string());
+            }
+        }
+    }
+    if mirrors.is_empty() {
+        return;
+    }
+
+    match mirror::push_all(profile) {
+        Ok(results) => {
+            for (mirror, result) in results {
+                match result {
+                    Ok(()) => ui::print_table(&mirror.name, "pushed", None),
+                    Err(e) => {
+                        ui::print_table(&mirror.name, &format!("failed: {}", e.lines().next().unwrap_or_default()), None);
+                        if mirror.required {
+                            missing.push(mirror.name);
+                        }
+                    }
+                }
+            }
+        }
+        Err(e) => {
+            write_log("error", "BACKUP", &format!("Error pushing to mirrors: {}", e), Some(profile.to_string())).unwrap();
+            printer(format!("Error pushing to mirrors: {}", e).as_str(), ui::MessageType::Error);
+            missing.extend(mirrors.into_iter().filter(|m| m.required).map(|m| m.name));
+        }
+    }
+    if missing.is_empty() {
+        printer("Backup complete on all required targets", ui::MessageType::Success);
+    } else {
+        let message = format!("Backup incomplete: not on required {}", missing.join(", "));
+        write_log("warn", "BACKUP", &message, Some(profile.to_string())).unwrap();
+        printer(&message, ui::MessageType::Error);
+    }
+}
+
+/// Push backups queued while the remote was unreachable, and to mirrors that missed a push.
+/// Stays quiet while they still are unreachable.
+pub fn retry_queued_push(profile: &str) {
+    if !backend::is_local(profile).unwrap_or(false) {
+        return;
+    }
+    let Ok(_lock) = lock::lock_repo(profile) else {
+        // another run holds the repository; it or the next one retries
+        return;
+    };
+    match remote::retry_queued(profile) {
+        Ok(Some(count)) => {
+            let noun = if count == 1 { "snapshot" } else { "snapshots" };
+            write_log("info", "PUSH", &format!("Pushed {} queued {}", count, noun), Some(profile.to_string())).unwrap();
+            printer(format!("Pushed {} queued {}", count, noun).as_str(), ui::MessageType::Success);
+        }
+        Ok(None) => {}
+        Err(e) => {
+            write_log("warn", "PUSH", &format!("Queued push failed: {}", e), Some(profile.to_string())).unwrap();
+        }
+    }
+    if let Ok(results) = mirror::retry_failed(profile) {
+        for (mirror, result) in results {
+            if result.is_ok() {
+                printer(format!("Mirror {} caught up", mirror.name).as_str(), ui::MessageType::Success);
+            }
+        }
+    }
+}
+
+/// Returns false if the backup failed
+fn backup_alias(alias: &str, message: Option<String>, profile: &str) -> bool {
+    if !is_tracked(alias) {
+        println!("{} not found", alias);
+        write_log("warn", "BACKUP", &format!("{} not found.", alias), None).unwrap();
+        return false;
+    }
+    // get the path of the file from alias
+    let path = match config::get_path_from_alias(alias) {
+        Ok(path) => path,
+        Err(e) => {
+            write_log("error", "BACKUP", &format!("Error getting path from alias: {}", e), None).unwrap();
+            eprintln!("Error getting path from alias: {}", e);
+            return false;
+        }
+    };
+    // check if the file exists
+    if !path.exists() {
+        println!("File {} not found.", path.display());
+        write_log("warn", "BACKUP", &format!("File {} not found.", path.display()), None).unwrap();
+        return false;
+    }
+    // copy the file to the repo
+    if let Err(e) = copy_file_to_repo(path.clone(), alias, profile, false) {
+        write_log("error", "BACKUP", &format!("Error copying file to repo: {}", e), None).unwrap();
+        eprintln!("Error copying file to repo: {}", e);
+        return false;
+    } else {
+        write_log("info", "BACKUP", &format!("File {} copied to repo successfully", alias), None).unwrap();
+    }
+    record_backup(profile, message.as_deref().unwrap_or(alias))
+}
+
+/// Back up every tracked file concurrently. Returns false if nothing could be backed up.
+fn backup_all(message: Option<String>, quiet: bool, profile: &str) -> bool {
+    let files: Vec<(String, PathBuf)> = match config::tracked_files() {
+        Ok(files) => files,
+        Err(e) => {
+            write_log("error", "BACKUP", &format!("Error reading tracked files: {}", e), None).unwrap();
+            printer(format!("Error reading tracked files: {}", e).as_str(), ui::MessageType::Error);
+            return false;
+        }
+    };
+    if files.is_empty() {
+        printer("No files are being tracked.", ui::MessageType::Default);
+        return true;
+    }
+
+    let progress = ui::progress_bar(files.len(), "Backing up", quiet);
+    let results = match ops::backup_files(&files, profile, false, &progress) {
+        Ok(results) => results,
+        Err(e) => {
+            write_log("error", "BACKUP", &format!("Error backing up files: {}", e), Some(profile.to_string())).unwrap();
+            printer(format!("Error backing up files: {}", e).as_str(), ui::MessageType::Error);
+            return false;
+        }
+    };
+    let aliases: Vec<&str> = files.iter().map(|(alias, _)| alias.as_str()).collect();
+    let (copied, failed) = print_summary("BACKUP", &aliases, &results, quiet, profile);
+
+    if failed > 0 && copied == 0 {
+        return false;
+    }
+    if copied > 0 {
+        let timestamp = chrono::Local::now().format("%Y-%m-%d %H:%M:%S");
+        let message = message.unwrap_or_else(|| format!("Backup: {}", timestamp));
+        return record_backup(profile, &message);
+    }
+    true
+}
+
+fn record_backup(profile: &str, message: &str) -> bool {
+    if let Err(e) = repo::commit(profile, message) {
+        write_log("error", "BACKUP", &format!("Error recording backup: {}", e), None).unwrap();
+        eprintln!("Error recording backup: {}", e);
+        false
+    } else {
+        write_log("info", "BACKUP", "Backup completed successfully", None).unwrap();
+        printer("Done", ui::MessageType::Default);
+        true
+    }
+}
+
+/// Print per-file results in input order followed by a count line.
+/// Failures and likely secrets are always listed; other lines are skipped under --quiet.
+/// Returns the number of files copied and the number that failed.
+pub fn print_summary(
+    action: &str,
+    aliases: &[&str],
+    results: &[Result<Transfer, String>],
+    quiet: bool,
+    profile: &str,
+) -> (usize, usize) {
+    let (mut copied, mut unchanged, mut failed) = (0, 0, 0);
+    for (alias, result) in aliases.iter().zip(results) {
+        match result {
+            Ok(Transfer::Copied) => {
+                copied += 1;
+                if !quiet {
+                    printer(format!("{:<15} copied", alias).as_str(), ui::MessageType::Default);
+                }
+            }
+            Ok(Transfer::Flagged(found)) => {
+                copied += 1;
+                printer(format!("{:<15} copied, looks like it contains secrets: {}", alias, found).as_str(), ui::MessageType::Warning);
+            }
+            Ok(Transfer::Unchanged) => {
+                unchanged += 1;
+                if !quiet {
+                    printer(format!("{:<15} unchanged", alias).as_str(), ui::MessageType::Default);
+                }
+            }
+            Err(e) => {
+                failed += 1;
+                write_log("error", action, &format!("{}: {}", alias, e), Some(profile.to_string())).unwrap();
+                printer(format!("{:<15} {}", alias, e).as_str(), ui::MessageType::Error);
+            }
+        }
+    }
+
+    let summary = format!("{} copied, {} unchanged, {} failed", copied, unchanged, failed);
+    let message_type = if failed > 0 { ui::MessageType::Warning } else { ui::MessageType::Success };
+    printer(&summary, message_type);
+    (copied, failed)
+}
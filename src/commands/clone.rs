@@ -0,0 +1,340 @@
+use directories::{BaseDirs, ProjectDirs};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::backend::LocalBackend;
+use crate::commands::{backup::print_summary, restore};
+use crate::config::{self, Config};
+use crate::ops::{self, write_log};
+use crate::ui::{self, printer};
+use crate::{crypto, format, remote, signing, store, vault};
+
+/// Alias the config file is backed up under by `confsync init`
+const CONFIG_ALIAS: &str = "confsync";
+
+/// Flags of `confsync clone`
+pub struct CloneOptions {
+    pub host: Option<String>,
+    /// `FROM=TO` path prefixes
+    pub maps: Vec<String>,
+    pub dry_run: bool,
+    pub yes: bool,
+    pub force: bool,
+    /// public keys the backups have to be signed with
+    pub trust: Vec<String>,
+}
+
+pub fn handle_clone(url: String, options: CloneOptions, quiet: bool, profile: &str) {
+    let CloneOptions { host, maps, dry_run, yes, force, trust } = options;
+    if config::check_config_exists() && !force {
+        ui::printer("confsync is already set up on this machine", ui::MessageType::Success);
+        ui::printer("\nuse --force to replace the configuration", ui::MessageType::Default);
+        write_log("info", "CLONE", "Clone aborted: config already exists", None).unwrap();
+        return;
+    }
+    let maps = match parse_maps(&maps) {
+        Ok(maps) => maps,
+        Err(e) => {
+            printer(&e, ui::MessageType::Error);
+            return;
+        }
+    };
+
+    let project_dirs = ProjectDirs::from("", "", "confsync").expect("Failed to get project directories");
+    // a dry run clones into the cache and throws it away afterwards
+    let repo_path = if dry_run {
+        let preview = project_dirs.cache_dir().join(profile).join("clone");
+        let _ = fs::remove_dir_all(&preview);
+        preview
+    } else {
+        project_dirs.data_dir().join(profile)
+    };
+
+    let source = match remote::clone(&repo_path, &url, host.as_deref()) {
+        Ok(source) => source,
+        Err(e) => {
+            write_log("error", "CLONE", &format!("Error cloning {}: {}", url, e), Some(profile.to_string())).unwrap();
+            printer(format!("Error cloning {}: {}", url, e).as_str(), ui::MessageType::Error);
+            return;
+        }
+    };
+    printer(format!("Cloned the backups of {}", source).as_str(), ui::MessageType::Success);
+    // checked before anything from the clone, the config included, is used
+    let trusted = match format::readable_version(&repo_path).and_then(|_| verify(&repo_path, &trust)) {
+        Ok(trusted) => trusted,
+        Err(e) => {
+            write_log("error", "CLONE", &format!("Refused backups of {}: {}", source, e), Some(profile.to_string())).unwrap();
+            printer(format!("Refused backups of {}: {}", source, e).as_str(), ui::MessageType::Error);
+            discard(&repo_path);
+            return;
+        }
+    };
+    if dry_run {
+        let result = preview(&repo_path, &url, &maps, trusted);
+        let _ = fs::remove_dir_all(&repo_path);
+        match result {
+            Ok(()) => printer("Dry run: nothing was installed or restored", ui::MessageType::Info),
+            Err(e) => printer(format!("Error preparing restore plan: {}", e).as_str(), ui::MessageType::Error),
+        }
+        return;
+    }
+    if let Err(e) = format::ensure_current(profile, true) {
+        write_log("error", "CLONE", &e, Some(profile.to_string())).unwrap();
+        printer(&e, ui::MessageType::Error);
+        return;
+    }
+
+    let mut config = match recover_config(&repo_path, &url, &maps) {
+        Ok(config) => config,
+        Err(e) => {
+            write_log("error", "CLONE", &format!("Error recovering config: {}", e), Some(profile.to_string())).unwrap();
+            printer(format!("Error recovering config: {}", e).as_str(), ui::MessageType::Error);
+            return;
+        }
+    };
+    // commands in the config run on every restore; only a trusted signature vouches for them
+    if !trusted && !review_commands(&config, yes) {
+        strip_commands(&mut config);
+        write_log("warn", "CLONE", "Commands in the stored config were not installed", Some(profile.to_string())).unwrap();
+        printer(
+            "Commands were not installed; add them back with `confsync config edit`",
+            ui::MessageType::Warning,
+        );
+    }
+    if let Err(e) = config::save_config(&config) {
+        write_log("error", "CLONE", &format!("Error saving config: {}", e), None).unwrap();
+        printer(format!("Error saving config: {}", e).as_str(), ui::MessageType::Error);
+        return;
+    }
+    write_log("info", "CLONE", &format!("Config recovered from {} ({})", url, source), Some(profile.to_string())).unwrap();
+
+    let files = restorable(&config);
+    if files.is_empty() {
+        printer("No files are being tracked.", ui::MessageType::Default);
+        return;
+    }
+    if let Err(e) = print_plan(&files, &repo_path, &config) {
+        printer(format!("Error preparing restore plan: {}", e).as_str(), ui::MessageType::Error);
+        return;
+    }
+    if !yes {
+        let question = format!("Restore {} files? [y/N]", files.len());
+        match ui::prompt(&question) {
+            Ok(answer) if answer == "y" || answer == "yes" => {}
+            Ok(_) => {
+                printer("Nothing restored. Run `confsync restore` later.", ui::MessageType::Default);
+                return;
+            }
+            Err(e) => {
+                printer(format!("{}. Use --yes, or run `confsync restore` later.", e).as_str(), ui::MessageType::Warning);
+                return;
+            }
+        }
+    }
+
+    // the plan was confirmed, so files that differ are replaced
+    let progress = ui::progress_bar(files.len(), "Restoring", quiet);
+    match ops::restore_files(&files, profile, true, &progress) {
+        Ok(results) => {
+            let aliases: Vec<&str> = files.iter().map(|(alias, _)| alias.as_str()).collect();
+            print_summary("RESTORE", &aliases, &results, quiet, profile);
+        }
+        Err(e) => {
+            write_log("error", "CLONE", &format!("Error restoring files: {}", e), Some(profile.to_string())).unwrap();
+            printer(format!("Error restoring files: {}", e).as_str(), ui::MessageType::Error);
+        }
+    }
+}
+
+/// Check the signature of the cloned backups against the --trust keys and the
+/// trust list of an existing config. Any --trust key makes a trusted signature required.
+/// True if a trusted key signed them.
+fn verify(repo_path: &Path, trust: &[String]) -> Result<bool, String> {
+    let mut signing = match config::check_config_exists() {
+        true => config::load_config()?.signing,
+        false => signing::SigningConfig::default(),
+    };
+    for key in trust {
+        signing::parse_public_key(key)?;
+        signing.trusted.insert(format!("--trust {}", signing::fingerprint(key)), key.clone());
+        signing.require_signed = true;
+    }
+    let index = store::load_index(repo_path)?;
+    let manifest = match fs::read(repo_path.join(signing::MANIFEST_FILE)) {
+        Ok(contents) => Some(vault::decode_at(repo_path, signing::MANIFEST_FILE, contents)?),
+        Err(_) => None,
+    };
+    if let signing::Verdict::Trusted { name } = signing::check(manifest.as_deref(), &index, &signing, "Backups")? {
+        printer(format!("Backups are signed by trusted key {}", name).as_str(), ui::MessageType::Success);
+        return Ok(true);
+    }
+    Ok(false)
+}
+
+/// Shell commands the stored config would run on this machine
+fn commands(config: &Config) -> Vec<(&'static str, &str)> {
+    let mut commands = Vec::new();
+    if let Some(command) = &config.secrets.command {
+        commands.push(("secrets.command", command.as_str()));
+    }
+    if let Some(command) = &config.encryption.passphrase_command {
+        commands.push(("encryption.passphrase_command", command.as_str()));
+    }
+    commands
+}
+
+/// Show the commands of an unsigned config and ask whether to keep them.
+/// --yes only answers the restore question, so nothing is kept without asking.
+fn review_commands(config: &Config, yes: bool) -> bool {
+    let commands = commands(config);
+    if commands.is_empty() {
+        return true;
+    }
+    printer("The stored config runs these commands, and its backups are not signed by a trusted key:", ui::MessageType::Warning);
+    for (key, command) in &commands {
+        printer(format!("  {} = {}", key, command).as_str(), ui::MessageType::Default);
+    }
+    if yes {
+        return false;
+    }
+    matches!(ui::prompt("Keep these commands? [y/N]").as_deref(), Ok("y" | "yes"))
+}
+
+/// Drop the commands so a cloned config cannot run anything until it is reviewed
+fn strip_commands(config: &mut Config) {
+    config.secrets.command = None;
+    config.encryption.passphrase_command = None;
+}
+
+/// Remove a refused clone so nothing uses it; the log stays
+fn discard(repo_path: &Path) {
+    let Ok(entries) = fs::read_dir(repo_path) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        if entry.file_name() == "log.txt" {
+            continue;
+        }
+        let path = entry.path();
+        let _ = if path.is_dir() { fs::remove_dir_all(&path) } else { fs::remove_file(&path) };
+    }
+}
+
+/// Print the restore plan of a cloned repository without installing anything
+fn preview(repo_path: &Path, url: &str, maps: &[(PathBuf, PathBuf)], trusted: bool) -> Result<(), String> {
+    let config = recover_config(repo_path, url, maps)?;
+    if !trusted && !commands(&config).is_empty() {
+        // --yes keeps nothing, so this only lists them
+        review_commands(&config, true);
+        printer("Clone asks before installing them", ui::MessageType::Default);
+    }
+    let files = restorable(&config);
+    if files.is_empty() {
+        printer("No files are being tracked.", ui::MessageType::Default);
+        return Ok(());
+    }
+    print_plan(&files, repo_path, &config)
+}
+
+/// Tracked files to restore, sorted by alias; the config itself is already installed
+fn restorable(config: &Config) -> Vec<(String, PathBuf)> {
+    let mut files: Vec<(String, PathBuf)> = config
+        .tracking
+        .file_map
+        .iter()
+        .filter(|(alias, _)| *alias != CONFIG_ALIAS)
+        .map(|(alias, path)| (alias.clone(), path.clone()))
+        .collect();
+    files.sort();
+    files
+}
+
+/// Parse `FROM=TO` path prefixes given with --map
+fn parse_maps(maps: &[String]) -> Result<Vec<(PathBuf, PathBuf)>, String> {
+    maps.iter()
+        .map(|map| match map.split_once('=') {
+            Some((from, to)) if !from.is_empty() && !to.is_empty() => Ok((PathBuf::from(from), PathBuf::from(to))),
+            _ => Err(format!("Invalid --map {}: expected FROM=TO", map)),
+        })
+        .collect()
+}
+
+/// Read the config stored under the `confsync` alias and adapt it to this machine.
+/// Backups without one get a config tracking every alias at its last source path.
+fn recover_config(repo_path: &Path, url: &str, maps: &[(PathBuf, PathBuf)]) -> Result<Config, String> {
+    let aliases = store::load_index(repo_path)?;
+
+    let mut config = match aliases.get(CONFIG_ALIAS) {
+        Some(record) => {
+            let contents = fs::read(store::content_path(repo_path, &record.key))
+                .map_err(|e| format!("Failed to read stored config: {}", e))?;
+            let contents = vault::decode_at(repo_path, &store::content_key(&record.key), contents)?;
+            let contents = if record.encrypted { crypto::decrypt(&contents)? } else { contents };
+            toml::from_str(&String::from_utf8_lossy(&contents)).map_err(|e| format!("Failed to parse stored config: {}", e))?
+        }
+        None => {
+            printer("Backups have no stored config; tracking every backed up alias", ui::MessageType::Warning);
+            let mut config = Config::default();
+            for (alias, record) in &aliases.aliases {
+                config.tracking.file_map.insert(alias.clone(), record.source.clone());
+            }
+            config
+        }
+    };
+
+    let local_config = config::default_config_path()
+        .ok_or_else(|| "Failed to find config directory".to_string())?;
+    let mut maps = maps.to_vec();
+    // the stored config path tells where the home directory was on the other machine
+    if let Some(old_config) = config.tracking.file_map.get(CONFIG_ALIAS) {
+        if let Some(home) = home_remap(old_config, &local_config) {
+            maps.push(home);
+        }
+    }
+
+    let file_map: HashMap<String, PathBuf> = config
+        .tracking
+        .file_map
+        .iter()
+        .map(|(alias, path)| {
+            let path = if alias == CONFIG_ALIAS { local_config.clone() } else { remap(path, &maps) };
+            (alias.clone(), path)
+        })
+        .collect();
+    config.tracking.file_map = file_map;
+
+    config.storage.repo_url = url.to_string();
+    config.storage.local = false;
+    // this machine records its backups under its own host name
+    config.storage.host = None;
+    Ok(config)
+}
+
+/// `(old home, new home)` if the config lived under another home directory
+fn home_remap(old_config: &Path, local_config: &Path) -> Option<(PathBuf, PathBuf)> {
+    let home = BaseDirs::new()?.home_dir().to_path_buf();
+    let relative = local_config.strip_prefix(&home).ok()?;
+    let old_home = old_config.to_str()?.strip_suffix(relative.to_str()?)?;
+    let old_home = PathBuf::from(old_home.trim_end_matches('/'));
+    if old_home.as_os_str().is_empty() || old_home == home {
+        return None;
+    }
+    Some((old_home, home))
+}
+
+/// Apply the first matching prefix mapping
+fn remap(path: &Path, maps: &[(PathBuf, PathBuf)]) -> PathBuf {
+    for (from, to) in maps {
+        if let Ok(rest) = path.strip_prefix(from) {
+            return to.join(rest);
+        }
+    }
+    path.to_path_buf()
+}
+
+/// Show where every file goes and whether it is new, replaced or already up to date
+fn print_plan(files: &[(String, PathBuf)], repo_path: &Path, config: &Config) -> Result<(), String> {
+    let backend = vault::wrap(Box::new(LocalBackend::new(repo_path.to_path_buf())))?;
+    restore::print_plan(files, &*backend, config)
+}
@@ -1,6 +1,6 @@
 use crate::cli::DeleteTarget;
 use crate::config::{check_config_exists, delete_config};
-use crate::{repo, ui};
+use crate::{backend, remote, repo, ui};
 use crate::ops::write_log;
 use crate::ui::printer;
 
@@ -24,7 +24,7 @@ pub fn handle_delete (target: DeleteTarget, profile: &str) {
         },
         DeleteTarget::Local { force } => {
             if force {
-                if let Err(e) = repo::delete_repo(&profile) {
+                if let Err(e) = repo::delete_repo(profile) {
                     write_log("error", "DELETE", &format!("Error deleting local repo: {}", e), None).unwrap();
                     printer(format!("Error deleting local repo: {}", e).as_str(), ui::MessageType::Error);
                 } else {
@@ -36,22 +36,16 @@ pub fn handle_delete (target: DeleteTarget, profile: &str) {
                 write_log("warn", "DELETE", "Attempt to delete local repo without force flag", None).unwrap();
             }
         },
-        DeleteTarget::Remote { force } => {
+        DeleteTarget::Remote { force, yes, all_hosts } => {
             if force {
-                if let Err(e) = repo::delete_repo(&profile) {
-                    write_log("error", "DELETE", &format!("Error deleting remote repo: {}", e), None).unwrap();
-                    printer(format!("Error deleting remote repo: {}", e).as_str(), ui::MessageType::Error);
-                } else {
-                    write_log("info", "DELETE", "Remote repository deleted", None).unwrap();
-                    printer("Remote repository deleted.", ui::MessageType::Info);
-                }
+                delete_remote(profile, yes, all_hosts);
             } else {
                 printer("Use --force to delete.", ui::MessageType::Warning);
             }
         },
         DeleteTarget::All { force } => {
             if force {
-                if let Err(e) = repo::delete_repo(&profile) {
+                if let Err(e) = repo::delete_repo(profile) {
                     write_log("error", "DELETE", &format!("Error deleting everything: {}", e), None).unwrap();
                     printer(format!("Error deleting all repos: {}", e).as_str(), ui::MessageType::Error);
                 } else {
@@ -71,3 +65,109 @@ pub fn handle_delete (target: DeleteTarget, profile: &str) {
         }
     }
 }
+
+/// Delete the backup branch on the remote after showing exactly what goes away
+fn delete_remote(profile: &str, yes: bool, all_hosts: bool) {
+    if !backend::is_local(profile).unwrap_or(true) {
+        return delete_objects(profile, yes, all_hosts);
+    }
+    let branch = match remote::remote_branch(profile) {
+        Ok(Some(branch)) => branch,
+        Ok(None) => {
+            printer("Nothing to delete: no backups on the remote.", ui::MessageType::Info);
+            return;
+        }
+        Err(e) => {
+            write_log("error", "DELETE", &format!("Error reading remote repo: {}", e), Some(profile.to_string())).unwrap();
+            printer(format!("Error reading remote repo: {}", e).as_str(), ui::MessageType::Error);
+            return;
+        }
+    };
+
+    printer("This will delete on the remote:", ui::MessageType::Warning);
+    ui::print_table("remote", &branch.url, None);
+    ui::print_table("branch", &branch.branch, None);
+    ui::print_table("last backup", &branch.head, Some("plain"));
+    ui::print_table("backups", &branch.commits.to_string(), Some("plain"));
+    printer("Local backups are kept.", ui::MessageType::Default);
+
+    if !yes && !confirm() {
+        return;
+    }
+
+    match remote::delete_branch(profile) {
+        Ok(emptied) => {
+            write_log("info", "DELETE", "Remote backups deleted", Some(profile.to_string())).unwrap();
+            printer("Remote backups deleted.", ui::MessageType::Info);
+            if emptied {
+                printer(
+                    format!("The remote refused to delete {}; it now holds a single empty commit.", branch.branch).as_str(),
+                    ui::MessageType::Default,
+                );
+            }
+        }
+        Err(e) => {
+            write_log("error", "DELETE", &format!("Error deleting remote repo: {}", e), Some(profile.to_string())).unwrap();
+            printer(format!("Error deleting remote repo: {}", e).as_str(), ui::MessageType::Error);
+        }
+    }
+}
+
+/// Ask before deleting remote backups. Returns false if the user declined.
+fn confirm() -> bool {
+    match ui::prompt("Delete the remote backups? [y/N]") {
+        Ok(answer) if answer.eq_ignore_ascii_case("y") || answer.eq_ignore_ascii_case("yes") => true,
+        Ok(_) => {
+            printer("Aborted.", ui::MessageType::Info);
+            false
+        }
+        Err(e) => {
+            printer(format!("{}. Use --yes to confirm.", e).as_str(), ui::MessageType::Error);
+            false
+        }
+    }
+}
+
+/// Delete every object of the profile from a remote backend such as s3.
+/// The objects are shared by every host of the profile, so this needs `--all-hosts`.
+fn delete_objects(profile: &str, yes: bool, all_hosts: bool) {
+    if !all_hosts {
+        printer(
+            "This profile stores its backups remotely, shared by every host that uses it. Use --all-hosts to delete them for all hosts.",
+            ui::MessageType::Warning,
+        );
+        write_log("warn", "DELETE", "Attempt to delete shared remote backups without --all-hosts", Some(profile.to_string())).unwrap();
+        return;
+    }
+    let result = backend::open(profile).and_then(|backend| backend.list("").map(|keys| (backend, keys)));
+    let (backend, keys) = match result {
+        Ok(found) => found,
+        Err(e) => {
+            write_log("error", "DELETE", &format!("Error listing backups: {}", e), Some(profile.to_string())).unwrap();
+            printer(format!("Error listing backups: {}", e).as_str(), ui::MessageType::Error);
+            return;
+        }
+    };
+    if keys.is_empty() {
+        printer("Nothing to delete: no backups on the remote.", ui::MessageType::Info);
+        return;
+    }
+
+    printer("This will delete on the remote:", ui::MessageType::Warning);
+    ui::print_table("backend", backend.name(), None);
+    ui::print_table("objects", &keys.len().to_string(), Some("plain"));
+    printer("These are the backups of every host using this profile.", ui::MessageType::Warning);
+    if !yes && !confirm() {
+        return;
+    }
+
+    for key in &keys {
+        if let Err(e) = backend.delete(key) {
+            write_log("error", "DELETE", &format!("Error deleting {}: {}", key, e), Some(profile.to_string())).unwrap();
+            printer(format!("Error deleting {}: {}", key, e).as_str(), ui::MessageType::Error);
+            return;
+        }
+    }
+    write_log("warn", "DELETE", &format!("Deleted {} objects from {}", keys.len(), backend.name()), Some(profile.to_string())).unwrap();
+    printer("Remote backups deleted.", ui::MessageType::Info);
+}
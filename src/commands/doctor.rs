@@ -0,0 +1,82 @@
+use directories::ProjectDirs;
+
+use crate::config::{check_config_exists, load_config};
+use crate::ops::write_log;
+use crate::perms::{self, Loose};
+use crate::ui::{self, printer};
+use crate::{crypto, signing};
+
+/// Check the local state for problems; exits with 1 when some are left unfixed
+pub fn handle_doctor(yes: bool, profile: &str) {
+    if !check_config_exists() {
+        println!(" Please run `confsync init` to initialize.");
+        return;
+    }
+    match check_permissions(yes, profile) {
+        Ok(true) => {}
+        Ok(false) => std::process::exit(1),
+        Err(e) => {
+            write_log("error", "DOCTOR", &format!("Error checking permissions: {}", e), Some(profile.to_string())).unwrap();
+            printer(format!("Error checking permissions: {}", e).as_str(), ui::MessageType::Error);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Backups, env snapshots, logs and keys should be readable by their owner only.
+/// Returns false if looser ones were found and left as they are.
+fn check_permissions(yes: bool, profile: &str) -> Result<bool, String> {
+    let loose = find_loose()?;
+    if loose.is_empty() {
+        write_log("info", "DOCTOR", "Permissions are owner-only", Some(profile.to_string()))?;
+        printer("Permissions are owner-only", ui::MessageType::Success);
+        return Ok(true);
+    }
+
+    let noun = if loose.len() == 1 { "path is" } else { "paths are" };
+    write_log("warn", "DOCTOR", &format!("{} {} accessible by other users", loose.len(), noun), Some(profile.to_string()))?;
+    printer(format!("{} {} accessible by other users:", loose.len(), noun).as_str(), ui::MessageType::Warning);
+    for path in &loose {
+        printer(
+            format!("    {:04o} {} (should be {:04o})", path.mode, ui::style_path(&path.path.display().to_string()), path.wanted).as_str(),
+            ui::MessageType::Default,
+        );
+    }
+    if !yes && !confirm() {
+        return Ok(false);
+    }
+
+    loose.iter().try_for_each(Loose::fix)?;
+    write_log("info", "DOCTOR", &format!("Restricted permissions of {} paths", loose.len()), Some(profile.to_string()))?;
+    printer(format!("Restricted permissions of {} paths", loose.len()).as_str(), ui::MessageType::Success);
+    Ok(true)
+}
+
+/// Everything in the data and cache directories (all profiles), and the key files
+fn find_loose() -> Result<Vec<Loose>, String> {
+    let project_dirs = ProjectDirs::from("", "", "confsync")
+        .ok_or_else(|| "Failed to find data directory".to_string())?;
+    let mut loose = perms::scan(project_dirs.data_dir())?;
+    loose.extend(perms::scan(project_dirs.cache_dir())?);
+
+    let config = load_config()?;
+    for key in [crypto::identity_path()?, signing::key_path(&config.signing)?, config.secrets.path()?] {
+        loose.extend(perms::check(&key)?);
+    }
+    Ok(loose)
+}
+
+/// Ask before changing permissions. Returns false if the user declined.
+fn confirm() -> bool {
+    match ui::prompt("Restrict them to the owner? [y/N]") {
+        Ok(answer) if answer.eq_ignore_ascii_case("y") || answer.eq_ignore_ascii_case("yes") => true,
+        Ok(_) => {
+            printer("Left unchanged.", ui::MessageType::Info);
+            false
+        }
+        Err(e) => {
+            printer(format!("{}. Use --yes to fix them.", e).as_str(), ui::MessageType::Error);
+            false
+        }
+    }
+}
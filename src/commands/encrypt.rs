@@ -0,0 +1,417 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::cli::EncryptCommands;
+use crate::config::{self, load_config, save_config};
+use crate::ops::write_log;
+use crate::perms;
+use crate::ui::{self, printer};
+use crate::commands::backup::push_backup;
+use crate::git::{self, BRANCH};
+use crate::{backend, crypto, remote, repo, store, vault};
+
+/// Progress of an interrupted `encrypt rotate`
+const ROTATION_FILE: &str = "confsync-rotation.toml";
+
+#[derive(Serialize, Deserialize, Debug)]
+struct Rotation {
+    started: String,
+    /// public keys the backups are re-encrypted to
+    recipients: Vec<String>,
+    /// aliases already re-encrypted, in backends without history
+    #[serde(default)]
+    done: BTreeSet<String>,
+    /// commits already rewritten: old hash to new hash
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    commits: BTreeMap<String, String>,
+    /// stored contents already re-encrypted: old blob to new blob
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    blobs: BTreeMap<String, String>,
+}
+
+pub fn handle_encrypt(command: EncryptCommands, quiet: bool, profile: &str) {
+    if !config::check_config_exists() {
+        println!(" Please run `confsync init` to initialize.");
+        return;
+    }
+    let result = match command {
+        EncryptCommands::Init => init(profile),
+        EncryptCommands::AddKey { key } => add_key(&key, profile),
+        EncryptCommands::Rotate { keep_identity } => rotate(keep_identity, quiet, profile),
+        EncryptCommands::Passphrase => enable_passphrase(quiet, profile),
+        EncryptCommands::ChangePassphrase => change_passphrase(profile),
+    };
+    if let Err(e) = result {
+        write_log("error", "ENCRYPT", &e, Some(profile.to_string())).unwrap();
+        printer(&e, ui::MessageType::Error);
+    }
+}
+
+fn init(profile: &str) -> Result<(), String> {
+    let path = crypto::identity_path()?;
+    match crypto::ensure_identity()? {
+        Some(public_key) => {
+            write_log("info", "ENCRYPT", &format!("Created identity {}", path.display()), Some(profile.to_string()))?;
+            printer(format!("Created identity {}", path.display()).as_str(), ui::MessageType::Success);
+            printer(format!("Public key: {}", public_key).as_str(), ui::MessageType::Default);
+            printer("Keep a copy of the identity: without it encrypted backups cannot be restored.", ui::MessageType::Warning);
+        }
+        None => {
+            printer(format!("Identity {} already exists", path.display()).as_str(), ui::MessageType::Info);
+            if let Some(public_key) = crypto::identity_public_keys()?.last() {
+                printer(format!("Public key: {}", public_key).as_str(), ui::MessageType::Default);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn add_key(input: &str, profile: &str) -> Result<(), String> {
+    let mut config = load_config()?;
+    let mut added = Vec::new();
+    for key in crypto::parse_recipients(input)? {
+        if !config.encryption.recipients.contains(&key) {
+            config.encryption.recipients.push(key.clone());
+            added.push(key);
+        }
+    }
+    if added.is_empty() {
+        printer("Key already added", ui::MessageType::Info);
+        return Ok(());
+    }
+    save_config(&config)?;
+    for key in &added {
+        write_log("info", "ENCRYPT", &format!("Added recipient {}", key), Some(profile.to_string()))?;
+        printer(format!("Added {}", key).as_str(), ui::MessageType::Success);
+    }
+    printer(
+        "New backups are encrypted to it. Run `confsync encrypt rotate --keep-identity` to re-encrypt the existing ones.",
+        ui::MessageType::Info,
+    );
+    Ok(())
+}
+
+/// Rotation progress lives next to the other local-only state, inside `.git` when there is one
+fn rotation_path(profile: &str) -> Result<PathBuf, String> {
+    let dir = backend::data_dir(profile)?;
+    let git_dir = dir.join(".git");
+    Ok(if git_dir.is_dir() { git_dir.join(ROTATION_FILE) } else { dir.join(ROTATION_FILE) })
+}
+
+fn save_rotation(path: &Path, rotation: &Rotation) -> Result<(), String> {
+    let contents = toml::to_string_pretty(rotation).map_err(|e| format!("Failed to serialize rotation: {}", e))?;
+    perms::write(path, contents).map_err(|e| format!("Failed to write rotation state: {}", e))
+}
+
+/// Switch the recipients to the new key set and record the rotation before touching any backup
+fn start_rotation(keep_identity: bool, path: &Path, profile: &str) -> Result<Rotation, String> {
+    let mut config = load_config()?;
+    if !keep_identity {
+        // old identities stay in the file so earlier snapshots can still be restored
+        let old_keys = crypto::identity_public_keys().unwrap_or_default();
+        let public_key = crypto::add_identity(&crypto::identity_path()?)?;
+        config.encryption.recipients.retain(|key| !old_keys.contains(key));
+        config.encryption.recipients.push(public_key.clone());
+        save_config(&config)?;
+        write_log("info", "ENCRYPT", &format!("Created identity {}", public_key), Some(profile.to_string()))?;
+        printer(format!("New public key: {}", public_key).as_str(), ui::MessageType::Default);
+    }
+    if config.encryption.recipients.is_empty() {
+        return Err("No encryption recipients configured. Run `confsync encrypt init` first.".into());
+    }
+    let rotation = Rotation {
+        started: chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+        recipients: config.encryption.recipients,
+        done: BTreeSet::new(),
+        commits: BTreeMap::new(),
+        blobs: BTreeMap::new(),
+    };
+    save_rotation(path, &rotation)?;
+    Ok(rotation)
+}
+
+/// Re-encrypt every stored version to the recipients of the rotation. In a git repository
+/// the whole history is rewritten, so removed keys open none of it; other backends keep no history.
+fn rotate(keep_identity: bool, quiet: bool, profile: &str) -> Result<(), String> {
+    let repo_path = backend::data_dir(profile)?;
+    let history = backend::is_local(profile)? && git::is_repo(&repo_path);
+    // a rewrite starts from a committed state; uncommitted files would be lost
+    if history && !git::git(&repo_path, &["status", "--porcelain"])?.is_empty() {
+        return Err("The repository has uncommitted changes. Run `confsync backup` first.".into());
+    }
+
+    let path = rotation_path(profile)?;
+    let mut rotation = match fs::read_to_string(&path) {
+        Ok(contents) => {
+            let rotation: Rotation =
+                toml::from_str(&contents).map_err(|e| format!("Failed to parse rotation state: {}", e))?;
+            printer(format!("Resuming the rotation started {}", rotation.started).as_str(), ui::MessageType::Info);
+            rotation
+        }
+        Err(_) => start_rotation(keep_identity, &path, profile)?,
+    };
+
+    let count = match history {
+        true => rewrite_history(&repo_path, &mut rotation, &path, quiet)?,
+        false => reencrypt_current(&mut rotation, &path, quiet, profile)?,
+    };
+    fs::remove_file(&path).map_err(|e| format!("Failed to remove rotation state: {}", e))?;
+    let noun = if history { "stored versions" } else { "aliases" };
+    let message = format!("Re-encrypted {} {} to {} recipients", count, noun, rotation.recipients.len());
+    write_log("info", "ENCRYPT", &message, Some(profile.to_string()))?;
+    printer(&message, ui::MessageType::Success);
+
+    // the remote and mirrors still hold the old history until it is replaced
+    let mirrors = load_config().map(|config| !config.storage.mirrors.is_empty()).unwrap_or(false);
+    if history && count > 0 && (remote::remote_url().is_ok() || mirrors) {
+        printer("Replacing the pushed history", ui::MessageType::Info);
+        push_backup(profile, true);
+    }
+    Ok(())
+}
+
+/// Re-encrypt the current content of every encrypted alias. Returns the number re-encrypted.
+fn reencrypt_current(rotation: &mut Rotation, path: &Path, quiet: bool, profile: &str) -> Result<usize, String> {
+    let backend = backend::open(profile)?;
+    let aliases = store::read_index(&*backend)?;
+    let pending: Vec<(&String, &store::AliasRecord)> = aliases
+        .aliases
+        .iter()
+        .filter(|(alias, record)| record.encrypted && !rotation.done.contains(*alias))
+        .collect();
+
+    let progress = ui::progress_bar(pending.len(), "Re-encrypting", quiet);
+    for (alias, record) in pending {
+        let key = store::content_key(&record.key);
+        let data = backend.get(&key)?.ok_or_else(|| format!("{} not found in backup", key))?;
+        // content written before an interruption is already readable with the new identity
+        let plaintext = crypto::decrypt(&data).map_err(|e| format!("{}: {}", alias, e))?;
+        backend.put(&key, &crypto::encrypt_to(&plaintext, &rotation.recipients)?)?;
+        rotation.done.insert(alias.clone());
+        save_rotation(path, rotation)?;
+        progress.inc(1);
+    }
+    progress.finish_and_clear();
+    if !rotation.done.is_empty() {
+        repo::commit(profile, "Rotate encryption keys")?;
+    }
+    Ok(rotation.done.len())
+}
+
+/// Rewrite every commit of the backup branch with its encrypted contents re-encrypted,
+/// then drop the old objects. Commits are rewritten oldest first and recorded as they go,
+/// so an interrupted run continues; the branch only moves once all are done.
+/// Returns the number of contents re-encrypted.
+fn rewrite_history(repo_path: &Path, rotation: &mut Rotation, path: &Path, quiet: bool) -> Result<usize, String> {
+    let head = git::git(repo_path, &["rev-parse", BRANCH])?;
+    // stopped after the branch moved: only the state file was left
+    if rotation.commits.values().any(|new| *new == head) {
+        return Ok(rotation.blobs.iter().filter(|(old, new)| old != new).count());
+    }
+    let commits = git::git(repo_path, &["rev-list", "--reverse", "--topo-order", "--parents", BRANCH])?;
+    let commits: Vec<Vec<&str>> = commits.lines().map(|line| line.split(' ').collect()).collect();
+
+    let progress = ui::progress_bar(commits.len(), "Rewriting history", quiet);
+    for hashes in &commits {
+        let (commit, parents) = (hashes[0], &hashes[1..]);
+        if rotation.commits.contains_key(commit) {
+            progress.inc(1);
+            continue;
+        }
+        let mut replaced = Vec::new();
+        let listing = git::git(repo_path, &["ls-tree", "-r", commit, "--", store::STORE_DIR])?;
+        for entry in listing.lines() {
+            let Some((meta, object)) = entry.split_once('\t') else {
+                continue;
+            };
+            let blob = meta.rsplit(' ').next().unwrap_or_default();
+            if !object.ends_with(&format!("/{}", store::CONTENT_FILE)) {
+                continue;
+            }
+            let new_blob = match rotation.blobs.get(blob) {
+                Some(new_blob) => new_blob.clone(),
+                None => {
+                    let new_blob = reencrypt_blob(repo_path, object, blob, &rotation.recipients)?;
+                    rotation.blobs.insert(blob.to_string(), new_blob.clone());
+                    new_blob
+                }
+            };
+            if new_blob != blob {
+                replaced.push((object.to_string(), new_blob));
+            }
+        }
+        let new_parents: Vec<String> = parents
+            .iter()
+            .map(|parent| rotation.commits.get(*parent).cloned().unwrap_or_else(|| parent.to_string()))
+            .collect();
+        let new_commit = if replaced.is_empty() && new_parents.iter().zip(parents.iter()).all(|(new, old)| new == old) {
+            commit.to_string()
+        } else {
+            let tree = match replaced.is_empty() {
+                true => git::git(repo_path, &["rev-parse", &format!("{}^{{tree}}", commit)])?,
+                false => git::replace_blobs(repo_path, commit, &replaced)?,
+            };
+            git::copy_commit(repo_path, commit, &tree, &new_parents)?
+        };
+        rotation.commits.insert(commit.to_string(), new_commit);
+        save_rotation(path, rotation)?;
+        progress.inc(1);
+    }
+    progress.finish_and_clear();
+
+    let new_head = rotation.commits.get(&head).cloned().unwrap_or(head.clone());
+    if new_head != head {
+        git::git(repo_path, &["update-ref", &format!("refs/heads/{}", BRANCH), &new_head, &head])?;
+        git::git(repo_path, &["reset", "--quiet", "--hard", &new_head])?;
+        // the old versions must not linger in the object store either
+        git::git(repo_path, &["reflog", "expire", "--expire=now", "--all"])?;
+        git::git(repo_path, &["gc", "--quiet", "--prune=now"])?;
+    }
+    Ok(rotation.blobs.iter().filter(|(old, new)| old != new).count())
+}
+
+/// The blob of stored content `object`, re-encrypted if it is encrypted. Returns the new blob,
+/// or the same one for plain content. Sealed content is opened and sealed again under its key.
+fn reencrypt_blob(repo_path: &Path, object: &str, blob: &str, recipients: &[String]) -> Result<String, String> {
+    let data = git::blob(repo_path, blob)?;
+    let sealed = vault::is_sealed(&data);
+    let inner = match sealed {
+        true => vault::decode_at(repo_path, object, data)?,
+        false => data,
+    };
+    if !crypto::is_encrypted(&inner) {
+        return Ok(blob.to_string());
+    }
+    let plaintext = crypto::decrypt(&inner).map_err(|e| format!("{}: {}", object, e))?;
+    let encrypted = crypto::encrypt_to(&plaintext, recipients)?;
+    let stored = match sealed {
+        true => vault::encode_at(repo_path, object, encrypted)?,
+        false => encrypted,
+    };
+    git::write_blob(repo_path, &stored)
+}
+
+/// Encrypt every object of the repository with a new data key wrapped by a passphrase.
+/// Storage keys derived from alias names are replaced by random ones. The vault is written
+/// first, so an interrupted run continues when started again.
+fn enable_passphrase(quiet: bool, profile: &str) -> Result<(), String> {
+    let raw = backend::open_raw(profile)?;
+    let existing = vault::read_vault(&*raw)?;
+    let resuming = existing.is_some();
+    let key = match existing {
+        Some(existing) => vault::unlock(&existing)?,
+        None => {
+            let (created, key) = vault::create(&vault::new_passphrase(true)?)?;
+            raw.put(vault::VAULT_FILE, vault::vault_contents(&created)?.as_bytes())?;
+            vault::remember(&created, key);
+            key
+        }
+    };
+
+    // the sealed index is written last, so until then an interrupted run starts over
+    let rekeyed = match raw.get(store::ALIASES_FILE)? {
+        Some(contents) if !vault::is_sealed(&contents) => {
+            let contents = read_object(&key, store::ALIASES_FILE, contents)?;
+            rekey(&*raw, &key, store::parse_index(&String::from_utf8_lossy(&contents))?)?
+        }
+        _ => 0,
+    };
+    remove_unused(&*raw, &key)?;
+
+    let mut pending = Vec::new();
+    for object in raw.list("")? {
+        if vault::is_plain_file(&object) {
+            continue;
+        }
+        if let Some(data) = raw.get(&object)? {
+            if !vault::is_sealed(&data) {
+                pending.push((object.clone(), read_object(&key, &object, data)?));
+            }
+        }
+    }
+    if resuming && pending.is_empty() && rekeyed == 0 {
+        return Err("Repository is already encrypted with a passphrase".into());
+    }
+
+    let progress = ui::progress_bar(pending.len(), "Encrypting", quiet);
+    for (object, data) in &pending {
+        raw.put(object, &vault::seal(&key, object, data)?)?;
+        progress.inc(1);
+    }
+    progress.finish_and_clear();
+    repo::commit(profile, "Encrypt repository with a passphrase")?;
+    write_log("info", "ENCRYPT", &format!("Encrypted {} objects with a passphrase", pending.len() + rekeyed), Some(profile.to_string()))?;
+    printer(format!("Repository encrypted ({} objects)", pending.len() + rekeyed).as_str(), ui::MessageType::Success);
+    if raw.local_root().is_some() {
+        printer(
+            "Snapshots committed before now stay readable in git history; `confsync delete remote` removes pushed ones.",
+            ui::MessageType::Warning,
+        );
+    }
+    Ok(())
+}
+
+/// Plaintext of an object that is plain or sealed
+fn read_object(key: &[u8; 32], object: &str, data: Vec<u8>) -> Result<Vec<u8>, String> {
+    match vault::is_sealed(&data) {
+        true => vault::open(key, object, data).map_err(|e| format!("{}: {}", object, e)),
+        false => Ok(data),
+    }
+}
+
+/// Move every alias to a random storage key, sealing its objects there, and write the sealed index.
+/// Returns the number of objects moved; the old ones are left for `remove_unused`.
+fn rekey(raw: &dyn backend::Backend, key: &[u8; 32], mut index: store::AliasIndex) -> Result<usize, String> {
+    let mut moved = 0;
+    for record in index.aliases.values_mut() {
+        let new_key = vault::random_name();
+        let prefix = format!("{}/{}/", store::STORE_DIR, record.key);
+        for object in raw.list(&prefix)? {
+            let Some(data) = raw.get(&object)? else {
+                continue;
+            };
+            let data = read_object(key, &object, data)?;
+            let target = format!("{}/{}/{}", store::STORE_DIR, new_key, &object[prefix.len()..]);
+            raw.put(&target, &vault::seal(key, &target, &data)?)?;
+            moved += 1;
+        }
+        record.key = new_key;
+    }
+    let contents = toml::to_string_pretty(&index).map_err(|e| format!("Failed to serialize alias index: {}", e))?;
+    raw.put(store::ALIASES_FILE, &vault::seal(key, store::ALIASES_FILE, contents.as_bytes())?)?;
+    Ok(moved + 1)
+}
+
+/// Delete stored objects of storage keys no alias uses: the names before `rekey`,
+/// or copies an interrupted run left behind
+fn remove_unused(raw: &dyn backend::Backend, key: &[u8; 32]) -> Result<(), String> {
+    let Some(contents) = raw.get(store::ALIASES_FILE)? else {
+        return Ok(());
+    };
+    let index = store::parse_index(&String::from_utf8_lossy(&read_object(key, store::ALIASES_FILE, contents)?))?;
+    let used: BTreeSet<&str> = index.aliases.values().map(|record| record.key.as_str()).collect();
+    for object in raw.list(&format!("{}/", store::STORE_DIR))? {
+        let storage_key = object.split('/').nth(1).unwrap_or_default();
+        if !used.contains(storage_key) {
+            raw.delete(&object)?;
+        }
+    }
+    Ok(())
+}
+
+/// Rewrap the data key with a new passphrase; the objects stay as they are
+fn change_passphrase(profile: &str) -> Result<(), String> {
+    let raw = backend::open_raw(profile)?;
+    let current = vault::read_vault(&*raw)?
+        .ok_or_else(|| "Repository is not encrypted with a passphrase. Run `confsync encrypt passphrase`.".to_string())?;
+    let key = vault::unlock(&current)?;
+    let rewrapped = vault::wrap_key(&key, &vault::new_passphrase(false)?)?;
+    raw.put(vault::VAULT_FILE, vault::vault_contents(&rewrapped)?.as_bytes())?;
+    vault::remember(&rewrapped, key);
+    repo::commit(profile, "Change repository passphrase")?;
+    write_log("info", "ENCRYPT", "Changed the repository passphrase", Some(profile.to_string()))?;
+    printer("Passphrase changed", ui::MessageType::Success);
+    Ok(())
+}
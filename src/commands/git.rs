@@ -0,0 +1,62 @@
+use directories::ProjectDirs;
+use std::io::{BufRead, BufReader};
+use std::process::{Command, Stdio};
+
+use crate::ops::write_log;
+use crate::ui::{self, printer};
+
+/// Run git against the profile repository. Returns git's exit code.
+pub fn handle_git(args: Vec<String>, profile: &str) -> i32 {
+    if let Err(e) = crate::backend::require_local(profile, "`confsync git`") {
+        printer(&e, ui::MessageType::Error);
+        return 1;
+    }
+    let project_dirs = ProjectDirs::from("", "", "confsync").expect("Failed to get project directories");
+    let repo_path = project_dirs.data_dir().join(profile);
+    if !crate::git::is_repo(&repo_path) {
+        printer("Repository does not exist. Please run `confsync init`.", ui::MessageType::Error);
+        return 1;
+    }
+
+    printer(format!("git {}", args.join(" ")).as_str(), ui::MessageType::Git);
+    write_log("info", "GIT", &format!("git {}", args.join(" ")), Some(profile.to_string())).unwrap();
+
+    let child = Command::new("git")
+        .args(&args)
+        .current_dir(&repo_path)
+        .env("GIT_DIR", repo_path.join(".git"))
+        .env("GIT_WORK_TREE", &repo_path)
+        .stdin(Stdio::inherit())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        .spawn();
+    let mut child = match child {
+        Ok(child) => child,
+        Err(e) => {
+            write_log("error", "GIT", &format!("Error running git: {}", e), Some(profile.to_string())).unwrap();
+            printer(format!("Error running git: {}", e).as_str(), ui::MessageType::Error);
+            return 1;
+        }
+    };
+
+    // stream stdout line by line instead of waiting for git to finish
+    if let Some(stdout) = child.stdout.take() {
+        for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+            ui::print_git_line(&line);
+        }
+    }
+
+    match child.wait() {
+        Ok(status) => {
+            let code = status.code().unwrap_or(1);
+            if code != 0 {
+                write_log("warn", "GIT", &format!("git exited with {}", code), Some(profile.to_string())).unwrap();
+            }
+            code
+        }
+        Err(e) => {
+            printer(format!("Error waiting for git: {}", e).as_str(), ui::MessageType::Error);
+            1
+        }
+    }
+}
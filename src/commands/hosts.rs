@@ -0,0 +1,27 @@
+use crate::config;
+use crate::ops::write_log;
+use crate::remote;
+use crate::ui::{self, printer};
+
+pub fn handle_hosts(profile: &str) {
+    let current = match config::host_name() {
+        Ok(host) => host,
+        Err(e) => {
+            printer(format!("Error getting host name: {}", e).as_str(), ui::MessageType::Error);
+            return;
+        }
+    };
+    match remote::hosts(profile) {
+        Ok(hosts) if hosts.is_empty() => printer("No host has pushed backups yet", ui::MessageType::Default),
+        Ok(hosts) => {
+            for host in hosts {
+                let name = if host.name == current { format!("{} *", host.name) } else { host.name };
+                ui::print_table(&name, &host.head, None);
+            }
+        }
+        Err(e) => {
+            write_log("error", "HOSTS", &format!("Error listing hosts: {}", e), Some(profile.to_string())).unwrap();
+            printer(format!("Error listing hosts: {}", e).as_str(), ui::MessageType::Error);
+        }
+    }
+}
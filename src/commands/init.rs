@@ -8,11 +8,7 @@ pub fn handle_init(repo_url: Option<String>, git: bool, force: bool, profile: Op
         return;
     }
     //  if repo_url is None or empty, set local to true
-    let local = if let Some(url) = repo_url.as_ref() {
-        !url.is_empty()
-    } else {
-        true
-    };
+    let local = repo_url.as_ref().is_none_or(|url| url.is_empty());
     let profile = profile.as_deref().unwrap_or("default");
     // load or create config
     let mut config = match load_config() {
@@ -63,6 +59,10 @@ pub fn handle_init(repo_url: Option<String>, git: bool, force: bool, profile: Op
         write_log("error", "INIT", &format!("Error copying config file to repo: {}", e), None).unwrap();
         eprintln!("Error copying config file to repo: {}", e);
     });
+    if let Err(e) = repo::commit(profile, "Initialize confsync") {
+        write_log("error", "INIT", &format!("Error committing initial state: {}", e), None).unwrap();
+        eprintln!("Error committing initial state: {}", e);
+    }
     
     ui::printer("✅ init completed", ui::MessageType::Success);
     ui::printer("use `confsync add` to add files", ui::MessageType::Default);
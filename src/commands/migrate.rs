@@ -0,0 +1,35 @@
+use crate::format::{self, CURRENT_VERSION};
+use crate::ops::write_log;
+use crate::ui::{self, printer};
+
+pub fn handle_migrate(profile: &str) {
+    match format::migrate(profile) {
+        Ok((_, None)) => {
+            printer(
+                format!("Repository already at format v{}", CURRENT_VERSION).as_str(),
+                ui::MessageType::Success,
+            );
+        }
+        Ok((from, Some(backup))) => {
+            write_log(
+                "info",
+                "MIGRATE",
+                &format!("Repository migrated v{} -> v{}", from, CURRENT_VERSION),
+                Some(profile.to_string()),
+            )
+            .unwrap();
+            printer(
+                format!("Migrated repository v{} -> v{}", from, CURRENT_VERSION).as_str(),
+                ui::MessageType::Success,
+            );
+            printer(
+                format!("Old layout kept at {}", backup.display()).as_str(),
+                ui::MessageType::Default,
+            );
+        }
+        Err(e) => {
+            write_log("error", "MIGRATE", &format!("Error migrating repository: {}", e), Some(profile.to_string())).unwrap();
+            printer(format!("Error migrating repository: {}", e).as_str(), ui::MessageType::Error);
+        }
+    }
+}
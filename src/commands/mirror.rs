@@ -0,0 +1,76 @@
+use crate::cli::MirrorCommands;
+use crate::config::{self, Mirror};
+use crate::mirror;
+use crate::ops::write_log;
+use crate::remote;
+use crate::ui::{self, printer};
+
+pub fn handle_mirror(command: MirrorCommands, profile: &str) {
+    let result = match command {
+        MirrorCommands::Add { name, url, required } => add_mirror(name, url, required, profile),
+        MirrorCommands::Remove { name } => remove_mirror(&name, profile),
+        MirrorCommands::Status => print_status(profile),
+    };
+    if let Err(e) = result {
+        write_log("error", "MIRROR", &e, Some(profile.to_string())).unwrap();
+        printer(&e, ui::MessageType::Error);
+    }
+}
+
+fn add_mirror(name: String, url: String, required: bool, profile: &str) -> Result<(), String> {
+    mirror::validate_name(&name)?;
+    let mut config = config::load_config()?;
+    if config.storage.mirrors.iter().any(|m| m.name == name) {
+        return Err(format!("Mirror {} already exists", name));
+    }
+    config.storage.mirrors.push(Mirror { name: name.clone(), url: url.clone(), required });
+    config::save_config(&config)?;
+    write_log("info", "MIRROR", &format!("Added mirror {} ({})", name, url), Some(profile.to_string()))?;
+    printer(format!("Mirror {} added; it gets the next push", name).as_str(), ui::MessageType::Success);
+    Ok(())
+}
+
+fn remove_mirror(name: &str, profile: &str) -> Result<(), String> {
+    let mut config = config::load_config()?;
+    let Some(position) = config.storage.mirrors.iter().position(|m| m.name == name) else {
+        return Err(format!("No mirror named {}", name));
+    };
+    let removed = config.storage.mirrors.remove(position);
+    config::save_config(&config)?;
+    mirror::forget(profile, &removed)?;
+    write_log("info", "MIRROR", &format!("Removed mirror {}", name), Some(profile.to_string()))?;
+    printer(format!("Mirror {} removed; its backups were left in place", name).as_str(), ui::MessageType::Success);
+    Ok(())
+}
+
+/// The remote and every mirror, as of their last push
+fn print_status(profile: &str) -> Result<(), String> {
+    if let Ok(url) = remote::remote_url() {
+        let state = match remote::queued(profile)? {
+            Some(queue) => format!("queued since {}: {}", queue.queued, queue.last_error),
+            None => match remote::unpushed(profile)? {
+                0 => "up to date".to_string(),
+                count => format!("{} not pushed", count),
+            },
+        };
+        ui::print_table(remote::REMOTE, &format!("{} (required) {}", url, state), None);
+    }
+
+    let statuses = mirror::status(profile)?;
+    if statuses.is_empty() {
+        printer("No mirrors. Add one with `confsync mirror add <NAME> <URL>`.", ui::MessageType::Default);
+        return Ok(());
+    }
+    for status in statuses {
+        let required = if status.mirror.required { " (required)" } else { "" };
+        let state = match (&status.state.last_error, status.behind) {
+            (Some(e), _) => format!("failed: {}", e),
+            (None, None) => "never pushed".to_string(),
+            (None, Some(0)) => "up to date".to_string(),
+            (None, Some(count)) => format!("{} not pushed", count),
+        };
+        let last = status.state.last_push.as_deref().map(|at| format!(", last push {}", at)).unwrap_or_default();
+        ui::print_table(&status.mirror.name, &format!("{}{} {}{}", status.mirror.url, required, state, last), None);
+    }
+    Ok(())
+}
@@ -1,3 +1,17 @@
 pub mod init;
+pub mod clone;
 pub mod add;
 pub mod delete;
+pub mod migrate;
+pub mod backup;
+pub mod restore;
+pub mod pull;
+pub mod git;
+pub mod hosts;
+pub mod status;
+pub mod mirror;
+pub mod encrypt;
+pub mod scan;
+pub mod audit;
+pub mod doctor;
+pub mod template;
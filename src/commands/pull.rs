@@ -0,0 +1,59 @@
+use crate::format;
+use crate::merge::Resolution;
+use crate::ops::write_log;
+use crate::remote;
+use crate::ui::{self, printer};
+
+pub fn handle_pull(mine: bool, theirs: bool, host: Option<String>, profile: &str) {
+    // aliases changed on both machines: use the flag, or ask for each one
+    let mut choose = |alias: &str| -> Result<Resolution, String> {
+        if mine {
+            return Ok(Resolution::Mine);
+        }
+        if theirs {
+            return Ok(Resolution::Theirs);
+        }
+        printer(format!("{} changed on this machine and on the remote", alias).as_str(), ui::MessageType::Warning);
+        loop {
+            let answer = ui::prompt("keep [m]ine, take [t]heirs or [e]dit a merge?")
+                .map_err(|e| format!("{}. Use --mine or --theirs.", e))?;
+            match answer.as_str() {
+                "m" | "mine" => return Ok(Resolution::Mine),
+                "t" | "theirs" => return Ok(Resolution::Theirs),
+                "e" | "edit" | "merge" => return Ok(Resolution::Merge),
+                _ => printer("Please answer m, t or e", ui::MessageType::Default),
+            }
+        }
+    };
+
+    match remote::pull(profile, host.as_deref(), &mut choose) {
+        Ok(report) if report.commits == 0 => printer("Already up to date", ui::MessageType::Success),
+        Ok(report) => {
+            printer(format!("Pulled {} backups", report.commits).as_str(), ui::MessageType::Success);
+            if let Some(merge) = report.merge {
+                for alias in &merge.taken {
+                    ui::print_table(alias, "remote", None);
+                }
+                for (alias, resolution) in &merge.conflicts {
+                    let how = match resolution {
+                        Resolution::Mine => "kept mine",
+                        Resolution::Theirs => "took theirs",
+                        Resolution::Merge => "merged",
+                    };
+                    ui::print_table(alias, how, None);
+                }
+            }
+            printer("use `confsync restore` to apply them", ui::MessageType::Default);
+        }
+        Err(e) => {
+            write_log("error", "PULL", &format!("Error pulling backups: {}", e), Some(profile.to_string())).unwrap();
+            printer(format!("Error pulling backups: {}", e).as_str(), ui::MessageType::Error);
+            return;
+        }
+    }
+    // the remote may have been written by a newer or older confsync
+    if let Err(e) = format::ensure_current(profile, true) {
+        write_log("warn", "PULL", &e, Some(profile.to_string())).unwrap();
+        printer(&e, ui::MessageType::Warning);
+    }
+}
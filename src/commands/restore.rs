@@ -0,0 +1,168 @@
+use std::path::PathBuf;
+
+use crate::backend::{self, Backend, LocalBackend};
+use crate::vault;
+use crate::commands::backup::print_summary;
+use crate::config::{self, is_tracked, Config};
+use crate::ops::{self, restore_file, write_log};
+use crate::remote;
+use crate::ui::{self, printer};
+
+pub fn handle_restore(target: Option<String>, dry_run: bool, overwrite: bool, host: Option<String>, quiet: bool, profile: &str) {
+    if dry_run {
+        return preview(target, host, profile);
+    }
+    if let Some(host) = host {
+        return restore_from_host(&host, target, overwrite, quiet, profile);
+    }
+    match target {
+        Some(target) => restore_alias(&target, overwrite, profile),
+        None => restore_all(overwrite, quiet, profile),
+    }
+}
+
+/// Show what restoring would do, without writing anything
+fn preview(target: Option<String>, host: Option<String>, profile: &str) {
+    let Some(files) = files_to_restore(target) else {
+        return;
+    };
+    let source = match &host {
+        Some(host) => host_snapshot(host, profile),
+        None => backend::open(profile).map_err(|e| printer(format!("Error opening backups: {}", e).as_str(), ui::MessageType::Error)).ok(),
+    };
+    let Some(source) = source else {
+        return;
+    };
+    let result = config::load_config().and_then(|config| print_plan(&files, &*source, &config));
+    match result {
+        Ok(()) => printer("Dry run: nothing was restored", ui::MessageType::Info),
+        Err(e) => printer(format!("Error preparing restore plan: {}", e).as_str(), ui::MessageType::Error),
+    }
+}
+
+/// Print what restoring `files` from `source` would do to each of them
+pub fn print_plan(files: &[(String, PathBuf)], source: &dyn Backend, config: &Config) -> Result<(), String> {
+    let plan = ops::restore_plan(files, source, config)?;
+    printer("Restore plan:", ui::MessageType::Info);
+    for ((alias, dest), action) in files.iter().zip(plan) {
+        ui::print_table(alias, &format!("{} ({})", dest.display(), action), None);
+    }
+    Ok(())
+}
+
+/// The tracked file of `target`, or every tracked file. Reports and returns None if there is none.
+fn files_to_restore(target: Option<String>) -> Option<Vec<(String, PathBuf)>> {
+    match target {
+        Some(target) => match config::get_path_from_alias(&target) {
+            Ok(path) => Some(vec![(target, path)]),
+            Err(_) => {
+                println!("{} not found", target);
+                write_log("warn", "RESTORE", &format!("{} not found.", target), None).unwrap();
+                None
+            }
+        },
+        None => match config::tracked_files() {
+            Ok(files) => Some(files),
+            Err(e) => {
+                write_log("error", "RESTORE", &format!("Error reading tracked files: {}", e), None).unwrap();
+                printer(format!("Error reading tracked files: {}", e).as_str(), ui::MessageType::Error);
+                None
+            }
+        },
+    }
+}
+
+/// The latest backup `host` pushed, unlocked. Reports and returns None if it is unavailable.
+fn host_snapshot(host: &str, profile: &str) -> Option<Box<dyn Backend>> {
+    let snapshot = match remote::checkout_host(profile, host) {
+        Ok(snapshot) => snapshot,
+        Err(e) => {
+            write_log("error", "RESTORE", &format!("Error fetching backups of {}: {}", host, e), Some(profile.to_string())).unwrap();
+            printer(format!("Error fetching backups of {}: {}", host, e).as_str(), ui::MessageType::Error);
+            return None;
+        }
+    };
+    match vault::wrap(Box::new(LocalBackend::new(snapshot))) {
+        Ok(snapshot) => Some(snapshot),
+        Err(e) => {
+            printer(format!("Error unlocking backups of {}: {}", host, e).as_str(), ui::MessageType::Error);
+            None
+        }
+    }
+}
+
+/// Restore tracked files from the latest backup another host pushed
+fn restore_from_host(host: &str, target: Option<String>, overwrite: bool, quiet: bool, profile: &str) {
+    let Some(files) = files_to_restore(target) else {
+        return;
+    };
+    let Some(snapshot) = host_snapshot(host, profile) else {
+        return;
+    };
+    let progress = ui::progress_bar(files.len(), "Restoring", quiet);
+    match ops::restore_files_from(&files, &*snapshot, profile, overwrite, &progress) {
+        Ok(results) => {
+            let aliases: Vec<&str> = files.iter().map(|(alias, _)| alias.as_str()).collect();
+            print_summary("RESTORE", &aliases, &results, quiet, profile);
+            write_log("info", "RESTORE", &format!("Restored from host {}", host), Some(profile.to_string())).unwrap();
+        }
+        Err(e) => {
+            write_log("error", "RESTORE", &format!("Error restoring files: {}", e), Some(profile.to_string())).unwrap();
+            printer(format!("Error restoring files: {}", e).as_str(), ui::MessageType::Error);
+        }
+    }
+}
+
+fn restore_alias(target: &str, overwrite: bool, profile: &str) {
+    // check if file is tracked
+    if !is_tracked(target) {
+        println!("{} not found", target);
+        write_log("warn", "RESTORE", &format!("{} not found.", target), None).unwrap();
+        return;
+    }
+    // get the path of the file from alias => dest
+    let path = match config::get_path_from_alias(target) {
+        Ok(path) => path,
+        Err(e) => {
+            write_log("error", "RESTORE", &format!("Error getting path from alias: {}", e), None).unwrap();
+            eprintln!("Error getting path from alias: {}", e);
+            return;
+        }
+    };
+    // copy the file from the repo to the dest
+    if let Err(e) = restore_file(path.clone(), target, profile, overwrite) {
+        write_log("error", "RESTORE", &format!("Error copying file to repo: {}", e), None).unwrap();
+        eprintln!("Error copying file to repo: {}", e);
+    } else {
+        write_log("info", "RESTORE", &format!("File {} copied from repo successfully", target), None).unwrap();
+        printer("Done", ui::MessageType::Default);
+    }
+}
+
+/// Restore every tracked file concurrently
+fn restore_all(overwrite: bool, quiet: bool, profile: &str) {
+    let files: Vec<(String, PathBuf)> = match config::tracked_files() {
+        Ok(files) => files,
+        Err(e) => {
+            write_log("error", "RESTORE", &format!("Error reading tracked files: {}", e), None).unwrap();
+            printer(format!("Error reading tracked files: {}", e).as_str(), ui::MessageType::Error);
+            return;
+        }
+    };
+    if files.is_empty() {
+        printer("No files are being tracked.", ui::MessageType::Default);
+        return;
+    }
+
+    let progress = ui::progress_bar(files.len(), "Restoring", quiet);
+    match ops::restore_files(&files, profile, overwrite, &progress) {
+        Ok(results) => {
+            let aliases: Vec<&str> = files.iter().map(|(alias, _)| alias.as_str()).collect();
+            print_summary("RESTORE", &aliases, &results, quiet, profile);
+        }
+        Err(e) => {
+            write_log("error", "RESTORE", &format!("Error restoring files: {}", e), Some(profile.to_string())).unwrap();
+            printer(format!("Error restoring files: {}", e).as_str(), ui::MessageType::Error);
+        }
+    }
+}
@@ -0,0 +1,76 @@
+use crate::config::{check_config_exists, load_config};
+use crate::ops::write_log;
+use crate::scan::{self, Finding};
+use crate::ui::{self, printer};
+use crate::{backend, store};
+
+/// Environment variables saved by `backup --env`; allowlisted as `env`
+const ENV_FILE: &str = "env_vars.txt";
+
+/// Check the stored backups (of one alias, or all) for likely secrets
+pub fn handle_scan(alias: Option<String>, profile: &str) {
+    if !check_config_exists() {
+        println!(" Please run `confsync init` to initialize.");
+        return;
+    }
+    if let Err(e) = scan_backups(alias.as_deref(), profile) {
+        write_log("error", "SCAN", &format!("Error scanning backups: {}", e), Some(profile.to_string())).unwrap();
+        printer(format!("Error scanning backups: {}", e).as_str(), ui::MessageType::Error);
+    }
+}
+
+fn scan_backups(only: Option<&str>, profile: &str) -> Result<(), String> {
+    let config = load_config()?.scan;
+    let backend = backend::open(profile)?;
+    let aliases = store::read_index(&*backend)?;
+    if only.is_some_and(|alias| aliases.get(alias).is_none()) {
+        return Err(format!("No backup found for {}", only.unwrap_or_default()));
+    }
+
+    let mut flagged = 0;
+    for (alias, record) in &aliases.aliases {
+        if only.is_some_and(|only| only != alias) {
+            continue;
+        }
+        if record.encrypted {
+            ui::print_table(alias, "encrypted", None);
+            continue;
+        }
+        let data = backend.get(&store::content_key(&record.key))?.unwrap_or_default();
+        if report(alias, config.unallowed(alias, scan::scan(&data))) {
+            flagged += 1;
+        }
+    }
+    if only.is_none() {
+        if let Some(data) = backend.get(ENV_FILE)? {
+            if report("env", config.unallowed("env", scan::scan(&data))) {
+                flagged += 1;
+            }
+        }
+    }
+
+    write_log("info", "SCAN", &format!("{} backups with likely secrets", flagged), Some(profile.to_string()))?;
+    if flagged == 0 {
+        printer("No likely secrets in the stored backups", ui::MessageType::Success);
+    } else {
+        printer(
+            format!("{} backups hold likely secrets in plaintext. Encrypt them with `confsync add --encrypt` or allow them in [scan.allow]", flagged).as_str(),
+            ui::MessageType::Warning,
+        );
+    }
+    Ok(())
+}
+
+/// Print the findings of one backup; true if there were any
+fn report(name: &str, findings: Vec<Finding>) -> bool {
+    if findings.is_empty() {
+        ui::print_table(name, "clean", None);
+        return false;
+    }
+    let noun = if findings.len() == 1 { "likely secret" } else { "likely secrets" };
+    ui::print_table(name, &format!("{} {}", findings.len(), noun), None);
+    for finding in &findings {
+        printer(format!("    line {}: {}", finding.line, finding.kind).as_str(), ui::MessageType::Default);
+    }
+    true
+}
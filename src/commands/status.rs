@@ -0,0 +1,115 @@
+use sha2::{Digest, Sha256};
+use std::fs;
+
+use crate::backend::{self, Backend};
+use crate::config::{self, check_config_exists};
+use crate::ops::write_log;
+use crate::ui::{self, printer};
+use crate::redact::Redaction;
+use crate::template::Template;
+use crate::{index, ops, remote, sensitive, store};
+
+pub fn handle_status(profile: &str) {
+    if !check_config_exists() {
+        println!(" Please run `confsync init` to initialize.");
+        return;
+    }
+    let backend = match backend::open(profile) {
+        Ok(backend) => backend,
+        Err(e) => {
+            printer(format!("Error opening backend: {}", e).as_str(), ui::MessageType::Error);
+            return;
+        }
+    };
+    if let Err(e) = print_files(&*backend, profile) {
+        write_log("error", "STATUS", &format!("Error reading status: {}", e), Some(profile.to_string())).unwrap();
+        printer(format!("Error reading status: {}", e).as_str(), ui::MessageType::Error);
+        return;
+    }
+    if let Ok(Some(last)) = backend.history().map(|lines| lines.last().cloned()) {
+        printer(format!("Last backup {}", last).as_str(), ui::MessageType::Default);
+    }
+
+    // remote backends have nothing to push
+    if backend.local_root().is_none() {
+        printer(format!("Backups are stored in {}", backend.name()).as_str(), ui::MessageType::Default);
+        return;
+    }
+    // counted against the last fetch, so this works offline
+    if remote::remote_url().is_err() {
+        return;
+    }
+    match remote::unpushed(profile) {
+        Ok(0) => printer("All snapshots pushed", ui::MessageType::Success),
+        Ok(count) => {
+            let noun = if count == 1 { "snapshot" } else { "snapshots" };
+            printer(format!("{} {} not yet pushed", count, noun).as_str(), ui::MessageType::Warning);
+        }
+        Err(e) => printer(format!("Error counting unpushed snapshots: {}", e).as_str(), ui::MessageType::Error),
+    }
+    if let Ok(Some(queue)) = remote::queued(profile) {
+        printer(
+            format!("Push queued since {} ({} attempts): {}", queue.queued, queue.attempts, queue.last_error).as_str(),
+            ui::MessageType::Default,
+        );
+    }
+}
+
+/// One line per tracked file: modified, unchanged, missing or not backed up yet,
+/// and whether it looks sensitive but is stored unencrypted.
+/// Templates are compared with their output for this host.
+fn print_files(backend: &dyn Backend, profile: &str) -> Result<(), String> {
+    let aliases = store::read_index(backend)?;
+    let cache = index::load(profile)?;
+    let config = config::load_config()?;
+
+    let files = config::tracked_files()?;
+    if files.is_empty() {
+        printer("No files are being tracked.", ui::MessageType::Default);
+    }
+    let unprotected = sensitive::unprotected(&files, &config)?;
+    for (alias, path) in files {
+        let state = match aliases.get(&alias) {
+            _ if !path.exists() => "missing",
+            None => "not backed up",
+            Some(record) => match Template::of(&config, &alias)? {
+                Some(template) => {
+                    let rendered = template.render(&ops::read_stored(record, backend)?)?;
+                    let current = fs::read(&path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+                    let current = match Redaction::of(&config, &alias) {
+                        Some(redaction) => redaction.redact(&current)?.0,
+                        None => current,
+                    };
+                    if current == rendered { "unchanged" } else { "differs from template" }
+                }
+                None => {
+                    let stored = match &record.hash {
+                        Some(hash) => hash.clone(),
+                        None => match backend.get(&store::content_key(&record.key))? {
+                            Some(data) => format!("{:x}", Sha256::digest(&data)),
+                            None => String::new(),
+                        },
+                    };
+                    let stat = index::stat(&path)?;
+                    let current = match (Redaction::of(&config, &alias), cache.cached(&path, &stat)) {
+                        (Some(redaction), _) => redaction.hash_file(&path)?,
+                        (None, Some(hash)) => hash.to_string(),
+                        (None, None) => index::hash_file(&path)?,
+                    };
+                    if current == stored { "unchanged" } else { "modified" }
+                }
+            },
+        };
+        match unprotected.iter().find(|(flagged, _)| *flagged == alias) {
+            Some((_, pattern)) => ui::print_table(&alias, &format!("{}, sensitive ({})", state, pattern), None),
+            None => ui::print_table(&alias, state, None),
+        }
+    }
+    if !unprotected.is_empty() {
+        printer(
+            "Sensitive files are stored unencrypted. Encrypt them with `confsync add <alias> <path> --encrypt`, or allow them in [sensitive] allow.",
+            ui::MessageType::Warning,
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,141 @@
+use directories::ProjectDirs;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::process::Command;
+
+use crate::cli::TemplateCommands;
+use crate::config::{self, check_config_exists, load_config, Config};
+use crate::ops::{self, write_log};
+use crate::redact::Redaction;
+use crate::template::{self, Template};
+use crate::ui::{self, printer};
+use crate::{backend, perms, repo, store};
+
+pub fn handle_template(command: TemplateCommands, profile: &str) {
+    if !check_config_exists() {
+        println!(" Please run `confsync init` to initialize.");
+        return;
+    }
+    let result = match command {
+        TemplateCommands::Edit { alias } => edit(&alias, profile),
+        TemplateCommands::Render { alias, host } => render(&alias, host.as_deref(), profile),
+        TemplateCommands::Vars { host } => print_vars(host.as_deref()),
+    };
+    if let Err(e) = result {
+        write_log("error", "TEMPLATE", &format!("Error with template: {}", e), Some(profile.to_string())).unwrap();
+        printer(format!("Error with template: {}", e).as_str(), ui::MessageType::Error);
+    }
+}
+
+/// Stored template of `alias`, decrypted; placeholders of redacted values stay in
+fn stored_template(alias: &str, config: &Config, profile: &str) -> Result<Vec<u8>, String> {
+    if !config.templates.aliases.contains(alias) {
+        return Err(format!("{} is not a template. Make it one with `confsync add {} <path> --template`", alias, alias));
+    }
+    let backend = backend::open(profile)?;
+    let aliases = store::read_index(&*backend)?;
+    let record = aliases.get(alias).ok_or_else(|| format!("No backup found for {}", alias))?;
+    ops::read_stored(record, &*backend)
+}
+
+/// Edit the template in $EDITOR; a changed one is stored, committed and rendered to the tracked file
+fn edit(alias: &str, profile: &str) -> Result<(), String> {
+    let config = load_config()?;
+    let original = stored_template(alias, &config, profile)?;
+    let template = Template::of(&config, alias)?.ok_or_else(|| format!("{} is not a template", alias))?;
+
+    // the tracked file is rendered again afterwards, which would drop changes made to it directly
+    let dest = config::get_path_from_alias(alias)?;
+    if let Ok(current) = fs::read(&dest) {
+        let rendered = template.render(&original)?;
+        let rendered = match Redaction::of(&config, alias) {
+            Some(redaction) => redaction.fill(rendered)?,
+            None => rendered,
+        };
+        if current != rendered && !confirm_overwrite(alias, &dest)? {
+            return Ok(());
+        }
+    }
+
+    // other users must not read it while it is open
+    let project_dirs = ProjectDirs::from("", "", "confsync")
+        .ok_or_else(|| "Failed to find cache directory".to_string())?;
+    let dir = project_dirs.cache_dir().join(profile).join("templates");
+    perms::create_dir(&dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+    let path = dir.join(alias);
+    perms::write(&path, &original).map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+
+    let editor = std::env::var("EDITOR").unwrap_or_else(|_| "nano".to_string());
+    let status = Command::new(editor)
+        .arg(&path)
+        .status()
+        .map_err(|e| format!("Failed to open template in editor: {}", e))?;
+    if !status.success() {
+        return Err("Editor exited with an error; template unchanged".into());
+    }
+    let edited = fs::read(&path).map_err(|e| format!("Failed to read edited template: {}", e))?;
+    if edited == original {
+        let _ = fs::remove_file(&path);
+        printer("Template unchanged", ui::MessageType::Info);
+        return Ok(());
+    }
+    // a template that does not render is kept for another try, not stored
+    template.render(&edited).map_err(|e| format!("{}. Your edit is kept in {}", e, path.display()))?;
+
+    ops::store_template(alias, &edited, profile)?;
+    repo::commit(profile, &format!("Template of {} updated", alias))?;
+    let _ = fs::remove_file(&path);
+    write_log("info", "TEMPLATE", &format!("Template of {} updated", alias), Some(profile.to_string()))?;
+    printer(format!("Template of {} updated", alias).as_str(), ui::MessageType::Success);
+
+    ops::restore_file(dest.clone(), alias, profile, true)?;
+    printer(format!("Rendered to {}", dest.display()).as_str(), ui::MessageType::Success);
+    Ok(())
+}
+
+/// Ask before editing a template whose tracked file no longer matches its output.
+/// Returns false if the user declined; without a terminal it refuses.
+fn confirm_overwrite(alias: &str, dest: &Path) -> Result<bool, String> {
+    printer(
+        format!("{} was changed since it was rendered from its template; those changes will be overwritten", dest.display()).as_str(),
+        ui::MessageType::Warning,
+    );
+    match ui::prompt("Edit the template anyway? [y/N]") {
+        Ok(answer) if answer.eq_ignore_ascii_case("y") || answer.eq_ignore_ascii_case("yes") => Ok(true),
+        Ok(_) => {
+            printer("Aborted.", ui::MessageType::Info);
+            Ok(false)
+        }
+        Err(e) => Err(format!(
+            "{}. Move the changes into the template, or discard them with `confsync restore {}`",
+            e, alias
+        )),
+    }
+}
+
+/// Print the output of the template, for this machine or another host
+fn render(alias: &str, host: Option<&str>, profile: &str) -> Result<(), String> {
+    let config = load_config()?;
+    let data = stored_template(alias, &config, profile)?;
+    let template = match host {
+        Some(host) => Template::for_host(&config, alias, host),
+        None => Template::of(&config, alias)?.ok_or_else(|| format!("{} is not a template", alias))?,
+    };
+    std::io::stdout()
+        .write_all(&template.render(&data)?)
+        .map_err(|e| format!("Failed to write output: {}", e))
+}
+
+/// Variables and their values, custom ones over the built-in ones
+fn print_vars(host: Option<&str>) -> Result<(), String> {
+    let config = load_config()?;
+    let host = match host {
+        Some(host) => host.to_string(),
+        None => config::host_name_of(&config)?,
+    };
+    for (name, value) in template::vars(&config, &host) {
+        ui::print_table(&name, &value, None);
+    }
+    Ok(())
+}
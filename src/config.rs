@@ -1,15 +1,43 @@
 use directories::ProjectDirs;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::fs;
 use std::path::PathBuf;
 
+use crate::backend::BackendConfig;
+use crate::redact::{Rules, SecretsConfig};
+use crate::scan::ScanConfig;
+use crate::sensitive::SensitiveConfig;
+use crate::signing::SigningConfig;
+use crate::template::TemplateConfig;
 use crate::ui;
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Config {
     pub storage: Storage,
     pub tracking: Tracking,
+    /// storage backend per profile; profiles without an entry use their local repository
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub backends: BTreeMap<String, BackendConfig>,
+    #[serde(default, skip_serializing_if = "Encryption::is_empty")]
+    pub encryption: Encryption,
+    /// secret scanning of files before they are stored
+    #[serde(default, skip_serializing_if = "ScanConfig::is_default")]
+    pub scan: ScanConfig,
+    /// values stored as `{{secret:<name>}}` placeholders, per alias
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub redact: BTreeMap<String, Rules>,
+    #[serde(default, skip_serializing_if = "SecretsConfig::is_empty")]
+    pub secrets: SecretsConfig,
+    /// snapshot signing and the keys trusted on restore
+    #[serde(default, skip_serializing_if = "SigningConfig::is_default")]
+    pub signing: SigningConfig,
+    /// paths `add` refuses to store unencrypted
+    #[serde(default, skip_serializing_if = "SensitiveConfig::is_default")]
+    pub sensitive: SensitiveConfig,
+    /// files rendered per host from a stored template, and their variables
+    #[serde(default, skip_serializing_if = "TemplateConfig::is_default")]
+    pub templates: TemplateConfig,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -22,6 +50,54 @@ pub struct Storage {
 
     /// optional profile name
     pub profile: Option<String>,
+
+    /// name this machine's backups are recorded under [default: hostname]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host: Option<String>,
+
+    /// extra destinations every push is replicated to
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub mirrors: Vec<Mirror>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Mirror {
+    pub name: String,
+    /// git URL or local directory (USB drive, NAS mount)
+    pub url: String,
+    /// a backup is only complete once this mirror has it
+    #[serde(default)]
+    pub required: bool,
+}
+
+/// Aliases stored encrypted with age, and the keys involved
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Encryption {
+    /// identity file used to decrypt [default: identity.txt next to this config]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub identity: Option<PathBuf>,
+    /// public keys every encrypted file is encrypted to
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub recipients: Vec<String>,
+    /// aliases whose content is encrypted at rest
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub aliases: BTreeSet<String>,
+    /// environment variable holding the repository passphrase [default: CONFSYNC_PASSPHRASE]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub passphrase_env: Option<String>,
+    /// shell command printing the repository passphrase, e.g. `pass show confsync`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub passphrase_command: Option<String>,
+}
+
+impl Encryption {
+    pub fn is_empty(&self) -> bool {
+        self.identity.is_none()
+            && self.recipients.is_empty()
+            && self.aliases.is_empty()
+            && self.passphrase_env.is_none()
+            && self.passphrase_command.is_none()
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -37,6 +113,8 @@ impl Default for Config {
                 local: true,
                 repo_url: String::new(),
                 profile: Some(String::from("default")),
+                host: None,
+                mirrors: Vec::new(),
             },
             tracking: Tracking {
                 file_map: HashMap::from_iter([(
@@ -44,6 +122,14 @@ impl Default for Config {
                     default_config_path().unwrap_or_else(|| PathBuf::from("config.toml")),
                 )]),
             },
+            backends: BTreeMap::new(),
+            encryption: Encryption::default(),
+            scan: ScanConfig::default(),
+            redact: BTreeMap::new(),
+            secrets: SecretsConfig::default(),
+            signing: SigningConfig::default(),
+            sensitive: SensitiveConfig::default(),
+            templates: TemplateConfig::default(),
         }
     }
 }
@@ -58,9 +144,36 @@ pub fn default_config_path() -> Option<PathBuf> {
     }
 }
 
+/// Name this machine's backups are recorded under: `storage.host`, or the hostname.
+/// Characters git does not allow in branch names are replaced with `-`.
+pub fn host_name() -> Result<String, String> {
+    host_name_of(&load_config()?)
+}
+
+/// `host_name` of a config that is not saved yet, e.g. one recovered by clone
+pub fn host_name_of(config: &Config) -> Result<String, String> {
+    let host = match &config.storage.host {
+        Some(host) if !host.trim().is_empty() => host.clone(),
+        _ => hostname::get()
+            .map_err(|e| format!("Failed to get hostname: {}", e))?
+            .to_string_lossy()
+            .to_string(),
+    };
+    let host: String = host
+        .trim()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.' { c } else { '-' })
+        .collect();
+    let host = host.replace("..", "-").trim_matches('.').to_string();
+    if host.is_empty() {
+        return Err("Host name is empty. Set `storage.host` in the config.".into());
+    }
+    Ok(host)
+}
+
 /// Check if the config file exists : returns true if it does
 pub fn check_config_exists() -> bool {
-    default_config_path().map_or(false, |path| path.is_file())
+    default_config_path().is_some_and(|path| path.is_file())
 }
 
 /// Load the config file if it exists, or return a default
@@ -198,6 +311,33 @@ pub fn add_tracking_file(path: PathBuf, name: String) -> Result<(), String> {
 }
 
 
+/// Store `alias` encrypted from its next backup on
+pub fn set_encrypted(alias: &str) -> Result<(), String> {
+    let mut config = load_config()?;
+    if config.encryption.aliases.insert(alias.to_string()) {
+        save_config(&config)?;
+    }
+    Ok(())
+}
+
+/// Store `alias` as a template from now on
+pub fn set_template(alias: &str) -> Result<(), String> {
+    let mut config = load_config()?;
+    if config.templates.aliases.insert(alias.to_string()) {
+        save_config(&config)?;
+    }
+    Ok(())
+}
+
+/// Store `alias` as it is although it looks sensitive
+pub fn allow_sensitive(alias: &str) -> Result<(), String> {
+    let mut config = load_config()?;
+    if config.sensitive.allow.insert(alias.to_string()) {
+        save_config(&config)?;
+    }
+    Ok(())
+}
+
 /// Remove a file from the tracking list
 pub fn _remove_tracking_file(name: String) -> Result<(), String> {
     let mut config = load_config()?;
@@ -205,6 +345,7 @@ pub fn _remove_tracking_file(name: String) -> Result<(), String> {
     if config.tracking.file_map.remove(&name).is_none() {
         return Err("File not found in tracking list".into());
     }
+    config.encryption.aliases.remove(&name);
 
     save_config(&config)?;
 
@@ -221,11 +362,19 @@ pub fn list_tracked_files() -> Result<(), String> {
     }
 
     for (name, path) in &config.tracking.file_map {
-        ui::print_table (&name, &path.display().to_string(), None);
+        ui::print_table (name, &path.display().to_string(), None);
     }
 
     Ok(())
 }
+/// All tracked files as (alias, path), sorted by alias
+pub fn tracked_files() -> Result<Vec<(String, PathBuf)>, String> {
+    let config = load_config()?;
+    let mut files: Vec<(String, PathBuf)> = config.tracking.file_map.into_iter().collect();
+    files.sort();
+    Ok(files)
+}
+
 /// Check if a file is being tracked. 
 pub fn is_tracked(name: &str) -> bool {
     if let Ok(config) = load_config() {
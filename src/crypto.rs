@@ -0,0 +1,147 @@
+use age::secrecy::ExposeSecret;
+use directories::ProjectDirs;
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use crate::config::{load_config, save_config};
+use crate::perms;
+
+/// Default identity file, next to config.toml
+const IDENTITY_FILE: &str = "identity.txt";
+
+/// Identity file used to decrypt: `encryption.identity`, or `identity.txt` in the config directory
+pub fn identity_path() -> Result<PathBuf, String> {
+    if let Some(path) = load_config()?.encryption.identity {
+        return Ok(path);
+    }
+    let project_dirs = ProjectDirs::from("", "", "confsync")
+        .ok_or_else(|| "Failed to find config directory".to_string())?;
+    Ok(project_dirs.config_dir().join(IDENTITY_FILE))
+}
+
+/// Create an identity if there is none yet and add its public key to the recipients.
+/// Returns the public key of a newly created identity.
+pub fn ensure_identity() -> Result<Option<String>, String> {
+    let path = identity_path()?;
+    if path.exists() {
+        return Ok(None);
+    }
+    let public_key = add_identity(&path)?;
+    let mut config = load_config()?;
+    if !config.encryption.recipients.contains(&public_key) {
+        config.encryption.recipients.push(public_key.clone());
+    }
+    save_config(&config)?;
+    Ok(Some(public_key))
+}
+
+/// Generate an identity and append it to the identity file, keeping older ones
+/// so content encrypted to them stays readable. Returns its public key.
+pub fn add_identity(path: &Path) -> Result<String, String> {
+    let identity = age::x25519::Identity::generate();
+    let public_key = identity.to_public().to_string();
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
+    }
+    // readable by the owner only, from the moment it exists
+    let mut file = perms::append(path)
+        .map_err(|e| format!("Failed to open identity {}: {}", path.display(), e))?;
+    let created = chrono::Local::now().format("%Y-%m-%dT%H:%M:%S%:z");
+    writeln!(file, "# created: {}\n# public key: {}\n{}", created, public_key, identity.to_string().expose_secret())
+        .map_err(|e| format!("Failed to write identity: {}", e))?;
+    Ok(public_key)
+}
+
+/// Identities in the identity file, newest last
+fn read_identities() -> Result<Vec<age::x25519::Identity>, String> {
+    let path = identity_path()?;
+    if !path.exists() {
+        return Err(format!(
+            "Backup is encrypted and there is no identity at {}. Copy the identity from a machine that has it.",
+            path.display()
+        ));
+    }
+    let contents = fs::read_to_string(&path).map_err(|e| format!("Failed to read identity {}: {}", path.display(), e))?;
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| line.starts_with("AGE-SECRET-KEY-"))
+        .map(|line| line.parse().map_err(|e| format!("Invalid identity in {}: {}", path.display(), e)))
+        .collect()
+}
+
+/// Public keys of the local identities, newest last
+pub fn identity_public_keys() -> Result<Vec<String>, String> {
+    Ok(read_identities()?.iter().map(|identity| identity.to_public().to_string()).collect())
+}
+
+/// Public keys in `input`: an `age1...` key, or a file holding them
+/// (a recipients file, or an identity file with `# public key:` lines)
+pub fn parse_recipients(input: &str) -> Result<Vec<String>, String> {
+    let contents = match input.starts_with("age1") {
+        true => input.to_string(),
+        false => fs::read_to_string(input).map_err(|e| format!("Failed to read {}: {}", input, e))?,
+    };
+    let keys = contents
+        .split_whitespace()
+        .filter(|word| word.starts_with("age1"))
+        .map(|key| {
+            key.parse::<age::x25519::Recipient>()
+                .map(|recipient| recipient.to_string())
+                .map_err(|e| format!("Invalid public key {}: {}", key, e))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    if keys.is_empty() {
+        return Err(format!("No age public key in {}", input));
+    }
+    Ok(keys)
+}
+
+/// Encrypt to every configured recipient
+pub fn encrypt(data: &[u8]) -> Result<Vec<u8>, String> {
+    let keys = load_config()?.encryption.recipients;
+    if keys.is_empty() {
+        return Err("No encryption recipients configured. Run `confsync encrypt init` to create a key.".into());
+    }
+    encrypt_to(data, &keys)
+}
+
+/// Encrypt to the given public keys
+pub fn encrypt_to(data: &[u8], keys: &[String]) -> Result<Vec<u8>, String> {
+    let recipients = keys
+        .iter()
+        .map(|key| key.parse::<age::x25519::Recipient>().map_err(|e| format!("Invalid recipient {}: {}", key, e)))
+        .collect::<Result<Vec<_>, _>>()?;
+    let encryptor = age::Encryptor::with_recipients(recipients.iter().map(|r| r as &dyn age::Recipient))
+        .map_err(|e| format!("Failed to encrypt: {}", e))?;
+
+    let mut encrypted = Vec::new();
+    let mut writer = encryptor.wrap_output(&mut encrypted).map_err(|e| format!("Failed to encrypt: {}", e))?;
+    writer.write_all(data).map_err(|e| format!("Failed to encrypt: {}", e))?;
+    writer.finish().map_err(|e| format!("Failed to encrypt: {}", e))?;
+    Ok(encrypted)
+}
+
+/// True for content written by `encrypt`
+pub fn is_encrypted(data: &[u8]) -> bool {
+    data.starts_with(b"age-encryption.org/v1\n")
+}
+
+/// Decrypt with the local identities
+pub fn decrypt(data: &[u8]) -> Result<Vec<u8>, String> {
+    let identities = read_identities()?;
+    let decryptor = age::Decryptor::new_buffered(data).map_err(|e| format!("Failed to decrypt: {}", e))?;
+    let mut reader = decryptor
+        .decrypt(identities.iter().map(|identity| identity as &dyn age::Identity))
+        .map_err(|e| match e {
+            age::DecryptError::NoMatchingKeys => {
+                let path = identity_path().map(|p| p.display().to_string()).unwrap_or_default();
+                format!("Backup is not encrypted to the identity at {}", path)
+            }
+            e => format!("Failed to decrypt: {}", e),
+        })?;
+    let mut decrypted = Vec::new();
+    reader.read_to_end(&mut decrypted).map_err(|e| format!("Failed to decrypt: {}", e))?;
+    Ok(decrypted)
+}
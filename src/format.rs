@@ -0,0 +1,304 @@
+use directories::ProjectDirs;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::backend::{self, Backend};
+use crate::ops::write_log;
+use crate::{git, perms, store};
+
+/// Layout version written by this build
+pub const CURRENT_VERSION: u32 = 3;
+
+/// Metadata file at the root of the profile repository
+pub const FORMAT_FILE: &str = "format.toml";
+
+/// Files that may exist in a repository before anything was stored
+const HOUSEKEEPING: &[&str] = &["log.txt", ".lock", ".git", ".gitignore"];
+
+/// Upgrades the repository at the given path by one version
+type Migration = fn(&Path) -> Result<(), String>;
+
+/// One step per version: MIGRATIONS[n] upgrades version n to n + 1
+const MIGRATIONS: &[Migration] = &[migrate_v0, migrate_v1, migrate_v2];
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RepoFormat {
+    /// on-disk layout version
+    pub version: u32,
+    /// confsync version that last wrote the layout
+    pub written_by: String,
+}
+
+fn repo_path(profile: &str) -> Result<PathBuf, String> {
+    let project_dirs = ProjectDirs::from("", "", "confsync")
+        .ok_or_else(|| "Failed to find config directory".to_string())?;
+    Ok(project_dirs.data_dir().join(profile))
+}
+
+/// Read the layout version. Repositories without a format file predate versioning (0).
+pub fn read_version(repo_path: &Path) -> Result<u32, String> {
+    let path = repo_path.join(FORMAT_FILE);
+    if !path.exists() {
+        return Ok(0);
+    }
+    let contents = fs::read_to_string(&path)
+        .map_err(|e| format!("Failed to read format file: {}", e))?;
+    parse_version(&contents)
+}
+
+/// Format version of a repository directory, refused if it is newer than this build
+pub fn readable_version(repo_path: &Path) -> Result<u32, String> {
+    let version = read_version(repo_path)?;
+    if version > CURRENT_VERSION {
+        return Err(format!(
+            "Repository format v{} is newer than supported v{}. Please upgrade confsync.",
+            version, CURRENT_VERSION
+        ));
+    }
+    Ok(version)
+}
+
+pub fn parse_version(contents: &str) -> Result<u32, String> {
+    let format: RepoFormat = toml::from_str(contents)
+        .map_err(|e| format!("Failed to parse format file: {}", e))?;
+    Ok(format.version)
+}
+
+fn format_contents(version: u32) -> Result<String, String> {
+    let format = RepoFormat {
+        version,
+        written_by: env!("CARGO_PKG_VERSION").to_string(),
+    };
+    toml::to_string_pretty(&format).map_err(|e| format!("Failed to serialize format file: {}", e))
+}
+
+fn write_version(repo_path: &Path, version: u32) -> Result<(), String> {
+    perms::write(repo_path.join(FORMAT_FILE), format_contents(version)?)
+        .map_err(|e| format!("Failed to write format file: {}", e))
+}
+
+/// Stamp a freshly created repository with the current version.
+/// Existing repositories are left for `migrate` so they are never mislabeled.
+pub fn stamp_new_repo(repo_path: &Path) -> Result<(), String> {
+    if repo_path.join(FORMAT_FILE).exists() {
+        return Ok(());
+    }
+    let has_data = fs::read_dir(repo_path)
+        .map_err(|e| format!("Failed to read repository: {}", e))?
+        .filter_map(|entry| entry.ok())
+        .any(|entry| !HOUSEKEEPING.iter().any(|name| entry.file_name() == *name));
+    if has_data {
+        return Ok(());
+    }
+    write_version(repo_path, CURRENT_VERSION)
+}
+
+/// Make sure the repository layout is one this build understands.
+/// Newer layouts are refused; older ones are migrated when `upgrade` is set.
+/// Returns true if a migration took place.
+pub fn ensure_current(profile: &str, upgrade: bool) -> Result<bool, String> {
+    if !backend::is_local(profile)? {
+        return check_backend(&*backend::open(profile)?, upgrade);
+    }
+    let repo_path = repo_path(profile)?;
+    if !repo_path.exists() {
+        return Ok(false);
+    }
+    let version = readable_version(&repo_path)?;
+    if version < CURRENT_VERSION {
+        if !upgrade {
+            return Err(format!(
+                "Repository format v{} is outdated. Run `confsync migrate` to upgrade.",
+                version
+            ));
+        }
+        migrate(profile)?;
+        return Ok(true);
+    }
+    Ok(false)
+}
+
+/// Backends other than the local one were always written in the current layout.
+/// Empty ones are stamped by writers; anything else is refused.
+fn check_backend(backend: &dyn Backend, upgrade: bool) -> Result<bool, String> {
+    match backend.get(FORMAT_FILE)? {
+        Some(contents) => {
+            let version = parse_version(&String::from_utf8_lossy(&contents))?;
+            if version != CURRENT_VERSION {
+                return Err(format!(
+                    "Backups in {} use format v{}, this build only reads v{}",
+                    backend.name(),
+                    version,
+                    CURRENT_VERSION
+                ));
+            }
+        }
+        None if upgrade => backend.put(FORMAT_FILE, format_contents(CURRENT_VERSION)?.as_bytes())?,
+        None => {}
+    }
+    Ok(false)
+}
+
+/// Upgrade the repository to the current layout, keeping a copy of the old one.
+/// Returns the version migrated from and the backup location, if any.
+pub fn migrate(profile: &str) -> Result<(u32, Option<PathBuf>), String> {
+    backend::require_local(profile, "Migrating")?;
+    let repo_path = repo_path(profile)?;
+    if !repo_path.exists() {
+        return Err("Repository does not exist".into());
+    }
+    let from = read_version(&repo_path)?;
+    if from > CURRENT_VERSION {
+        return Err(format!(
+            "Repository format v{} is newer than supported v{}",
+            from, CURRENT_VERSION
+        ));
+    }
+    if from == CURRENT_VERSION {
+        return Ok((from, None));
+    }
+
+    let backup_path = backup_layout(&repo_path, from)?;
+    write_log(
+        "info",
+        "MIGRATE",
+        &format!("Old layout v{} saved to {}", from, backup_path.display()),
+        Some(profile.to_string()),
+    )?;
+
+    for version in from..CURRENT_VERSION {
+        MIGRATIONS[version as usize](&repo_path)?;
+        write_version(&repo_path, version + 1)?;
+        write_log(
+            "info",
+            "MIGRATE",
+            &format!("Migrated repository v{} -> v{}", version, version + 1),
+            Some(profile.to_string()),
+        )?;
+    }
+    Ok((from, Some(backup_path)))
+}
+
+/// Copy the repository next to itself as `<profile>.v<N>.bak`
+fn backup_layout(repo_path: &Path, version: u32) -> Result<PathBuf, String> {
+    let name = repo_path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .ok_or_else(|| "Failed to get repository name".to_string())?;
+    let mut backup_path = repo_path.with_file_name(format!("{}.v{}.bak", name, version));
+    if backup_path.exists() {
+        let timestamp = chrono::Local::now().format("%Y%m%d%H%M%S");
+        backup_path = repo_path.with_file_name(format!("{}.v{}.{}.bak", name, version, timestamp));
+    }
+    copy_dir(repo_path, &backup_path)?;
+    Ok(backup_path)
+}
+
+fn copy_dir(src: &Path, dest: &Path) -> Result<(), String> {
+    perms::create_dir(dest).map_err(|e| format!("Failed to create directory: {}", e))?;
+    for entry in fs::read_dir(src).map_err(|e| format!("Failed to read directory: {}", e))? {
+        let entry = entry.map_err(|e| format!("Failed to read directory entry: {}", e))?;
+        if entry.file_name() == ".lock" {
+            continue;
+        }
+        let target = dest.join(entry.file_name());
+        if entry.path().is_dir() {
+            copy_dir(&entry.path(), &target)?;
+        } else {
+            fs::copy(entry.path(), &target)
+                .map_err(|e| format!("Failed to copy {}: {}", entry.path().display(), e))?;
+        }
+    }
+    Ok(())
+}
+
+/// v0 -> v1: the layout is unchanged, v1 only adds the format file
+fn migrate_v0(_repo_path: &Path) -> Result<(), String> {
+    Ok(())
+}
+
+/// v1 -> v2: `<alias>/<file>` and `<alias>/<file>.cmt` move to `store/<key>/`,
+/// with a record per alias in aliases.toml
+fn migrate_v1(repo_path: &Path) -> Result<(), String> {
+    let mut index = store::load_index(repo_path)?;
+
+    for entry in fs::read_dir(repo_path).map_err(|e| format!("Failed to read repository: {}", e))? {
+        let entry = entry.map_err(|e| format!("Failed to read directory entry: {}", e))?;
+        let alias_dir = entry.path();
+        if !alias_dir.is_dir() || entry.file_name() == store::STORE_DIR {
+            continue;
+        }
+        let alias = entry
+            .file_name()
+            .to_str()
+            .ok_or_else(|| format!("Invalid alias directory {}", alias_dir.display()))?
+            .to_string();
+
+        // every stored name of the alias; the newest one is the current content
+        let mut files = Vec::new();
+        let mut history = Vec::new();
+        for file in fs::read_dir(&alias_dir).map_err(|e| format!("Failed to read {}: {}", alias, e))? {
+            let path = file.map_err(|e| format!("Failed to read directory entry: {}", e))?.path();
+            if path.extension().is_some_and(|ext| ext == "cmt") {
+                let contents = fs::read_to_string(&path)
+                    .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+                history.extend(contents.lines().map(|line| line.to_string()));
+            } else if path.is_file() {
+                let modified = fs::metadata(&path)
+                    .and_then(|meta| meta.modified())
+                    .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+                files.push((modified, path));
+            }
+        }
+        files.sort();
+        let Some((_, content)) = files.pop() else {
+            continue;
+        };
+        history.sort();
+
+        // history lines are `[timestamp] source path`
+        let entry_of = |line: &String| {
+            line.strip_prefix('[')
+                .and_then(|rest| rest.split_once("] "))
+                .map(|(ts, path)| (ts.to_string(), PathBuf::from(path)))
+        };
+        let source = history
+            .last()
+            .and_then(entry_of)
+            .map(|(_, path)| path)
+            .unwrap_or_else(|| PathBuf::from(content.file_name().unwrap_or_default()));
+
+        let key = index.record(&alias, &source)?;
+        if let Some(record) = index.aliases.get_mut(&alias) {
+            if let Some((first, _)) = history.first().and_then(entry_of) {
+                record.created = first;
+            }
+            if let Some((last, _)) = history.last().and_then(entry_of) {
+                record.updated = last;
+            }
+        }
+
+        perms::create_dir(store::key_dir(repo_path, &key))
+            .map_err(|e| format!("Failed to create directory: {}", e))?;
+        fs::rename(&content, store::content_path(repo_path, &key))
+            .map_err(|e| format!("Failed to move {}: {}", content.display(), e))?;
+        if !history.is_empty() {
+            perms::write(store::history_path(repo_path, &key), history.join("\n") + "\n")
+                .map_err(|e| format!("Failed to write history of {}: {}", alias, e))?;
+        }
+        fs::remove_dir_all(&alias_dir)
+            .map_err(|e| format!("Failed to remove {}: {}", alias_dir.display(), e))?;
+    }
+
+    store::save_index(repo_path, &index)
+}
+
+/// v2 -> v3: the repository becomes a git repository holding the existing content
+fn migrate_v2(repo_path: &Path) -> Result<(), String> {
+    git::init(repo_path)?;
+    // the format file is written after this step; include it in the first commit
+    write_version(repo_path, 3)?;
+    git::commit_all(repo_path, "Import existing backups")?;
+    Ok(())
+}
@@ -0,0 +1,237 @@
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use crate::perms;
+
+/// Branch all backups are committed to
+pub const BRANCH: &str = "main";
+
+/// Local-only files that never belong in the backup history
+const GITIGNORE: &str = "# generated by confsync\n.lock\nlog.txt\n";
+
+/// Run git inside the repository and return its stdout
+pub fn git(repo_path: &Path, args: &[&str]) -> Result<String, String> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(repo_path)
+        .args(args)
+        .output()
+        .map_err(|e| format!("Failed to run git: {}", e))?;
+    if !output.status.success() {
+        return Err(format!(
+            "git {} failed: {}",
+            args.first().unwrap_or(&""),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim_end().to_string())
+}
+
+pub fn is_repo(repo_path: &Path) -> bool {
+    repo_path.join(".git").exists()
+}
+
+/// Turn the directory into a git repository on the `main` branch
+pub fn init(repo_path: &Path) -> Result<(), String> {
+    if !is_repo(repo_path) {
+        git(repo_path, &["init", "--quiet"])?;
+        // `init -b` needs git 2.28; pointing HEAD works everywhere
+        git(repo_path, &["symbolic-ref", "HEAD", &format!("refs/heads/{}", BRANCH)])?;
+    }
+    let gitignore = repo_path.join(".gitignore");
+    if !gitignore.exists() {
+        perms::write(&gitignore, GITIGNORE)
+            .map_err(|e| format!("Failed to write .gitignore: {}", e))?;
+    }
+    // `git init` leaves .git open to others
+    perms::restrict(repo_path)
+}
+
+/// Stage everything and commit it. Returns the new commit hash, or None if nothing changed.
+pub fn commit_all(repo_path: &Path, message: &str) -> Result<Option<String>, String> {
+    git(repo_path, &["add", "--all"])?;
+    if git(repo_path, &["status", "--porcelain"])?.is_empty() {
+        return Ok(None);
+    }
+
+    commit(repo_path, message).map(Some)
+}
+
+/// Commit what is staged (or an in-progress merge). Returns the new commit hash.
+pub fn commit(repo_path: &Path, message: &str) -> Result<String, String> {
+    git_as_user(repo_path, &["commit", "--quiet", "--no-edit", "-m", message])?;
+    git(repo_path, &["rev-parse", "--short", "HEAD"])
+}
+
+/// Run a git command that records an identity (commit, merge).
+/// Falls back to a confsync identity on machines without git config.
+pub fn git_as_user(repo_path: &Path, args: &[&str]) -> Result<String, String> {
+    let mut full = Vec::new();
+    if git(repo_path, &["config", "user.email"]).is_err() {
+        full.extend(["-c", "user.name=confsync", "-c", "user.email=confsync@localhost"]);
+    }
+    full.extend_from_slice(args);
+    git(repo_path, &full)
+}
+
+/// Point `name` at `url`, adding the remote if it does not exist yet
+pub fn set_remote(repo_path: &Path, name: &str, url: &str) -> Result<(), String> {
+    match git(repo_path, &["remote", "get-url", name]) {
+        Ok(current) if current == url => Ok(()),
+        Ok(_) => git(repo_path, &["remote", "set-url", name, url]).map(|_| ()),
+        Err(_) => git(repo_path, &["remote", "add", name, url]).map(|_| ()),
+    }
+}
+
+/// Resolve a ref to a commit hash, or None if it does not exist
+pub fn rev_parse(repo_path: &Path, rev: &str) -> Option<String> {
+    git(repo_path, &["rev-parse", "--verify", "--quiet", rev]).ok()
+}
+
+/// Contents of `path` at `rev` as raw bytes, or None if it does not exist there
+pub fn show(repo_path: &Path, rev: &str, path: &str) -> Result<Option<Vec<u8>>, String> {
+    if rev_parse(repo_path, &format!("{}:{}", rev, path)).is_none() {
+        return Ok(None);
+    }
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(repo_path)
+        .args(["show", &format!("{}:{}", rev, path)])
+        .output()
+        .map_err(|e| format!("Failed to run git: {}", e))?;
+    if !output.status.success() {
+        return Err(format!(
+            "git show failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(Some(output.stdout))
+}
+
+/// True if `ancestor` is reachable from `rev`
+pub fn is_ancestor(repo_path: &Path, ancestor: &str, rev: &str) -> bool {
+    git(repo_path, &["merge-base", "--is-ancestor", ancestor, rev]).is_ok()
+}
+
+/// Run git with extra environment variables and `input` on stdin; returns raw stdout
+fn run(repo_path: &Path, args: &[&str], env: &[(&str, &str)], input: &[u8]) -> Result<Vec<u8>, String> {
+    let mut child = Command::new("git")
+        .arg("-C")
+        .arg(repo_path)
+        .args(args)
+        .envs(env.iter().copied())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("Failed to run git: {}", e))?;
+    let mut stdin = child.stdin.take().ok_or_else(|| "Failed to write to git".to_string())?;
+    // written from another thread so a large output cannot block the input
+    let input = input.to_vec();
+    let writer = std::thread::spawn(move || stdin.write_all(&input));
+    let output = child.wait_with_output().map_err(|e| format!("Failed to run git: {}", e))?;
+    writer
+        .join()
+        .map_err(|_| "Failed to write to git".to_string())?
+        .map_err(|e| format!("Failed to write to git: {}", e))?;
+    if !output.status.success() {
+        return Err(format!(
+            "git {} failed: {}",
+            args.first().unwrap_or(&""),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(output.stdout)
+}
+
+/// Raw contents of the blob `hash`
+pub fn blob(repo_path: &Path, hash: &str) -> Result<Vec<u8>, String> {
+    run(repo_path, &["cat-file", "blob", hash], &[], &[])
+}
+
+/// Store `data` as a blob and return its hash
+pub fn write_blob(repo_path: &Path, data: &[u8]) -> Result<String, String> {
+    let hash = run(repo_path, &["hash-object", "-w", "--stdin"], &[], data)?;
+    Ok(String::from_utf8_lossy(&hash).trim().to_string())
+}
+
+/// Tree of `commit` with the blobs at some paths replaced, written through a scratch index
+pub fn replace_blobs(repo_path: &Path, commit: &str, blobs: &[(String, String)]) -> Result<String, String> {
+    let index = repo_path.join(".git").join("confsync-rewrite.index");
+    let index_path = index.to_string_lossy().to_string();
+    let env = [("GIT_INDEX_FILE", index_path.as_str())];
+    let result = (|| {
+        run(repo_path, &["read-tree", commit], &env, &[])?;
+        for (path, hash) in blobs {
+            run(repo_path, &["update-index", "--cacheinfo", &format!("100644,{},{}", hash, path)], &env, &[])?;
+        }
+        run(repo_path, &["write-tree"], &env, &[])
+    })();
+    let _ = std::fs::remove_file(&index);
+    Ok(String::from_utf8_lossy(&result?).trim().to_string())
+}
+
+/// A copy of `commit` with another tree and parents; message, author and dates are kept
+pub fn copy_commit(repo_path: &Path, commit: &str, tree: &str, parents: &[String]) -> Result<String, String> {
+    let fields = git(repo_path, &["log", "-1", "--date=raw", "--format=%an%x00%ae%x00%ad%x00%cn%x00%ce%x00%cd", commit])?;
+    let fields: Vec<&str> = fields.split('\0').collect();
+    let [author, author_email, author_date, committer, committer_email, committer_date] = fields[..] else {
+        return Err(format!("Failed to read commit {}", commit));
+    };
+    // the message exactly as recorded: everything after the header
+    let raw = run(repo_path, &["cat-file", "commit", commit], &[], &[])?;
+    let message = raw
+        .windows(2)
+        .position(|pair| pair == b"\n\n")
+        .map_or(&[][..], |end| &raw[end + 2..]);
+    let mut args = vec!["commit-tree", tree];
+    for parent in parents {
+        args.extend(["-p", parent.as_str()]);
+    }
+    args.extend(["-F", "-"]);
+    let env = [
+        ("GIT_AUTHOR_NAME", author),
+        ("GIT_AUTHOR_EMAIL", author_email),
+        ("GIT_AUTHOR_DATE", author_date),
+        ("GIT_COMMITTER_NAME", committer),
+        ("GIT_COMMITTER_EMAIL", committer_email),
+        ("GIT_COMMITTER_DATE", committer_date),
+    ];
+    let hash = run(repo_path, &args, &env, message)?;
+    Ok(String::from_utf8_lossy(&hash).trim().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn copied_commit_keeps_message_and_author_with_new_blobs() {
+        let dir = std::env::temp_dir().join(format!("confsync-git-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("store/a")).unwrap();
+        init(&dir).unwrap();
+        std::fs::write(dir.join("store/a/content"), "old").unwrap();
+        git(&dir, &["add", "--all"]).unwrap();
+        git(&dir, &["-c", "user.name=Someone", "-c", "user.email=someone@example.com", "commit", "--quiet", "-m", "Backup: one\n\nbody"])
+            .unwrap();
+        let commit = git(&dir, &["rev-parse", "HEAD"]).unwrap();
+
+        let blob = write_blob(&dir, b"new").unwrap();
+        let tree = replace_blobs(&dir, &commit, &[("store/a/content".to_string(), blob)]).unwrap();
+        let copy = copy_commit(&dir, &commit, &tree, &[]).unwrap();
+        assert_ne!(copy, commit);
+        assert_eq!(show(&dir, &copy, "store/a/content").unwrap().unwrap(), b"new");
+        assert_eq!(show(&dir, &commit, "store/a/content").unwrap().unwrap(), b"old");
+        let format = "%an %ae %ad %B";
+        assert_eq!(
+            git(&dir, &["log", "-1", &format!("--format={}", format), &copy]).unwrap(),
+            git(&dir, &["log", "-1", &format!("--format={}", format), &commit]).unwrap()
+        );
+        // the scratch index is gone and the real one untouched
+        assert!(!dir.join(".git/confsync-rewrite.index").exists());
+        assert!(git(&dir, &["status", "--porcelain"]).unwrap().is_empty());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
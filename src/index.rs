@@ -0,0 +1,127 @@
+use directories::ProjectDirs;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Read;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use crate::perms;
+
+/// Files modified this recently are not cached: a second write within the
+/// mtime granularity would go unnoticed (git's "racily clean" problem)
+const RACY_WINDOW: Duration = Duration::from_secs(2);
+
+/// File metadata that must be unchanged for a cached hash to be trusted
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct FileStat {
+    pub size: u64,
+    pub mtime: i64,
+    pub mtime_nsec: i64,
+    pub ctime: i64,
+    pub ctime_nsec: i64,
+    pub inode: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct IndexEntry {
+    #[serde(flatten)]
+    pub stat: FileStat,
+    /// sha256 of the content, hex encoded
+    pub hash: String,
+}
+
+/// Local cache of (path, metadata, hash), kept in the cache dir per profile.
+/// Never part of the repository; deleting it only costs a full re-hash.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct FileIndex {
+    #[serde(skip)]
+    location: PathBuf,
+    #[serde(default)]
+    files: BTreeMap<String, IndexEntry>,
+}
+
+impl FileIndex {
+    /// Cached hash of the file if its metadata still matches `stat`
+    pub fn cached(&self, path: &Path, stat: &FileStat) -> Option<&str> {
+        self.files
+            .get(&path.display().to_string())
+            .filter(|entry| entry.stat == *stat)
+            .map(|entry| entry.hash.as_str())
+    }
+
+    /// Record the hash of a file that was just read, if its metadata can be trusted
+    pub fn update(&mut self, path: &Path, stat: FileStat, hash: &str) {
+        let key = path.display().to_string();
+        let modified = SystemTime::UNIX_EPOCH
+            + Duration::new(stat.mtime.max(0) as u64, stat.mtime_nsec.clamp(0, 999_999_999) as u32);
+        let racy = SystemTime::now()
+            .duration_since(modified)
+            .map_or(true, |age| age < RACY_WINDOW);
+        if racy {
+            self.files.remove(&key);
+        } else {
+            self.files.insert(key, IndexEntry { stat, hash: hash.to_string() });
+        }
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        if let Some(parent) = self.location.parent() {
+            perms::create_dir(parent)
+                .map_err(|e| format!("Failed to create cache directory: {}", e))?;
+        }
+        let toml_string = toml::to_string_pretty(self)
+            .map_err(|e| format!("Failed to serialize index: {}", e))?;
+        perms::write(&self.location, toml_string)
+            .map_err(|e| format!("Failed to write index: {}", e))
+    }
+}
+
+/// Load the index of the profile. A missing or unreadable index starts empty.
+pub fn load(profile: &str) -> Result<FileIndex, String> {
+    let project_dirs = ProjectDirs::from("", "", "confsync")
+        .ok_or_else(|| "Failed to find cache directory".to_string())?;
+    let location = project_dirs.cache_dir().join(profile).join("index.toml");
+
+    let mut index: FileIndex = fs::read_to_string(&location)
+        .ok()
+        .and_then(|contents| toml::from_str(&contents).ok())
+        .unwrap_or_default();
+    index.location = location;
+    Ok(index)
+}
+
+pub fn stat(path: &Path) -> Result<FileStat, String> {
+    let meta = fs::metadata(path)
+        .map_err(|e| format!("Failed to get metadata of {}: {}", path.display(), e))?;
+
+    Ok(FileStat {
+        size: meta.len(),
+        mtime: meta.mtime(),
+        mtime_nsec: meta.mtime_nsec(),
+        ctime: meta.ctime(),
+        ctime_nsec: meta.ctime_nsec(),
+        // stored signed; only compared for equality
+        inode: meta.ino() as i64,
+    })
+}
+
+/// sha256 of a file, hex encoded
+pub fn hash_file(path: &Path) -> Result<String, String> {
+    let mut file = fs::File::open(path)
+        .map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+    let mut hasher = Sha256::new();
+    let mut buffer = [0u8; 8192];
+    loop {
+        let n = file
+            .read(&mut buffer)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buffer[..n]);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
@@ -0,0 +1,162 @@
+use directories::ProjectDirs;
+use std::fs;
+use std::io::Write;
+use std::os::unix::fs::{MetadataExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+
+use crate::cli::{Commands, DeleteTarget, MirrorCommands, TemplateCommands};
+use crate::config::default_config_path;
+use crate::ops::write_log;
+use crate::perms;
+
+/// Name of the lock file inside the profile repository
+const LOCK_FILE: &str = ".lock";
+
+/// Held lock: a `flock` on the lock file, which the kernel drops if this process dies.
+/// The file is removed when the guard is dropped.
+pub struct LockGuard {
+    path: PathBuf,
+    _file: fs::File,
+}
+
+impl Drop for LockGuard {
+    fn drop(&mut self) {
+        // removed while still locked; a run that opened it before notices and retries.
+        // the repo may have been deleted while locked (`delete local`)
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Lock the repository of the profile.
+pub fn lock_repo(profile: &str) -> Result<LockGuard, String> {
+    let project_dirs = ProjectDirs::from("", "", "confsync")
+        .ok_or_else(|| "Failed to find config directory".to_string())?;
+    let repo_path = project_dirs.data_dir().join(profile);
+    acquire(repo_path.join(LOCK_FILE), "Repository", profile)
+}
+
+/// Lock config.toml for writing.
+pub fn lock_config(profile: &str) -> Result<LockGuard, String> {
+    let path = default_config_path().ok_or_else(|| "Could not determine config path".to_string())?;
+    acquire(path.with_extension("toml.lock"), "Config", profile)
+}
+
+/// Take the locks a command needs before it runs.
+/// Writers of config.toml also take the config lock, always before the repo lock.
+pub fn acquire_for(command: &Commands, profile: &str) -> Result<Vec<LockGuard>, String> {
+    let (repo, config) = writes(command);
+    let mut locks = Vec::new();
+    if config {
+        locks.push(lock_config(profile)?);
+    }
+    if repo {
+        locks.push(lock_repo(profile)?);
+    }
+    Ok(locks)
+}
+
+/// Whether a command writes the repository and config.toml
+pub fn writes(command: &Commands) -> (bool, bool) {
+    match command {
+        // restore may write config.toml back (`confsync` alias);
+        // backup and pull add a new signing key to the trust list
+        Commands::Init { .. }
+        | Commands::Clone { .. }
+        | Commands::Add { .. }
+        | Commands::Remove { .. }
+        | Commands::Restore { .. }
+        | Commands::Backup { .. }
+        | Commands::Pull { .. }
+        | Commands::Template { command: TemplateCommands::Edit { .. } } => {
+            (true, true)
+        }
+        Commands::Delete { target: DeleteTarget::Config { .. } } => (false, true),
+        Commands::Delete { target: DeleteTarget::All { .. } } => (true, true),
+        Commands::Delete { .. }
+        | Commands::Hosts
+        | Commands::Migrate
+        | Commands::Git { .. } => (true, false),
+        Commands::Profile { .. } => (false, true),
+        Commands::Mirror { command: MirrorCommands::Status } => (false, false),
+        Commands::Mirror { .. } => (true, true),
+        Commands::Encrypt { .. } => (true, true),
+        _ => (false, false),
+    }
+}
+
+fn acquire(path: PathBuf, what: &str, profile: &str) -> Result<LockGuard, String> {
+    if let Some(parent) = path.parent() {
+        perms::create_dir(parent)
+            .map_err(|e| format!("Failed to create lock directory: {}", e))?;
+    }
+
+    // a holder removes the file on release; a lock taken on the removed file is retried
+    for _ in 0..3 {
+        let mut file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .mode(perms::FILE_MODE)
+            .open(&path)
+            .map_err(|e| format!("Failed to create lock file: {}", e))?;
+        if !flock(&file, false)? {
+            return Err(match read_holder(&path) {
+                Some((pid, since)) => format!("{} locked by pid {} since {}", what, pid, since),
+                None => format!("{} is being locked by another confsync run", what),
+            });
+        }
+        if !is_current(&file, &path) {
+            continue;
+        }
+
+        // the kernel released the lock of a run that died without removing the file
+        if let Some((pid, _)) = read_holder(&path) {
+            write_log(
+                "warn",
+                "LOCK",
+                &format!("Taking over stale lock {} (pid {})", path.display(), pid),
+                Some(profile.to_string()),
+            )?;
+        }
+        let timestamp = chrono::Local::now().format("%Y-%m-%d %H:%M:%S");
+        file.set_len(0)
+            .and_then(|()| writeln!(file, "{} {}", std::process::id(), timestamp))
+            .map_err(|e| format!("Failed to write lock file: {}", e))?;
+        return Ok(LockGuard { path, _file: file });
+    }
+    Err(format!("Failed to acquire lock {}", path.display()))
+}
+
+/// Read the pid and timestamp of the current lock holder
+fn read_holder(path: &Path) -> Option<(u32, String)> {
+    let contents = fs::read_to_string(path).ok()?;
+    let (pid, since) = contents.trim().split_once(' ')?;
+    Some((pid.parse().ok()?, since.to_string()))
+}
+
+/// True if `file` is still the file at `path`, not one its previous holder removed
+fn is_current(file: &fs::File, path: &Path) -> bool {
+    match (file.metadata(), fs::metadata(path)) {
+        (Ok(held), Ok(current)) => held.dev() == current.dev() && held.ino() == current.ino(),
+        _ => false,
+    }
+}
+
+/// Take an exclusive `flock` on `file`. Returns false if another process holds it and
+/// `wait` is not set. The kernel releases it when the file is closed or its holder dies.
+pub fn flock(file: &fs::File, wait: bool) -> Result<bool, String> {
+    use std::os::unix::io::AsRawFd;
+    let operation = if wait { libc::LOCK_EX } else { libc::LOCK_EX | libc::LOCK_NB };
+    loop {
+        if unsafe { libc::flock(file.as_raw_fd(), operation) } == 0 {
+            return Ok(true);
+        }
+        let error = std::io::Error::last_os_error();
+        match error.raw_os_error() {
+            Some(libc::EINTR) => continue,
+            Some(libc::EWOULDBLOCK) => return Ok(false),
+            _ => return Err(format!("Failed to lock: {}", error)),
+        }
+    }
+}
@@ -1,21 +1,55 @@
 
 use clap::{CommandFactory, Parser};
 
+#[cfg(not(unix))]
+compile_error!("confsync only runs on Unix-like systems: it relies on file modes and flock");
+
+mod audit;
+mod backend;
 mod cli;
 mod config;
+mod crypto;
+mod format;
+mod git;
+mod index;
+mod redact;
 mod repo;
+mod scan;
+mod sensitive;
+mod signing;
+mod template;
 mod ops;
+mod perms;
+mod pool;
+mod merge;
+mod mirror;
+mod remote;
+mod store;
 mod commands;
+mod lock;
 mod ui;
+mod vault;
 
 use commands::{delete::handle_delete, init::handle_init};
 use commands::add::handle_add;
+use commands::migrate::handle_migrate;
+use commands::{backup::{handle_backup, retry_queued_push}, pull::handle_pull, restore::handle_restore};
+use commands::status::handle_status;
+use commands::scan::handle_scan;
+use commands::audit::handle_audit;
+use commands::doctor::handle_doctor;
+use commands::template::handle_template;
+use commands::mirror::handle_mirror;
+use commands::encrypt::handle_encrypt;
+use commands::git::handle_git;
+use commands::hosts::handle_hosts;
+use commands::clone::{handle_clone, CloneOptions};
 
 use cli::{Cli, ConfigCommands};
 use config::{
-    check_config_exists, default_config_path, view_config, is_tracked
+    check_config_exists, default_config_path, view_config
 };
-use ops::{copy_file_to_repo, restore_file, write_log};
+use ops::write_log;
 use ui::printer;
 
 
@@ -40,14 +74,85 @@ fn main() {
         return; 
     }
 
+    // pushes queued while the remote was unreachable are retried by the next writer;
+    // read-only commands stay off the network and the repository lock
+    let retry = match &cli.command {
+        Some(cli::Commands::Backup { push, force, .. }) => !push && !force,
+        Some(cli::Commands::Restore { dry_run, .. }) => !dry_run,
+        // these push themselves, have no repository yet, or remove what would be pushed
+        Some(
+            cli::Commands::Init { .. } | cli::Commands::Clone { .. } | cli::Commands::Delete { .. }
+            | cli::Commands::Encrypt { command: cli::EncryptCommands::Rotate { .. } },
+        ) => false,
+        Some(command) => lock::writes(command).0,
+        None => false,
+    };
+    if retry && check_config_exists() {
+        retry_queued_push(&profile);
+    }
+
+    // writers hold the repository (and config) lock until main returns
+    let _locks = match &cli.command {
+        Some(command) => match lock::acquire_for(command, &profile) {
+            Ok(locks) => locks,
+            Err(e) => {
+                write_log("warn", "LOCK", &e, Some(profile.clone())).unwrap();
+                printer(&e, ui::MessageType::Error);
+                std::process::exit(1);
+            }
+        },
+        None => Vec::new(),
+    };
+
+    // refuse newer repository layouts; writers upgrade older ones in place.
+    // clone checks what it cloned and migrate what it migrates; deleting works on any layout
+    let upgrade = match &cli.command {
+        Some(cli::Commands::Restore { dry_run: true, .. } | cli::Commands::Mirror { command: cli::MirrorCommands::Status }) => Some(false),
+        Some(
+            cli::Commands::Add { .. } | cli::Commands::Backup { .. } | cli::Commands::Restore { .. }
+            | cli::Commands::Pull { .. } | cli::Commands::Git { .. } | cli::Commands::Mirror { .. }
+            | cli::Commands::Encrypt {
+                command: cli::EncryptCommands::Rotate { .. } | cli::EncryptCommands::Passphrase | cli::EncryptCommands::ChangePassphrase,
+            }
+            | cli::Commands::Template { command: cli::TemplateCommands::Edit { .. } },
+        ) => Some(true),
+        Some(
+            cli::Commands::List { .. } | cli::Commands::Status | cli::Commands::Scan { .. } | cli::Commands::Audit { .. }
+            | cli::Commands::Template { .. } | cli::Commands::Hosts | cli::Commands::Doctor { .. } | cli::Commands::Encrypt { .. }
+            | cli::Commands::Delete { target: cli::DeleteTarget::Remote { .. } },
+        ) => Some(false),
+        Some(cli::Commands::Init { .. } | cli::Commands::Clone { .. } | cli::Commands::Delete { .. } | cli::Commands::Migrate) => None,
+        // no repository involved
+        Some(cli::Commands::Remove { .. } | cli::Commands::Watch { .. } | cli::Commands::Profile { .. } | cli::Commands::Config { .. })
+        | None => None,
+    };
+    if let Some(upgrade) = upgrade {
+        match format::ensure_current(&profile, upgrade) {
+            Ok(true) => printer(
+                format!("Repository upgraded to format v{}", format::CURRENT_VERSION).as_str(),
+                ui::MessageType::Info,
+            ),
+            Ok(false) => {}
+            Err(e) => {
+                write_log("error", "FORMAT", &e, Some(profile.clone())).unwrap();
+                printer(&e, ui::MessageType::Error);
+                std::process::exit(1);
+            }
+        }
+    }
+
     match cli.command {
         Some(command) => match command {
             cli::Commands::Init { remote,git, force } => 
                 handle_init(remote, git,force,None),
-            cli::Commands::Add { path,name } => 
-                handle_add(path, name, &profile),
+            cli::Commands::Clone { remote, host, maps, dry_run, yes, force, trust } =>
+                handle_clone(remote, CloneOptions { host, maps, dry_run, yes, force, trust }, cli.quiet, &profile),
+            cli::Commands::Add { path, name, encrypt, allow_sensitive, template } =>
+                handle_add(path, name, encrypt, allow_sensitive, template, &profile),
             cli::Commands::Delete { target } => 
                 handle_delete(target, &profile),
+            cli::Commands::Migrate =>
+                handle_migrate(&profile),
             cli::Commands::Config { command } => {
                 match command {
                     ConfigCommands::Show => {
@@ -73,102 +178,34 @@ fn main() {
                     },
                 }
             }
-            cli::Commands::Git { .. } => {
-                printer("Git functionality has been removed", ui::MessageType::Warning);
-            }
-            cli::Commands::Backup { alias, message, push: _, force: _, env } => {
-                if !check_config_exists() {
-                    println!(" Please run `confsync init` to initialize.");
-                    write_log("warn", "BACKUP", "Attempt to backup without config", None).unwrap();
-                    return;
-                }
-                //if env is true, save env variables into a new file in repo
-                if env {
-                    if !check_config_exists() {
-                        println!(" Please run `confsync init` to initialize.");
-                        write_log("warn", "BACKUP", "Attempt to backup without config", None).unwrap();
-                        return;
-                    }
-                    if let Err(e) = ops::save_env_vars(&profile) {
-                        write_log("error", "BACKUP", &format!("Error saving env vars: {}", e), None).unwrap();
-                        eprintln!("Error saving env vars: {}", e);
-                        return;
-                    } else {
-                        ui::printer("Env saved successfully", ui::MessageType::Success);
-                        write_log("info", "BACKUP", "Env vars saved successfully", None).unwrap();
-                    }
-                }
-                // if alias is not empty, check its existance
-                let alias = alias.unwrap_or_default();
-                if !alias.is_empty() {
-            
-                    if !is_tracked(alias.as_str()) {
-                        println!("{} not found", alias);
-                        write_log("warn", "BACKUP", &format!("{} not found.", alias), None).unwrap();
-                        return;
-                    }
-                    // get the path of the file from alias
-                    let path = match config::get_path_from_alias(&alias) {
-                        Ok(path) => path,
-                        Err(e) => {
-                            write_log("error", "BACKUP", &format!("Error getting path from alias: {}", e), None).unwrap();
-                            eprintln!("Error getting path from alias: {}", e);
-                            return;
-                        }
-                    };
-                    // check if the file exists
-                    if !path.exists() {
-                        println!("File {} not found.", path.display());
-                        write_log("warn", "BACKUP", &format!("File {} not found.", path.display()), None).unwrap();
-                        return;
-                    }
-                    // copy the file to the repo
-                    if let Err(e) = copy_file_to_repo(path.clone(), alias.as_str(), &profile,false) {
-                        write_log("error", "BACKUP", &format!("Error copying file to repo: {}", e), None).unwrap();
-                        eprintln!("Error copying file to repo: {}", e);
-                        return;
-                    } else {
-                        write_log("info", "BACKUP", &format!("File {} copied to repo successfully", alias), None).unwrap();
-                    }
-                    if let Err(e) = repo::commit(&profile, message.as_deref().unwrap_or(&alias)) {
-                        write_log("error", "BACKUP", &format!("Error recording backup: {}", e), None).unwrap();
-                        eprintln!("Error recording backup: {}", e);
-                        return;
-                    } else {
-                        write_log("info", "BACKUP", "Backup completed successfully", None).unwrap();
-                        printer("Done", ui::MessageType::Default);
-                    }
-                    
-                }
-                
+            cli::Commands::Git { args } => {
+                let code = handle_git(args, &profile);
+                // release the lock before exiting with git's status
+                drop(_locks);
+                std::process::exit(code);
             }
-            cli::Commands::Restore { target, dry_run: _, overwrite } => {
-                // check if file is tracked
-                if !is_tracked(target.as_str()) {
-                    println!("{} not found", target);
-                    write_log("warn", "RESTORE", &format!("{} not found.", target), None).unwrap();
-                    return;
-                }
-                // get the path of the file from alias => dest
-                let path= match config::get_path_from_alias(&target) {
-                    Ok(path) => path,
-                    Err(e) => {
-                        write_log("error", "RESTORE", &format!("Error getting path from alias: {}", e), None).unwrap();
-                        eprintln!("Error getting path from alias: {}", e);
-                        return;
-                    }
-                };
-                // copy the file from the repo to the dest
-                if let Err(e) = restore_file(path.clone(), target.as_str(), &profile, overwrite) {
-                    write_log("error", "RESTORE", &format!("Error copying file to repo: {}", e), None).unwrap();
-                    eprintln!("Error copying file to repo: {}", e);
-                    return;
-                } else {
-                    write_log("info", "RESTORE", &format!("File {} copied from repo successfully", target), None).unwrap();
-                    printer("Done", ui::MessageType::Default);  
-
-                }
-            },
+            cli::Commands::Backup { alias, message, push, force, env } =>
+                handle_backup(alias, message, push, force, env, cli.quiet, &profile),
+            cli::Commands::Pull { mine, theirs, host } =>
+                handle_pull(mine, theirs, host, &profile),
+            cli::Commands::Restore { target, dry_run, overwrite, host } =>
+                handle_restore(target, dry_run, overwrite, host, cli.quiet, &profile),
+            cli::Commands::Hosts =>
+                handle_hosts(&profile),
+            cli::Commands::Mirror { command } =>
+                handle_mirror(command, &profile),
+            cli::Commands::Encrypt { command } =>
+                handle_encrypt(command, cli.quiet, &profile),
+            cli::Commands::Status =>
+                handle_status(&profile),
+            cli::Commands::Scan { alias } =>
+                handle_scan(alias, &profile),
+            cli::Commands::Audit { command } =>
+                handle_audit(command, &profile),
+            cli::Commands::Template { command } =>
+                handle_template(command, &profile),
+            cli::Commands::Doctor { yes } =>
+                handle_doctor(yes, &profile),
             cli::Commands::List { alias   } => {
                 // list the tracked files if alias is empty
                 if alias.is_none() {
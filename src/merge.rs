@@ -0,0 +1,448 @@
+use sha2::{Digest, Sha256};
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::format::{self, CURRENT_VERSION};
+use crate::backend::{LocalBackend, HISTORY_LOG};
+use crate::{audit, git, perms, signing, vault};
+use crate::index::hash_file;
+use crate::store::{self, AliasIndex, AliasRecord};
+
+/// How an alias changed on both machines is resolved
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resolution {
+    /// keep the local content
+    Mine,
+    /// take the remote content
+    Theirs,
+    /// three-way merge, finished in $EDITOR
+    Merge,
+}
+
+/// What a merge did to each alias
+#[derive(Debug, Default)]
+pub struct MergeReport {
+    /// aliases only changed remotely, taken as is
+    pub taken: Vec<String>,
+    /// aliases changed on both sides and how they were resolved
+    pub conflicts: Vec<(String, Resolution)>,
+}
+
+/// Merge diverged remote backups into the local branch, alias by alias.
+/// `choose` is asked for every alias whose content changed on both sides.
+pub fn merge_remote(
+    repo_path: &Path,
+    remote_ref: &str,
+    choose: &mut dyn FnMut(&str) -> Result<Resolution, String>,
+) -> Result<MergeReport, String> {
+    if let Some(contents) = git::show(repo_path, remote_ref, format::FORMAT_FILE)? {
+        let version = format::parse_version(&String::from_utf8_lossy(&contents))?;
+        if version > CURRENT_VERSION {
+            return Err(format!(
+                "Remote repository format v{} is newer than supported v{}. Please upgrade confsync.",
+                version, CURRENT_VERSION
+            ));
+        }
+    }
+    // both sides have to be encrypted with the same data key; passphrases may differ
+    let key_id = |contents: Option<Vec<u8>>| -> Result<Option<String>, String> {
+        contents.map(|contents| vault::parse_vault(&contents).map(|vault| vault.key_id)).transpose()
+    };
+    let their_key = key_id(git::show(repo_path, remote_ref, vault::VAULT_FILE)?)?;
+    let our_key = key_id(fs::read(repo_path.join(vault::VAULT_FILE)).ok())?;
+    if their_key != our_key {
+        return Err("Remote backups use a different passphrase encryption than this repository; clone them instead of pulling".into());
+    }
+
+    // nothing local may be lost if the merge has to be aborted
+    git::commit_all(repo_path, "Local changes before merge")?;
+    let base = git::git(repo_path, &["merge-base", "HEAD", remote_ref]).ok();
+
+    // keep our tree and record the remote as second parent; files are merged below
+    git::git_as_user(
+        repo_path,
+        &["merge", "--quiet", "--no-commit", "--no-ff", "--allow-unrelated-histories", "-s", "ours", remote_ref],
+    )?;
+
+    match merge_tree(repo_path, remote_ref, base.as_deref(), choose) {
+        Ok(report) => {
+            // the merged snapshot is this host's to vouch for
+            let backend = vault::wrap(Box::new(LocalBackend::new(repo_path.to_path_buf())))?;
+            signing::sign(&*backend)?;
+            git::git(repo_path, &["add", "--all"])?;
+            let message = format!("Merge backups from {}", remote_ref);
+            git::commit(repo_path, vault::commit_message(repo_path, &message))?;
+            Ok(report)
+        }
+        Err(e) => {
+            let _ = git::git(repo_path, &["merge", "--abort"]);
+            Err(e)
+        }
+    }
+}
+
+fn load_index_at(repo_path: &Path, rev: Option<&str>) -> Result<AliasIndex, String> {
+    match rev {
+        Some(rev) => match git::show(repo_path, rev, store::ALIASES_FILE)? {
+            Some(contents) => {
+                store::parse_index(&String::from_utf8_lossy(&vault::decode_at(repo_path, store::ALIASES_FILE, contents)?))
+            }
+            None => Ok(AliasIndex::default()),
+        },
+        None => Ok(AliasIndex::default()),
+    }
+}
+
+/// Path of a file of the alias inside the repository, relative to its root
+fn store_path(key: &str, file: &str) -> String {
+    format!("{}/{}/{}", store::STORE_DIR, key, file)
+}
+
+/// Identity of the content of `alias` at `rev`: the recorded plaintext hash, since encrypted
+/// content differs on every host. Records from before hashes were kept fall back to the blob id.
+fn content_id(repo_path: &Path, index: &AliasIndex, rev: Option<&str>, alias: &str) -> Option<String> {
+    let record = index.get(alias)?;
+    if let Some(hash) = &record.hash {
+        return Some(hash.clone());
+    }
+    git::rev_parse(repo_path, &format!("{}:{}", rev?, store_path(&record.key, store::CONTENT_FILE)))
+}
+
+fn merge_tree(
+    repo_path: &Path,
+    remote_ref: &str,
+    base: Option<&str>,
+    choose: &mut dyn FnMut(&str) -> Result<Resolution, String>,
+) -> Result<MergeReport, String> {
+    let mut ours = store::load_index(repo_path)?;
+    let theirs = load_index_at(repo_path, Some(remote_ref))?;
+    let base_index = load_index_at(repo_path, base)?;
+
+    let mut report = MergeReport::default();
+    let aliases: BTreeSet<String> = ours.aliases.keys().chain(theirs.aliases.keys()).cloned().collect();
+
+    for alias in aliases {
+        let Some(their_record) = theirs.get(&alias).cloned() else {
+            continue;
+        };
+        let mine = content_id(repo_path, &ours, Some("HEAD"), &alias);
+        let their = content_id(repo_path, &theirs, Some(remote_ref), &alias);
+        let old = content_id(repo_path, &base_index, base, &alias);
+        // removed here since the common backup, as opposed to new on the remote
+        let removed = ours.get(&alias).is_none() && base_index.get(&alias).is_some();
+
+        let resolution = if their.is_none() || their == mine || their == old {
+            Resolution::Mine
+        } else if !removed && (mine.is_none() || mine == old) {
+            report.taken.push(alias.clone());
+            Resolution::Theirs
+        } else {
+            let resolution = choose(&alias)?;
+            report.conflicts.push((alias.clone(), resolution));
+            resolution
+        };
+        if removed {
+            match resolution {
+                // the local removal stands
+                Resolution::Mine => continue,
+                Resolution::Theirs => {}
+                Resolution::Merge => {
+                    return Err(format!("{} was removed here and changed on the remote; keep mine or theirs", alias));
+                }
+            }
+        }
+
+        // a remote-only alias gets a local key, which may differ from the remote one
+        if ours.get(&alias).is_none() {
+            let key = if ours.aliases.values().any(|r| r.key == their_record.key) {
+                ours.new_key(&alias)
+            } else {
+                their_record.key.clone()
+            };
+            ours.aliases.insert(alias.clone(), AliasRecord { key, ..their_record.clone() });
+        }
+        let record = ours.aliases.get_mut(&alias).expect("alias recorded above");
+        let key_dir = store::key_dir(repo_path, &record.key);
+        perms::create_dir(&key_dir).map_err(|e| format!("Failed to create directory: {}", e))?;
+        let content_path = store::content_path(repo_path, &record.key);
+        let content_key = store_path(&record.key, store::CONTENT_FILE);
+        let their_key = store_path(&their_record.key, store::CONTENT_FILE);
+        let their_content = || -> Result<Vec<u8>, String> {
+            git::show(repo_path, remote_ref, &their_key)?.ok_or_else(|| format!("Remote content of {} is missing", alias))
+        };
+
+        match resolution {
+            Resolution::Mine => {}
+            Resolution::Theirs => {
+                // sealed objects are bound to their key; one stored under another key is sealed again
+                let content = match their_key == content_key {
+                    true => their_content()?,
+                    false => vault::encode_at(repo_path, &content_key, vault::decode_at(repo_path, &their_key, their_content()?)?)?,
+                };
+                perms::write(&content_path, content).map_err(|e| format!("Failed to write {}: {}", alias, e))?;
+                record.source = their_record.source.clone();
+                record.file_name = their_record.file_name.clone();
+                record.updated = their_record.updated.clone();
+                record.encrypted = their_record.encrypted;
+                // recorded hashes are of the plaintext, also for encrypted content
+                record.hash = match &their_record.hash {
+                    Some(hash) => Some(hash.clone()),
+                    None => Some(hash_file(&content_path)?),
+                };
+            }
+            Resolution::Merge if record.encrypted || their_record.encrypted => {
+                return Err(format!("{} is encrypted and cannot be merged line by line; keep mine or theirs", alias));
+            }
+            Resolution::Merge => {
+                let base_content = match (base, base_index.get(&alias)) {
+                    (Some(base), Some(base_record)) => {
+                        let base_key = store_path(&base_record.key, store::CONTENT_FILE);
+                        match git::show(repo_path, base, &base_key)? {
+                            Some(content) => vault::decode_at(repo_path, &base_key, content)?,
+                            None => Vec::new(),
+                        }
+                    }
+                    _ => Vec::new(),
+                };
+                let mine = fs::read(&content_path).map_err(|e| format!("Failed to read {}: {}", alias, e))?;
+                let merged = merge_file(
+                    repo_path,
+                    vault::decode_at(repo_path, &content_key, mine)?,
+                    base_content,
+                    vault::decode_at(repo_path, &their_key, their_content()?)?,
+                )?;
+                record.hash = Some(format!("{:x}", Sha256::digest(&merged)));
+                perms::write(&content_path, vault::encode_at(repo_path, &content_key, merged)?)
+                    .map_err(|e| format!("Failed to write {}: {}", alias, e))?;
+                record.updated = chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string();
+            }
+        }
+
+        // backup timestamps of both machines are kept
+        let their_history_key = store_path(&their_record.key, store::HISTORY_FILE);
+        let their_history = git::show(repo_path, remote_ref, &their_history_key)?
+            .map(|contents| vault::decode_at(repo_path, &their_history_key, contents))
+            .transpose()?;
+        union_lines(repo_path, &store_path(&record.key, store::HISTORY_FILE), their_history)?;
+    }
+
+    store::save_index(repo_path, &ours)?;
+    let their_history = git::show(repo_path, remote_ref, HISTORY_LOG)?
+        .map(|contents| vault::decode_at(repo_path, HISTORY_LOG, contents))
+        .transpose()?;
+    union_history(repo_path, their_history)?;
+    Ok(report)
+}
+
+/// Append the records of `other` missing from the history index.
+/// Local records stay as they are, so the hash chain is extended rather than rewritten.
+fn union_history(repo_path: &Path, other: Option<Vec<u8>>) -> Result<(), String> {
+    let Some(other) = other else {
+        return Ok(());
+    };
+    let mine = read_decoded(repo_path, HISTORY_LOG)?;
+    let mine = String::from_utf8_lossy(&mine);
+    let other = String::from_utf8_lossy(&other);
+    let known: BTreeSet<&str> = mine.lines().map(audit::text).collect();
+    let mut missing: Vec<&str> = other.lines().map(audit::text).filter(|text| !known.contains(text)).collect();
+    // records start with their timestamp
+    missing.sort();
+    missing.dedup();
+    let contents = audit::extend(&mine, &missing);
+    write_encoded(repo_path, HISTORY_LOG, contents.into_bytes())
+}
+
+/// Merge the lines of `other` into the object `key`, sorted and without duplicates
+fn union_lines(repo_path: &Path, key: &str, other: Option<Vec<u8>>) -> Result<(), String> {
+    let Some(other) = other else {
+        return Ok(());
+    };
+    let mine = read_decoded(repo_path, key)?;
+    let mine = String::from_utf8_lossy(&mine);
+    let other = String::from_utf8_lossy(&other);
+    let lines: BTreeSet<&str> = mine.lines().chain(other.lines()).collect();
+    let mut contents = lines.into_iter().collect::<Vec<_>>().join("\n");
+    contents.push('\n');
+    write_encoded(repo_path, key, contents.into_bytes())
+}
+
+/// Decrypted contents of the local object `key`, empty if it does not exist
+fn read_decoded(repo_path: &Path, key: &str) -> Result<Vec<u8>, String> {
+    match fs::read(repo_path.join(key)) {
+        Ok(contents) => vault::decode_at(repo_path, key, contents),
+        Err(_) => Ok(Vec::new()),
+    }
+}
+
+fn write_encoded(repo_path: &Path, key: &str, contents: Vec<u8>) -> Result<(), String> {
+    perms::write(repo_path.join(key), vault::encode_at(repo_path, key, contents)?)
+        .map_err(|e| format!("Failed to write {}: {}", key, e))
+}
+
+/// Scratch directory of a merge, removed however the merge ends: it holds decrypted content
+struct WorkDir(PathBuf);
+
+impl Drop for WorkDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Three-way merge of the local content with the remote one, finished in $EDITOR
+fn merge_file(repo_path: &Path, mine: Vec<u8>, base: Vec<u8>, theirs: Vec<u8>) -> Result<Vec<u8>, String> {
+    let work_dir = WorkDir(repo_path.join(".git").join("confsync-merge"));
+    perms::create_dir(&work_dir.0).map_err(|e| format!("Failed to create merge directory: {}", e))?;
+    let merged = work_dir.0.join("merged");
+    let base_path = work_dir.0.join("base");
+    let theirs_path = work_dir.0.join("theirs");
+    perms::write(&merged, mine).map_err(|e| format!("Failed to prepare merge: {}", e))?;
+    perms::write(&base_path, base).map_err(|e| format!("Failed to prepare merge: {}", e))?;
+    perms::write(&theirs_path, theirs).map_err(|e| format!("Failed to prepare merge: {}", e))?;
+
+    // exit code is the number of conflicts, capped at 127; errors exit with 255
+    let status = Command::new("git")
+        .args(["merge-file", "-L", "mine", "-L", "base", "-L", "theirs"])
+        .arg(&merged)
+        .arg(&base_path)
+        .arg(&theirs_path)
+        .status()
+        .map_err(|e| format!("Failed to run git merge-file: {}", e))?;
+    if status.code().is_none_or(|code| code > 127) {
+        return Err("git merge-file failed".into());
+    }
+
+    let editor = std::env::var("EDITOR").unwrap_or_else(|_| "nano".to_string());
+    let status = Command::new(editor)
+        .arg(&merged)
+        .status()
+        .map_err(|e| format!("Failed to open merge in editor: {}", e))?;
+    if !status.success() {
+        return Err("Editor exited with an error; merge aborted".into());
+    }
+
+    let result = fs::read(&merged).map_err(|e| format!("Failed to read merge result: {}", e))?;
+    if String::from_utf8_lossy(&result).lines().any(|line| line.starts_with("<<<<<<<")) {
+        return Err("Conflict markers left in the merge result; merge aborted".into());
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::git::BRANCH;
+
+    /// Store `content` under `alias` in the working tree of `repo`
+    fn store_alias(repo: &Path, alias: &str, content: &str) {
+        let mut index = store::load_index(repo).unwrap();
+        let key = index.record(alias, Path::new(&format!("/home/user/.{}", alias))).unwrap();
+        index.aliases.get_mut(alias).unwrap().hash = Some(format!("{:x}", Sha256::digest(content)));
+        fs::create_dir_all(store::key_dir(repo, &key)).unwrap();
+        fs::write(store::content_path(repo, &key), content).unwrap();
+        let mut history = fs::read_to_string(store::history_path(repo, &key)).unwrap_or_default();
+        history.push_str(&format!("[{}] {}\n", content.lines().next().unwrap_or_default(), alias));
+        fs::write(store::history_path(repo, &key), history).unwrap();
+        store::save_index(repo, &index).unwrap();
+    }
+
+    fn stored(repo: &Path, alias: &str) -> String {
+        let index = store::load_index(repo).unwrap();
+        fs::read_to_string(store::content_path(repo, &index.get(alias).unwrap().key)).unwrap()
+    }
+
+    /// A laptop and a desktop that diverged through a bare remote since their common backup:
+    /// `notes` has other lines changed on each side, `shell` is changed on both,
+    /// `git` is only changed and `vim` only added on the desktop.
+    /// Returns the laptop repository, with the desktop's backups fetched to `origin/main`.
+    fn diverged(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("confsync-merge-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let (laptop, desktop) = (dir.join("laptop"), dir.join("desktop"));
+        git::git(&dir, &["init", "--quiet", "--bare", "remote.git"]).unwrap();
+
+        fs::create_dir_all(&laptop).unwrap();
+        git::init(&laptop).unwrap();
+        store_alias(&laptop, "notes", "one\ntwo\nthree\n");
+        store_alias(&laptop, "shell", "base shell\n");
+        store_alias(&laptop, "git", "base git\n");
+        git::commit_all(&laptop, "base").unwrap();
+        git::set_remote(&laptop, "origin", dir.join("remote.git").to_str().unwrap()).unwrap();
+        git::git(&laptop, &["push", "--quiet", "origin", BRANCH]).unwrap();
+
+        git::git(&dir, &["clone", "--quiet", "--branch", BRANCH, "remote.git", "desktop"]).unwrap();
+        store_alias(&desktop, "notes", "ONE\ntwo\nthree\n");
+        store_alias(&desktop, "shell", "their shell\n");
+        store_alias(&desktop, "git", "their git\n");
+        store_alias(&desktop, "vim", "their vim\n");
+        git::commit_all(&desktop, "desktop").unwrap();
+        git::git(&desktop, &["push", "--quiet", "origin", BRANCH]).unwrap();
+
+        store_alias(&laptop, "notes", "one\ntwo\nTHREE\n");
+        store_alias(&laptop, "shell", "my shell\n");
+        git::commit_all(&laptop, "laptop").unwrap();
+        git::git(&laptop, &["fetch", "--quiet", "origin"]).unwrap();
+        laptop
+    }
+
+    fn merge(laptop: &Path, choose: &mut dyn FnMut(&str) -> Result<Resolution, String>) -> Result<MergeReport, String> {
+        let remote_ref = format!("origin/{}", BRANCH);
+        let base = git::git(laptop, &["merge-base", "HEAD", &remote_ref]).unwrap();
+        merge_tree(laptop, &remote_ref, Some(&base), choose)
+    }
+
+    fn cleanup(laptop: &Path) {
+        let _ = fs::remove_dir_all(laptop.parent().unwrap());
+    }
+
+    #[test]
+    fn one_sided_changes_are_taken_and_conflicts_are_asked() {
+        let laptop = diverged("sides");
+        let mut asked = Vec::new();
+        let report = merge(&laptop, &mut |alias| {
+            asked.push(alias.to_string());
+            Ok(if alias == "shell" { Resolution::Mine } else { Resolution::Theirs })
+        })
+        .unwrap();
+
+        assert_eq!(asked, ["notes", "shell"]);
+        assert_eq!(report.taken, ["git", "vim"]);
+        assert_eq!(report.conflicts, [("notes".to_string(), Resolution::Theirs), ("shell".to_string(), Resolution::Mine)]);
+        assert_eq!(stored(&laptop, "notes"), "ONE\ntwo\nthree\n");
+        assert_eq!(stored(&laptop, "shell"), "my shell\n");
+        assert_eq!(stored(&laptop, "git"), "their git\n");
+        assert_eq!(stored(&laptop, "vim"), "their vim\n");
+
+        // taken content carries the remote's hash, and both machines' backup times are kept
+        let index = store::load_index(&laptop).unwrap();
+        let record = index.get("git").unwrap();
+        assert_eq!(record.hash.as_deref(), Some(format!("{:x}", Sha256::digest("their git\n")).as_str()));
+        let history = fs::read_to_string(store::history_path(&laptop, &index.get("shell").unwrap().key)).unwrap();
+        assert_eq!(history, "[base shell] shell\n[my shell] shell\n[their shell] shell\n");
+        cleanup(&laptop);
+    }
+
+    #[test]
+    fn merge_combines_both_edits_and_leaves_no_decrypted_copies() {
+        // the editor accepts what git merge-file produced
+        std::env::set_var("EDITOR", "true");
+        let laptop = diverged("edit");
+        let work_dir = laptop.join(".git").join("confsync-merge");
+        let report = merge(&laptop, &mut |alias| Ok(if alias == "notes" { Resolution::Merge } else { Resolution::Mine })).unwrap();
+
+        assert!(report.conflicts.contains(&("notes".to_string(), Resolution::Merge)));
+        let merged = "ONE\ntwo\nTHREE\n";
+        assert_eq!(stored(&laptop, "notes"), merged);
+        let index = store::load_index(&laptop).unwrap();
+        assert_eq!(index.get("notes").unwrap().hash.as_deref(), Some(format!("{:x}", Sha256::digest(merged)).as_str()));
+        assert!(!work_dir.exists());
+
+        // the same line edited on both sides keeps its conflict markers, so the merge fails
+        let result = merge(&laptop, &mut |alias| Ok(if alias == "shell" { Resolution::Merge } else { Resolution::Mine }));
+        assert!(result.unwrap_err().contains("Conflict markers"));
+        assert!(!work_dir.exists());
+        cleanup(&laptop);
+    }
+}
@@ -0,0 +1,161 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::config::{self, load_config, Mirror};
+use crate::git::{self, BRANCH};
+use crate::ops::write_log;
+use crate::perms;
+use crate::remote;
+
+/// Git remotes of mirrors are named `mirror-<name>`
+const MIRROR_PREFIX: &str = "mirror-";
+
+/// Last push result of every mirror, kept inside `.git` so it is never committed
+const STATE_FILE: &str = "confsync-mirrors.toml";
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct MirrorState {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_push: Option<String>,
+    /// set while the last push failed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct StateFile {
+    #[serde(default)]
+    mirrors: BTreeMap<String, MirrorState>,
+}
+
+/// A mirror with its last known state
+#[derive(Debug)]
+pub struct MirrorStatus {
+    pub mirror: Mirror,
+    pub state: MirrorState,
+    /// snapshots the mirror does not have yet; None if it was never pushed to
+    pub behind: Option<usize>,
+}
+
+/// Every mirror pushed to, with the result of its push
+pub type PushResults = Vec<(Mirror, Result<(), String>)>;
+
+fn remote_name(mirror: &Mirror) -> String {
+    format!("{}{}", MIRROR_PREFIX, mirror.name)
+}
+
+fn state_path(repo_path: &Path) -> PathBuf {
+    repo_path.join(".git").join(STATE_FILE)
+}
+
+fn load_state(repo_path: &Path) -> Result<StateFile, String> {
+    let path = state_path(repo_path);
+    if !path.exists() {
+        return Ok(StateFile::default());
+    }
+    let contents = fs::read_to_string(&path).map_err(|e| format!("Failed to read mirror state: {}", e))?;
+    toml::from_str(&contents).map_err(|e| format!("Failed to parse mirror state: {}", e))
+}
+
+fn save_state(repo_path: &Path, state: &StateFile) -> Result<(), String> {
+    let contents = toml::to_string_pretty(state).map_err(|e| format!("Failed to serialize mirror state: {}", e))?;
+    perms::write(state_path(repo_path), contents).map_err(|e| format!("Failed to write mirror state: {}", e))
+}
+
+/// Mirror names become git remote names
+pub fn validate_name(name: &str) -> Result<(), String> {
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        return Err(format!("Invalid mirror name {}: use letters, digits, - and _", name));
+    }
+    Ok(())
+}
+
+/// Push this host's branch to every mirror. Mirrors replicate the branch as is, so pushes are forced.
+pub fn push_all(profile: &str) -> Result<PushResults, String> {
+    push_where(profile, |_| true)
+}
+
+/// Push again to the mirrors whose last push failed
+pub fn retry_failed(profile: &str) -> Result<PushResults, String> {
+    push_where(profile, |state| state.is_some_and(|state| state.last_error.is_some()))
+}
+
+fn push_where(
+    profile: &str,
+    select: impl Fn(Option<&MirrorState>) -> bool,
+) -> Result<PushResults, String> {
+    let mirrors = load_config()?.storage.mirrors;
+    if mirrors.is_empty() {
+        return Ok(Vec::new());
+    }
+    let repo_path = remote::repo_path(profile)?;
+    let branch = remote::host_branch(&config::host_name()?);
+    let mut state = load_state(&repo_path)?;
+
+    let mut results = Vec::new();
+    for mirror in mirrors {
+        if !select(state.mirrors.get(&mirror.name)) {
+            continue;
+        }
+        let result = push_one(&repo_path, &mirror, &branch);
+        let entry = state.mirrors.entry(mirror.name.clone()).or_default();
+        match &result {
+            Ok(()) => {
+                entry.last_push = Some(chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string());
+                entry.last_error = None;
+                write_log("info", "MIRROR", &format!("Pushed {} to {}", branch, mirror.name), Some(profile.to_string()))?;
+            }
+            Err(e) => {
+                entry.last_error = Some(e.lines().next().unwrap_or_default().to_string());
+                write_log("warn", "MIRROR", &format!("Push to {} failed: {}", mirror.name, e), Some(profile.to_string()))?;
+            }
+        }
+        results.push((mirror, result));
+    }
+    save_state(&repo_path, &state)?;
+    Ok(results)
+}
+
+fn push_one(repo_path: &Path, mirror: &Mirror, branch: &str) -> Result<(), String> {
+    let name = remote_name(mirror);
+    remote::connect_to(repo_path, &name, &mirror.url)?;
+    git::git(repo_path, &["push", "--quiet", "--force", &name, &format!("{}:refs/heads/{}", BRANCH, branch)])?;
+    // keep the remote-tracking ref current so `mirror status` can count offline
+    let _ = git::git(repo_path, &["fetch", "--quiet", &name, branch]);
+    Ok(())
+}
+
+/// State of every configured mirror, without contacting any of them
+pub fn status(profile: &str) -> Result<Vec<MirrorStatus>, String> {
+    let mirrors = load_config()?.storage.mirrors;
+    let repo_path = remote::repo_path(profile)?;
+    let branch = remote::host_branch(&config::host_name()?);
+    let mut state = load_state(&repo_path)?;
+
+    let mut statuses = Vec::new();
+    for mirror in mirrors {
+        let tracking = format!("{}/{}", remote_name(&mirror), branch);
+        let behind = match git::rev_parse(&repo_path, &tracking) {
+            Some(_) => git::git(&repo_path, &["rev-list", "--count", &format!("{}..HEAD", tracking)])?
+                .parse()
+                .ok(),
+            None => None,
+        };
+        let state = state.mirrors.remove(&mirror.name).unwrap_or_default();
+        statuses.push(MirrorStatus { mirror, state, behind });
+    }
+    Ok(statuses)
+}
+
+/// Drop the git remote and recorded state of a mirror that was removed from the config
+pub fn forget(profile: &str, mirror: &Mirror) -> Result<(), String> {
+    let repo_path = remote::repo_path(profile)?;
+    let _ = git::git(&repo_path, &["remote", "remove", &remote_name(mirror)]);
+    let mut state = load_state(&repo_path)?;
+    if state.mirrors.remove(&mirror.name).is_some() {
+        save_state(&repo_path, &state)?;
+    }
+    Ok(())
+}
@@ -1,175 +1,549 @@
 use directories::ProjectDirs;
-use std::{fs, io::{Read, Write}, path::{Path, PathBuf}};
-
+use indicatif::ProgressBar;
+use sha2::{Digest, Sha256};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::{fs, path::{Path, PathBuf}};
+
+use crate::backend::{self, Backend};
+use crate::{audit, config, crypto, index, lock, perms, pool, scan, sensitive, signing, store};
+use crate::redact::Redaction;
+use crate::template::Template;
+use crate::scan::{ScanConfig, ScanMode};
 use crate::ui::{self, printer};
 
-/// helper fn to compare two files
-fn compare_files(path1: &Path,path2: &Path) -> Result<bool, String> {
-    let mut file1 = fs::File::open(path1)
-        .map_err(|e| format!("Failed to open file1: {}", e))?;
-    let mut file2 = fs::File::open(path2)
-        .map_err(|e| format!("Failed to open file2: {}", e))?;
+/// Outcome of backing up or restoring a single file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Transfer {
+    Copied,
+    Unchanged,
+    /// copied, with likely secrets the scanner only warns about;
+    /// reported by the caller since workers do not print
+    Flagged(String),
+}
 
-    let mut buf1 = [0u8;8192];
-    let mut buf2 = [0u8;8192];
+/// A hash computed by a worker, recorded in the file index afterwards
+type Hashed = Option<(PathBuf, index::FileStat, String)>;
 
-    loop {
-        let n1 = file1.read(&mut buf1)
-            .map_err(|e| format!("Failed to read file1: {}", e))?;
-        let n2 = file2.read(&mut buf2)
-            .map_err(|e| format!("Failed to read file2: {}", e))?;
-        if n1 != n2 {
-            return Ok(false);
-        }
-        if n1 == 0 {
-            break;
+// Copy tracked file
+pub fn copy_file_to_repo(src: PathBuf, alias: &str, profile: &str, force: bool) -> Result<(), String> {
+    let files = [(alias.to_string(), src)];
+    let results = backup_files(&files, profile, force, &ProgressBar::hidden())?;
+    match results.into_iter().next() {
+        Some(Ok(Transfer::Unchanged)) => {
+            printer("That one has a backup", ui::MessageType::Success);
+            Ok(())
         }
-        if buf1[..n1] != buf2[..n2] {
-            return Ok(false);
+        Some(Ok(Transfer::Flagged(found))) => {
+            printer(format!("{} looks like it contains secrets: {}", alias, found).as_str(), ui::MessageType::Warning);
+            Ok(())
         }
+        Some(result) => result.map(|_| ()),
+        None => Ok(()),
     }
-    Ok(true)
 }
 
+/// Back up `(alias, source)` pairs concurrently.
+/// Returns one result per file, in input order; the outer error is for repository-wide failures.
+pub fn backup_files(
+    files: &[(String, PathBuf)],
+    profile: &str,
+    force: bool,
+    progress: &ProgressBar,
+) -> Result<Vec<Result<Transfer, String>>, String> {
+    let backend = backend::open(profile)?;
+    // creating the key adds it to the config, which may be one of the files
+    signing::host_key()?;
+
+    // content is stored under the alias' storage key, not the live file name.
+    // keys are assigned up front so workers only read shared state
+    let mut aliases = store::read_index(&*backend)?;
+    let config = config::load_config()?;
+    let mut jobs = Vec::new();
+    for (alias, src) in files {
+        let stored = aliases.get(alias).cloned();
+        let key = aliases.record(alias, src)?;
+        // aliases the scanner encrypted stay encrypted
+        let auto = config.scan.mode == ScanMode::Encrypt && stored.as_ref().is_some_and(|r| r.encrypted);
+        let encrypt = config.encryption.aliases.contains(alias) || auto;
+        jobs.push((alias.as_str(), src, key, stored, encrypt));
+    }
 
-// Copy tracked file
-pub fn copy_file_to_repo(src: PathBuf, alias: &str, profile: &str, force: bool) -> Result<(), String> {
-    
-    let project_dirs =
-        ProjectDirs::from("", "", "confsync").expect("Failed to get project directories");
-    let repo_path = project_dirs.data_dir().join(profile);
+    let mut cache = index::load(profile)?;
+    let results = pool::run(
+        &jobs,
+        |(alias, src, key, stored, encrypt)| {
+            let redaction = Redaction::of(&config, alias);
+            let template = Template::of(&config, alias)?;
+            let options = StoreOptions { encrypt: *encrypt, force, scan: &config.scan, redaction, template };
+            store_file(src, alias, key, stored.as_ref(), options, &*backend, profile, &cache)
+        },
+        progress,
+    );
+    progress.finish_and_clear();
+
+    let mut outcomes = Vec::new();
+    for ((alias, _, _, _, _), result) in jobs.iter().zip(results) {
+        outcomes.push(result.map(|(transfer, hashed, encrypted)| {
+            if let Some((path, stat, hash)) = hashed {
+                if let Some(record) = aliases.aliases.get_mut(*alias) {
+                    record.hash = Some(hash.clone());
+                    record.encrypted = encrypted;
+                }
+                // the hash of redacted content or of a template is not the hash of the file
+                if !config.redact.contains_key(*alias) && !config.templates.aliases.contains(*alias) {
+                    cache.update(&path, stat, &hash);
+                }
+            }
+            transfer
+        }));
+    }
+    cache.save()?;
+    // only the records of these files: other hosts may have recorded theirs meanwhile
+    store::update_index(&*backend, &mut |index| {
+        for (alias, _, _, _, _) in &jobs {
+            if let Some(record) = aliases.get(alias) {
+                index.aliases.insert(alias.to_string(), record.clone());
+            }
+        }
+        Ok(())
+    })?;
+    Ok(outcomes)
+}
 
-    // extract the file name from the path
-    let file_name = src
-        .file_name()
-        .ok_or_else(|| "Failed to get file name".to_string())?
-        .to_str()
-        .ok_or_else(|| "Failed to convert file name to string".to_string())?;
+/// How a file is stored
+struct StoreOptions<'a> {
+    encrypt: bool,
+    force: bool,
+    scan: &'a ScanConfig,
+    redaction: Option<Redaction<'a>>,
+    template: Option<Template<'a>>,
+}
 
-    let dest = repo_path.join(alias).join(file_name);
-    // create the directory if it doesn't exist
-    if let Some(parent) = dest.parent() {
-        fs::create_dir_all(parent).map_err(|e| format!("Failed to create directory: {}", e))?;
-    }
-    write_log("info", "COPY", &format!("Copying {} to {}", src.display(), dest.display()), Some(profile.to_string()))?;
-
-    let _file_size = fs::metadata(&src)
-        .map_err(|e| format!("Failed to get file metadata: {}", e))?
-        .len();
-    // compare the files
-    if !force && dest.exists() {
-        let src_meta = fs::metadata(&src)
-            .map_err(|e| format!("Failed to get source file metadata: {}", e))?;
-        let dest_meta = fs::metadata(&dest)
-            .map_err(|e| format!("Failed to get destination file metadata: {}", e))?;
-
-        if src_meta.len() == dest_meta.len() {
-            if compare_files(&src, &dest)? {
-                printer(format!("That one has a backup").as_str(), ui::MessageType::Success);
+/// Store one file under its key, unless its hash matches the stored content.
+/// Encrypted aliases are stored as age files, redacted values as placeholders.
+/// Templates are only checked against their rendered output.
+/// Returns whether the stored content is encrypted.
+#[allow(clippy::too_many_arguments)]
+fn store_file(
+    src: &Path,
+    alias: &str,
+    key: &str,
+    stored: Option<&store::AliasRecord>,
+    options: StoreOptions,
+    backend: &dyn Backend,
+    profile: &str,
+    cache: &index::FileIndex,
+) -> Result<(Transfer, Hashed, bool), String> {
+    let StoreOptions { encrypt, force, scan, redaction, template } = options;
+    if !src.exists() {
+        return Err(format!("File {} not found.", src.display()));
+    }
+    let content_key = store::content_key(key);
+    write_log("info", "COPY", &format!("Copying {} to {}", src.display(), content_key), Some(profile.to_string()))?;
+
+    // compare hashes; the file index avoids reading files whose metadata is unchanged
+    let src_stat = index::stat(src)?;
+    // redacted files are compared as stored; their values are kept locally even when nothing else changed
+    let redacted = match redaction {
+        Some(redaction) => {
+            let data = fs::read(src).map_err(|e| format!("Failed to read source file: {}", e))?;
+            let (data, values) = redaction.redact(&data)?;
+            redaction.remember(&values)?;
+            Some(data)
+        }
+        None => None,
+    };
+    // the rendered output cannot be turned back into the template; it is changed with `template edit`
+    if let (Some(template), Some(record)) = (&template, stored) {
+        let current = match redacted {
+            Some(data) => data,
+            None => fs::read(src).map_err(|e| format!("Failed to read source file: {}", e))?,
+        };
+        let stored_template = read_stored(record, backend)?;
+        if template.render(&stored_template)? != current {
+            return Err(format!(
+                "{} differs from the output of its template: it was edited, or the template or its variables changed. Change the template with `confsync template edit {}`, or render it again with `confsync restore {}`",
+                alias, alias, alias
+            ));
+        }
+        if record.encrypted == encrypt {
+            return Ok((Transfer::Unchanged, None, encrypt));
+        }
+        let (hash, encrypt, transfer) = put_content(alias, key, &stored_template, encrypt, scan, backend, profile, &src.display().to_string())?;
+        return Ok((transfer, Some((src.to_path_buf(), src_stat, hash)), encrypt));
+    }
+    // content switching to or from encryption is stored again
+    if !force && stored.is_none_or(|record| record.encrypted == encrypt) {
+        // without a recorded hash the stored content has to be read
+        let stored_hash = match stored.and_then(|record| record.hash.as_deref()) {
+            Some(hash) => Some(hash.to_string()),
+            None if encrypt => None,
+            None => backend.get(&content_key)?.map(|data| format!("{:x}", Sha256::digest(&data))),
+        };
+        if let Some(stored_hash) = stored_hash {
+            let src_hash = match (&redacted, cache.cached(src, &src_stat)) {
+                (Some(data), _) => format!("{:x}", Sha256::digest(data)),
+                (None, Some(hash)) => hash.to_string(),
+                (None, None) => index::hash_file(src)?,
+            };
+            if src_hash == stored_hash {
                 write_log("info",
                     "COPY",
-                    &format!("File already backedup {}", dest.display()), Some(profile.to_string()))?;
-                return Ok(());
+                    &format!("File already backedup {}", content_key), Some(profile.to_string()))?;
+                return Ok((Transfer::Unchanged, Some((src.to_path_buf(), src_stat, src_hash)), encrypt));
             }
         }
     }
 
+    // read once for the scan, the hash and the upload
+    let data = match redacted {
+        Some(data) => data,
+        None => fs::read(src).map_err(|e| format!("Failed to read source file: {}", e))?,
+    };
+    let (hash, encrypt, transfer) = put_content(alias, key, &data, encrypt, scan, backend, profile, &src.display().to_string())?;
+    Ok((transfer, Some((src.to_path_buf(), src_stat, hash)), encrypt))
+}
 
-    // Re-open source file to reset pointer for copying
-    let mut src_file = fs::File::open(&src)
-        .map_err(|e| format!("Failed to open source file: {}", e))?;
-    let mut dest_file = fs::File::create(&dest)
-    .map_err(|e| format!("Failed to create destination file: {}", e))?;
-    let mut buffer = [0u8; 8192];
-
-    loop {
-        let bytes_read = src_file
-            .read(&mut buffer)
-            .map_err(|e| format!("Failed to read from source file: {}", e))?;
-        if bytes_read == 0 {
-            break;
+/// Scan, encrypt if needed and store `data` as the content of `key`, noting `source` in its history.
+/// Returns the hash of `data`, whether it was stored encrypted and the transfer,
+/// flagged with the findings the scanner only warns about.
+#[allow(clippy::too_many_arguments)]
+fn put_content(
+    alias: &str,
+    key: &str,
+    data: &[u8],
+    mut encrypt: bool,
+    scan: &ScanConfig,
+    backend: &dyn Backend,
+    profile: &str,
+    source: &str,
+) -> Result<(String, bool, Transfer), String> {
+    let content_key = store::content_key(key);
+    let mut transfer = Transfer::Copied;
+    if !encrypt && scan.mode != ScanMode::Off {
+        let findings = scan.unallowed(alias, scan::scan(data));
+        if !findings.is_empty() {
+            let found = scan::describe(&findings);
+            match scan.mode {
+                ScanMode::Block => {
+                    return Err(format!(
+                        "{} looks like it contains secrets: {}. Store it with `confsync add --encrypt` or allow them in [scan.allow]",
+                        alias, found
+                    ));
+                }
+                ScanMode::Encrypt => {
+                    write_log("warn", "SCAN", &format!("Encrypting {}, likely secrets: {}", alias, found), Some(profile.to_string()))?;
+                    encrypt = true;
+                }
+                _ => {
+                    write_log("warn", "SCAN", &format!("{} has likely secrets: {}", alias, found), Some(profile.to_string()))?;
+                    transfer = Transfer::Flagged(found);
+                }
+            }
         }
-
-        dest_file
-            .write_all(&buffer[..bytes_read])
-            .map_err(|e| format!("Failed to write to destination file: {}", e))?;
-
     }
-    // append or create a new file => alias.cmt, to track backup time
-    let cmt_file = repo_path.join(alias).join(format!("{}.cmt", file_name));
-    let mut cmt_file = fs::OpenOptions::new()
-        .append(true)
-        .create(true)
-        .open(cmt_file)
-        .map_err(|e| format!("Failed to open comment file: {}", e))?;
+    let hash = format!("{:x}", Sha256::digest(data));
+    if encrypt {
+        backend.put(&content_key, &crypto::encrypt(data)?)?;
+    } else {
+        backend.put(&content_key, data)?;
+    }
+
+    // history.cmt tracks the backup times of the alias
     let timestamp = chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string();
-    writeln!(
-        cmt_file,
-        "[{}] {}",
-        timestamp,
-        src.display()
-    )
-    .map_err(|e| format!("Failed to write to comment file: {}", e))?;
+    backend.append_line(&store::history_key(key), &format!("[{}] {}", timestamp, source))?;
+    write_log("info", "COPY", &format!("Stored {} as {}", alias, key), Some(profile.to_string()))?;
+    Ok((hash, encrypt, transfer))
+}
 
-    Ok(())
+/// Store an edited template of `alias`, redacted and scanned like a backup of the file
+pub fn store_template(alias: &str, data: &[u8], profile: &str) -> Result<(), String> {
+    let backend = backend::open(profile)?;
+    let config = config::load_config()?;
+    let aliases = store::read_index(&*backend)?;
+    let record = aliases.get(alias).cloned().ok_or_else(|| format!("No backup found for {}", alias))?;
+    let data = match Redaction::of(&config, alias) {
+        Some(redaction) => {
+            let (data, values) = redaction.redact(data)?;
+            redaction.remember(&values)?;
+            data
+        }
+        None => data.to_vec(),
+    };
+    let encrypt = record.encrypted || config.encryption.aliases.contains(alias);
+    let (hash, encrypted, transfer) = put_content(alias, &record.key, &data, encrypt, &config.scan, &*backend, profile, "template edit")?;
+    if let Transfer::Flagged(found) = transfer {
+        printer(format!("{} looks like it contains secrets: {}", alias, found).as_str(), ui::MessageType::Warning);
+    }
+    store::update_index(&*backend, &mut |index| {
+        if let Some(record) = index.aliases.get_mut(alias) {
+            record.hash = Some(hash.clone());
+            record.encrypted = encrypted;
+        }
+        Ok(())
+    })
 }
 
 /// restore file from repo if content is different
 pub fn restore_file(dest:PathBuf,alias:&str,profile: &str, force: bool) -> Result<(), String> {
-    let project_dirs =
-        ProjectDirs::from("", "", "confsync").expect("Failed to get project directories");
-    let repo_path = project_dirs.data_dir().join(profile);
+    let files = [(alias.to_string(), dest)];
+    let results = restore_files(&files, profile, force, &ProgressBar::hidden())?;
+    match results.into_iter().next() {
+        Some(Ok(Transfer::Unchanged)) => {
+            printer("That one is already up to date", ui::MessageType::Success);
+            Ok(())
+        }
+        Some(result) => result.map(|_| ()),
+        None => Ok(()),
+    }
+}
 
-    // extract the file name from the path
-    let file_name = dest
-        .file_name()
-        .ok_or_else(|| "Failed to get file name".to_string())?
-        .to_str()
-        .ok_or_else(|| "Failed to convert file name to string".to_string())?;
+/// Restore `(alias, destination)` pairs concurrently. Results are in input order.
+pub fn restore_files(
+    files: &[(String, PathBuf)],
+    profile: &str,
+    force: bool,
+    progress: &ProgressBar,
+) -> Result<Vec<Result<Transfer, String>>, String> {
+    let backend = backend::open(profile)?;
+    restore_files_from(files, &*backend, profile, force, progress)
+}
 
-    let src = repo_path.join(alias).join(file_name);
-    if !src.exists() {
-        return Err(format!("File {} not found in backup", src.display()));
+/// Restore `(alias, destination)` pairs from any backend,
+/// e.g. another host's backup checked out by `remote::checkout_host`.
+pub fn restore_files_from(
+    files: &[(String, PathBuf)],
+    backend: &dyn Backend,
+    profile: &str,
+    force: bool,
+    progress: &ProgressBar,
+) -> Result<Vec<Result<Transfer, String>>, String> {
+    let aliases = store::read_index(backend)?;
+    let config = config::load_config()?;
+    signing::check(backend.get(signing::MANIFEST_FILE)?.as_deref(), &aliases, &config.signing, "Backups")?;
+    let mut cache = index::load(profile)?;
+    let results = pool::run(
+        files,
+        |(alias, dest)| {
+            let record = aliases
+                .get(alias)
+                .ok_or_else(|| format!("No backup found for {}", alias))?;
+            let redaction = Redaction::of(&config, alias);
+            // secrets restored in the clear must not be readable by others
+            let private = record.encrypted || redaction.is_some() || sensitive::matches(dest, &config)?.is_some();
+            match Template::of(&config, alias)? {
+                Some(template) => render_file(dest, record, &template, redaction, private, backend, force),
+                None => fetch_file(dest, record, redaction, private, backend, force, &cache),
+            }
+        },
+        progress,
+    );
+    progress.finish_and_clear();
+
+    let outcomes = results
+        .into_iter()
+        .map(|result| {
+            result.map(|(transfer, hashed)| {
+                if let Some((path, stat, hash)) = hashed {
+                    cache.update(&path, stat, &hash);
+                }
+                transfer
+            })
+        })
+        .collect();
+    cache.save()?;
+    Ok(outcomes)
+}
+
+/// What restoring each `(alias, destination)` would do, without reading or writing any content:
+/// "new", "up to date", "replace", "render" (templates) or "no backup"
+pub fn restore_plan(files: &[(String, PathBuf)], backend: &dyn Backend, config: &config::Config) -> Result<Vec<&'static str>, String> {
+    let aliases = store::read_index(backend)?;
+    files
+        .iter()
+        .map(|(alias, dest)| {
+            Ok(match aliases.get(alias) {
+                None => "no backup",
+                Some(_) if !dest.exists() => "new",
+                // stored as a template, rendered for this host
+                Some(_) if config.templates.aliases.contains(alias) => "render",
+                Some(record) => {
+                    let stored = match &record.hash {
+                        Some(hash) => hash.clone(),
+                        None => {
+                            let data = backend.get(&store::content_key(&record.key))?.unwrap_or_default();
+                            format!("{:x}", Sha256::digest(&data))
+                        }
+                    };
+                    let current = match Redaction::of(config, alias) {
+                        Some(redaction) => redaction.hash_file(dest)?,
+                        None => index::hash_file(dest)?,
+                    };
+                    if current == stored { "up to date" } else { "replace" }
+                }
+            })
+        })
+        .collect()
+}
+
+/// Copy the stored content of `record` to `dest`, unless `dest` already matches it.
+/// Placeholders of redacted values are filled in.
+fn fetch_file(
+    dest: &Path,
+    record: &store::AliasRecord,
+    redaction: Option<Redaction>,
+    private: bool,
+    backend: &dyn Backend,
+    force: bool,
+    cache: &index::FileIndex,
+) -> Result<(Transfer, Hashed), String> {
+    let current = if !force && dest.exists() {
+        let dest_stat = index::stat(dest)?;
+        let dest_hash = match (redaction, cache.cached(dest, &dest_stat)) {
+            (Some(redaction), _) => redaction.hash_file(dest)?,
+            (None, Some(hash)) => hash.to_string(),
+            (None, None) => index::hash_file(dest)?,
+        };
+        Some((dest_stat, dest_hash))
+    } else {
+        None
+    };
+    // a recorded hash spares downloading content that is already in place
+    if let (Some(stored_hash), Some((_, dest_hash))) = (&record.hash, &current) {
+        if dest_hash == stored_hash {
+            let (dest_stat, dest_hash) = current.expect("checked above");
+            let hashed = redaction.is_none().then(|| (dest.to_path_buf(), dest_stat, dest_hash));
+            return Ok((Transfer::Unchanged, hashed));
+        }
+    }
+
+    let data = read_stored(record, backend)?;
+    if let Some((dest_stat, dest_hash)) = current {
+        if record.hash.is_none() && format!("{:x}", Sha256::digest(&data)) == dest_hash {
+            let hashed = redaction.is_none().then(|| (dest.to_path_buf(), dest_stat, dest_hash));
+            return Ok((Transfer::Unchanged, hashed));
+        }
+    }
+    let data = match redaction {
+        Some(redaction) => redaction.fill(data)?,
+        None => data,
+    };
+    // a new machine may not have the parent directories yet
+    if let Some(parent) = dest.parent() {
+        fs::create_dir_all(parent).map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
     }
-    if !force && compare_files(&dest, &src)? {
-        printer("That one is already up to date", ui::MessageType::Success);
-        return Ok(());
+    write_restored(dest, &data, private)?;
+    Ok((Transfer::Copied, None))
+}
+
+/// Write the output of a template to `dest`, unless `dest` already has it
+fn render_file(
+    dest: &Path,
+    record: &store::AliasRecord,
+    template: &Template,
+    redaction: Option<Redaction>,
+    private: bool,
+    backend: &dyn Backend,
+    force: bool,
+) -> Result<(Transfer, Hashed), String> {
+    let data = template.render(&read_stored(record, backend)?)?;
+    let data = match redaction {
+        Some(redaction) => redaction.fill(data)?,
+        None => data,
+    };
+    if !force && fs::read(dest).is_ok_and(|current| current == data) {
+        return Ok((Transfer::Unchanged, None));
+    }
+    if let Some(parent) = dest.parent() {
+        fs::create_dir_all(parent).map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
+    }
+    write_restored(dest, &data, private)?;
+    Ok((Transfer::Copied, None))
+}
+
+/// Write restored content to `dest`. Private content goes to an owner-only file;
+/// other files keep the mode they have, or get the default one.
+fn write_restored(dest: &Path, data: &[u8], private: bool) -> Result<(), String> {
+    let result = match private {
+        true => perms::write(dest, data),
+        false => fs::write(dest, data),
+    };
+    result.map_err(|e| format!("Failed to copy file: {}", e))
+}
+
+/// Stored content of `record`, decrypted and checked against its recorded hash
+pub fn read_stored(record: &store::AliasRecord, backend: &dyn Backend) -> Result<Vec<u8>, String> {
+    let content_key = store::content_key(&record.key);
+    let data = backend
+        .get(&content_key)?
+        .ok_or_else(|| format!("{} not found in backup", content_key))?;
+    let data = if record.encrypted { crypto::decrypt(&data)? } else { data };
+    // the recorded hash is part of the signed manifest
+    match &record.hash {
+        Some(hash) if *hash != format!("{:x}", Sha256::digest(&data)) => {
+            Err(format!("Stored content of {} does not match its recorded hash", content_key))
+        }
+        // every record of a signed snapshot has one; a missing hash was removed
+        None if backend.get(signing::MANIFEST_FILE)?.is_some() => {
+            Err(format!("{} has no recorded hash, but the repository is signed", content_key))
+        }
+        _ => Ok(data),
     }
-    fs::copy(src, dest).map_err(|e| format!("Failed to copy file: {}", e))?;
-    Ok(())
 }
 
 /// Read the cmt file: timestamp only
 /// return the datetime of the commits in a list of strings
 pub fn read_cmt(alias: &str, profile: &str) -> Result<Vec<String>, String> {
+    let backend = backend::open(profile)?;
+    let aliases = store::read_index(&*backend)?;
+    let record = aliases
+        .get(alias)
+        .ok_or_else(|| format!("No backup found for {}", alias))?;
+
+    let contents = backend
+        .get(&store::history_key(&record.key))?
+        .ok_or_else(|| "Comment file does not exist".to_string())?;
+    let lines: Vec<String> = String::from_utf8_lossy(&contents).lines().map(|line| line.to_string()).collect();
+    Ok(lines)
+}
+
+/// The profile's log file
+pub fn log_path(profile: &str) -> PathBuf {
     let project_dirs =
         ProjectDirs::from("", "", "confsync").expect("Failed to get project directories");
-    let repo_path = project_dirs.data_dir().join(profile);
-
-    let cmt_file = repo_path
-        .join(alias)
-        .read_dir()
-        .map_err(|e| format!("Failed to read directory: {}", e))?
-        .filter_map(|entry| entry.ok())
-        .find(|entry| entry.path().extension().map_or(false, |ext| ext == "cmt"))
-        .map(|entry| entry.path())
-        .ok_or_else(|| "Failed to locate comment file with .cmt extension".to_string())?;
-    if !cmt_file.exists() {
-        return Err("Comment file does not exist".into());
-    }
+    project_dirs.data_dir().join(profile).join("log.txt")
+}
 
-    let mut file = fs::File::open(cmt_file)
-        .map_err(|e| format!("Failed to open comment file: {}", e))?;
+/// End of the log's hash chain: `<records> <hash>`, next to the profile directory so
+/// it is neither committed nor removed with the log
+pub fn log_head_path(profile: &str) -> PathBuf {
+    let project_dirs =
+        ProjectDirs::from("", "", "confsync").expect("Failed to get project directories");
+    project_dirs.data_dir().join(format!("{}.log.head", profile))
+}
 
-    let mut contents = String::new();
-    file.read_to_string(&mut contents)
-        .map_err(|e| format!("Failed to read comment file: {}", e))?;
+/// Taken by every log writer, also in other confsync processes. The head is replaced
+/// on each write, so it cannot be locked itself.
+fn log_lock_path(profile: &str) -> PathBuf {
+    let mut path = log_head_path(profile).into_os_string();
+    path.push(".lock");
+    PathBuf::from(path)
+}
 
-    let lines: Vec<String> = contents.lines().map(|line| line.to_string()).collect();
-    Ok(lines)
+/// Last line of a file, read from its end
+fn last_line(path: &Path) -> Option<String> {
+    let mut file = fs::File::open(path).ok()?;
+    let len = file.metadata().ok()?.len();
+    file.seek(SeekFrom::Start(len.saturating_sub(LOG_TAIL))).ok()?;
+    let mut tail = Vec::new();
+    file.read_to_end(&mut tail).ok()?;
+    String::from_utf8_lossy(&tail).lines().last().map(str::to_string)
 }
 
-/// write to log file
+/// Bytes read from the end of the log to find its last record
+const LOG_TAIL: u64 = 64 * 1024;
+
+/// write to log file; every record carries the hash of the previous one
 pub fn write_log(
     log_type: &str,
     action: &str,
@@ -177,58 +551,85 @@ pub fn write_log(
     profile: Option<String>,
 ) -> Result<(), String> {
     let profile_str = profile.as_deref().unwrap_or("default");
-
-    let project_dirs =
-        ProjectDirs::from("", "", "confsync").expect("Failed to get project directories");
-    let log_path = project_dirs
-        .data_dir()
-        .join(profile_str)
-        .join("log.txt");
+    let log_path = log_path(profile_str);
+    let head_path = log_head_path(profile_str);
 
     if let Some(parent) = log_path.parent() {
-        fs::create_dir_all(parent)
+        perms::create_dir(parent)
             .map_err(|e| format!("Failed to create log directory: {}", e))?;
     }
 
-    let mut file = fs::OpenOptions::new()
-        .append(true)
-        .create(true)
-        .open(log_path)
+    // read head, append, write head: one writer at a time across processes, or the chain forks
+    let lock = perms::append(log_lock_path(profile_str)).map_err(|e| format!("Failed to open log lock: {}", e))?;
+    lock::flock(&lock, true)?;
+    // logs from before chaining have no head yet; their last line starts the chain
+    let head = match fs::read_to_string(&head_path).ok().and_then(|contents| audit::Head::parse(&contents)) {
+        Some(head) => head.catch_up(last_line(&log_path).as_deref()),
+        None => audit::head(&fs::read_to_string(&log_path).unwrap_or_default()),
+    };
+
+    let mut file = perms::append(log_path)
         .map_err(|e| format!("Failed to open log file: {}", e))?;
 
     let timestamp = chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string();
-    writeln!(
-        file,
-        "[{} | {}] {} => {}: {}",
-        timestamp, profile_str, log_type, action, message
-    )
-    .map_err(|e| format!("Failed to write to log file: {}", e))?;
+    let text = format!("[{} | {}] {} => {}: {}", timestamp, profile_str, log_type, action, message);
+    let line = audit::link(&head, &text);
+    writeln!(file, "{}", line).map_err(|e| format!("Failed to write to log file: {}", e))?;
 
-    Ok(())
+    audit::Head { records: head.records + 1, hash: audit::line_hash(&line) }.write(&head_path)
 }
 
 
 /// Save environment variables to a file
 pub fn save_env_vars(profile: &str) -> Result<(), String> {
-    let project_dirs =
-        ProjectDirs::from("", "", "confsync").expect("Failed to get project directories");
-    let env_path = project_dirs.data_dir().join(profile).join("env_vars.txt");
-
-    if let Some(parent) = env_path.parent() {
-        fs::create_dir_all(parent)
-            .map_err(|e| format!("Failed to create env directory: {}", e))?;
+    let scan = config::load_config()?.scan;
+    let mut lines = Vec::new();
+    let mut secrets = Vec::new();
+    for (key, value) in std::env::vars() {
+        let line = format!("{}={}", key, value);
+        let secret = scan.mode != ScanMode::Off && !scan.unallowed("env", scan::scan(line.as_bytes())).is_empty();
+        if secret {
+            secrets.push(key);
+        }
+        // variables holding likely secrets are left out unless scanning only warns
+        if !secret || scan.mode == ScanMode::Warn {
+            lines.push(line);
+        }
+    }
+    if !secrets.is_empty() {
+        let action = if scan.mode == ScanMode::Warn { "Saved" } else { "Left out" };
+        write_log("warn", "SCAN", &format!("{} environment variables with likely secrets: {}", action, secrets.join(", ")), Some(profile.to_string()))?;
+        printer(format!("{} environment variables with likely secrets: {}", action, secrets.join(", ")).as_str(), ui::MessageType::Warning);
     }
+    // through the backend, so passphrase-encrypted repositories store them encrypted too
+    backend::open(profile)?.append_line("env_vars.txt", &lines.join("\n"))
+}
 
-    let mut file = fs::OpenOptions::new()
-        .append(true)
-        .create(true)
-        .open(env_path)
-        .map_err(|e| format!("Failed to open env file: {}", e))?;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
 
-    for (key, value) in std::env::vars() {
-        writeln!(file, "{}={}", key, value)
-            .map_err(|e| format!("Failed to write to env file: {}", e))?;
+    fn mode(path: &Path) -> u32 {
+        fs::metadata(path).unwrap().permissions().mode() & 0o777
     }
 
-    Ok(())
+    #[test]
+    fn private_content_is_restored_owner_only() {
+        let dir = std::env::temp_dir().join(format!("confsync-restore-mode-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let (private, public) = (dir.join("credentials"), dir.join("zshrc"));
+        for path in [&private, &public] {
+            fs::write(path, "old").unwrap();
+            fs::set_permissions(path, fs::Permissions::from_mode(0o644)).unwrap();
+        }
+
+        write_restored(&private, b"token = secret", true).unwrap();
+        write_restored(&public, b"alias ll='ls -l'", false).unwrap();
+        assert_eq!(mode(&private), 0o600);
+        assert_eq!(mode(&public), 0o644);
+        assert_eq!(fs::read(&private).unwrap(), b"token = secret");
+        let _ = fs::remove_dir_all(&dir);
+    }
 }
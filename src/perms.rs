@@ -0,0 +1,103 @@
+use std::fs::{self, DirBuilder, File, OpenOptions};
+use std::io::{self, ErrorKind, Write};
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+/// Directories in the data and cache directories: owner only
+pub const DIR_MODE: u32 = 0o700;
+/// Files in them (stored blobs, env snapshots, logs) and key files: owner read/write only
+pub const FILE_MODE: u32 = 0o600;
+
+/// `fs::create_dir_all`, with every directory it creates owner-only
+pub fn create_dir(path: impl AsRef<Path>) -> io::Result<()> {
+    DirBuilder::new().recursive(true).mode(DIR_MODE).create(path)
+}
+
+/// `fs::write` to an owner-only file; an existing file is tightened first
+pub fn write(path: impl AsRef<Path>, data: impl AsRef<[u8]>) -> io::Result<()> {
+    let mut file = OpenOptions::new().write(true).create(true).truncate(true).mode(FILE_MODE).open(path)?;
+    tighten(&file)?;
+    file.write_all(data.as_ref())
+}
+
+/// Create a new owner-only file; fails if it exists
+pub fn create_new(path: impl AsRef<Path>) -> io::Result<File> {
+    OpenOptions::new().write(true).create_new(true).mode(FILE_MODE).open(path)
+}
+
+/// Open a file for appending, as `write` would create it
+pub fn append(path: impl AsRef<Path>) -> io::Result<File> {
+    let file = OpenOptions::new().append(true).create(true).mode(FILE_MODE).open(path)?;
+    tighten(&file)?;
+    Ok(file)
+}
+
+fn tighten(file: &File) -> io::Result<()> {
+    let mode = file.metadata()?.permissions().mode();
+    if mode & 0o077 != 0 {
+        file.set_permissions(fs::Permissions::from_mode(mode & 0o700))?;
+    }
+    Ok(())
+}
+
+/// A file or directory that group or others can access
+pub struct Loose {
+    pub path: PathBuf,
+    pub mode: u32,
+    pub wanted: u32,
+}
+
+impl Loose {
+    pub fn fix(&self) -> Result<(), String> {
+        fs::set_permissions(&self.path, fs::Permissions::from_mode(self.wanted))
+            .map_err(|e| format!("Failed to change permissions of {}: {}", self.path.display(), e))
+    }
+}
+
+/// `path` itself, if it is looser than it should be
+pub fn check(path: &Path) -> Result<Option<Loose>, String> {
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(format!("Failed to read {}: {}", path.display(), e)),
+    };
+    if metadata.file_type().is_symlink() {
+        return Ok(None);
+    }
+    let mode = metadata.permissions().mode() & 0o7777;
+    if mode & 0o077 == 0 {
+        return Ok(None);
+    }
+    let wanted = if metadata.is_dir() { DIR_MODE } else { (mode & 0o700) | FILE_MODE };
+    Ok(Some(Loose { path: path.to_path_buf(), mode, wanted }))
+}
+
+/// `root` and everything below it that is looser than it should be. The insides of
+/// `.git` are left to git; the owner-only repository directory already guards them.
+pub fn scan(root: &Path) -> Result<Vec<Loose>, String> {
+    let mut loose = Vec::new();
+    loose.extend(check(root)?);
+    if root.is_dir() && !root.is_symlink() {
+        walk(root, &mut loose)?;
+    }
+    Ok(loose)
+}
+
+fn walk(dir: &Path, loose: &mut Vec<Loose>) -> Result<(), String> {
+    let entries = fs::read_dir(dir).map_err(|e| format!("Failed to list {}: {}", dir.display(), e))?;
+    for entry in entries {
+        let entry = entry.map_err(|e| format!("Failed to list {}: {}", dir.display(), e))?;
+        let path = entry.path();
+        loose.extend(check(&path)?);
+        let file_type = entry.file_type().map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        if file_type.is_dir() && entry.file_name() != ".git" {
+            walk(&path, loose)?;
+        }
+    }
+    Ok(())
+}
+
+/// Tighten everything below `root`, e.g. a working tree git has just written
+pub fn restrict(root: &Path) -> Result<(), String> {
+    scan(root)?.iter().try_for_each(Loose::fix)
+}
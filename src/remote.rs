@@ -0,0 +1,568 @@
+use directories::ProjectDirs;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::backend;
+use crate::config::{self, load_config};
+use crate::git::{self, BRANCH};
+use crate::merge::{self, MergeReport, Resolution};
+use crate::ops::write_log;
+use crate::{perms, signing, store, vault};
+
+/// Name of the git remote pointing at `storage.repo_url`
+pub const REMOTE: &str = "origin";
+
+/// Every host pushes its `main` to its own branch `hosts/<host>` on the remote
+const HOSTS_PREFIX: &str = "hosts/";
+
+/// Branch on the remote holding the backups of `host`
+pub fn host_branch(host: &str) -> String {
+    format!("{}{}", HOSTS_PREFIX, host)
+}
+
+/// Remote-tracking ref of `host`'s branch after a fetch
+fn host_ref(host: &str) -> String {
+    format!("{}/{}", REMOTE, host_branch(host))
+}
+
+pub fn repo_path(profile: &str) -> Result<PathBuf, String> {
+    backend::require_local(profile, "Syncing with git remotes")?;
+    let project_dirs = ProjectDirs::from("", "", "confsync")
+        .ok_or_else(|| "Failed to find config directory".to_string())?;
+    let repo_path = project_dirs.data_dir().join(profile);
+    if !git::is_repo(&repo_path) {
+        return Err("Repository does not exist. Please run `confsync init`.".into());
+    }
+    Ok(repo_path)
+}
+
+/// Configured remote URL of the backups
+pub fn remote_url() -> Result<String, String> {
+    let config = load_config()?;
+    if config.storage.repo_url.is_empty() {
+        return Err("No remote configured. Run `confsync init <REMOTE> --force` to set one.".into());
+    }
+    Ok(config.storage.repo_url)
+}
+
+/// Local directory behind a remote URL, if it is one (`/path`, `./path`, `file:///path`)
+pub fn local_path(url: &str) -> Option<PathBuf> {
+    let path = url.strip_prefix("file://").unwrap_or(url);
+    if path.starts_with('/') || path.starts_with("./") || path.starts_with("../") {
+        Some(PathBuf::from(path))
+    } else {
+        None
+    }
+}
+
+/// Point the repository's remote at the configured URL.
+/// A local directory that is empty, or missing inside an existing directory, becomes a bare repository.
+/// One whose parent is missing too (an unmounted drive) is left alone so pushes fail and get queued.
+fn connect(repo_path: &Path) -> Result<String, String> {
+    let url = remote_url()?;
+    connect_to(repo_path, REMOTE, &url)?;
+    Ok(url)
+}
+
+/// Point the git remote `name` at `url`, creating local directories as `connect` does
+pub fn connect_to(repo_path: &Path, name: &str, url: &str) -> Result<(), String> {
+    if let Some(dir) = local_path(url) {
+        let empty = match fs::read_dir(&dir) {
+            Ok(mut entries) => entries.next().is_none(),
+            Err(_) => dir.parent().is_some_and(|parent| parent.is_dir()),
+        };
+        if empty {
+            fs::create_dir_all(&dir)
+                .map_err(|e| format!("Failed to create remote directory: {}", e))?;
+            git::git(&dir, &["init", "--bare", "--quiet"])?;
+            git::git(&dir, &["symbolic-ref", "HEAD", &format!("refs/heads/{}", BRANCH)])?;
+        }
+    }
+    git::set_remote(repo_path, name, url)
+}
+
+/// Queued push waiting for the remote, kept inside `.git` so it is never committed
+const PUSH_QUEUE: &str = "confsync-push-queue.toml";
+
+/// A push that failed because the remote was unreachable
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PushQueue {
+    /// when the first failed push was queued
+    pub queued: String,
+    /// the queued push overwrites the remote
+    pub force: bool,
+    pub attempts: u32,
+    pub last_error: String,
+}
+
+/// Outcome of a push
+#[derive(Debug)]
+pub enum Push {
+    Pushed,
+    /// the remote was unreachable; the push is queued with this error
+    Queued(String),
+}
+
+fn queue_path(repo_path: &Path) -> PathBuf {
+    repo_path.join(".git").join(PUSH_QUEUE)
+}
+
+/// The queued push of the profile, if any
+pub fn queued(profile: &str) -> Result<Option<PushQueue>, String> {
+    let path = queue_path(&repo_path(profile)?);
+    if !path.exists() {
+        return Ok(None);
+    }
+    let contents = fs::read_to_string(&path).map_err(|e| format!("Failed to read push queue: {}", e))?;
+    toml::from_str(&contents).map(Some).map_err(|e| format!("Failed to parse push queue: {}", e))
+}
+
+fn save_queue(repo_path: &Path, queue: &PushQueue) -> Result<(), String> {
+    let contents = toml::to_string_pretty(queue).map_err(|e| format!("Failed to serialize push queue: {}", e))?;
+    perms::write(queue_path(repo_path), contents).map_err(|e| format!("Failed to write push queue: {}", e))
+}
+
+/// Push the backup branch to the remote.
+/// A diverged remote is only overwritten when `force` is set.
+/// When the remote cannot be reached the push is queued for `retry_queued`.
+pub fn push(profile: &str, force: bool) -> Result<Push, String> {
+    let repo_path = repo_path(profile)?;
+    let previous = queued(profile)?;
+    // a queued force push stays forced
+    let force = force || previous.as_ref().is_some_and(|queue| queue.force);
+    match push_now(profile, &repo_path, force) {
+        Ok(()) => {
+            if previous.is_some() {
+                fs::remove_file(queue_path(&repo_path))
+                    .map_err(|e| format!("Failed to clear push queue: {}", e))?;
+            }
+            Ok(Push::Pushed)
+        }
+        Err((true, e)) => Err(e),
+        Err((false, e)) => {
+            let e = e.lines().next().unwrap_or_default().to_string();
+            let queue = match previous {
+                Some(queue) => PushQueue { force, attempts: queue.attempts + 1, last_error: e.clone(), ..queue },
+                None => PushQueue {
+                    queued: chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+                    force,
+                    attempts: 1,
+                    last_error: e.clone(),
+                },
+            };
+            save_queue(&repo_path, &queue)?;
+            write_log("warn", "PUSH", &format!("Remote unreachable, push queued: {}", e), Some(profile.to_string()))?;
+            Ok(Push::Queued(e))
+        }
+    }
+}
+
+/// Push a queued backup if there is one.
+/// Returns the number of snapshots pushed, or None if nothing was queued or the remote is still unreachable.
+pub fn retry_queued(profile: &str) -> Result<Option<usize>, String> {
+    if queued(profile)?.is_none() {
+        return Ok(None);
+    }
+    let pending = unpushed(profile)?;
+    match push(profile, false)? {
+        Push::Pushed => Ok(Some(pending)),
+        Push::Queued(_) => Ok(None),
+    }
+}
+
+/// Push, reporting whether the remote was reachable along with any error
+fn push_now(profile: &str, repo_path: &Path, force: bool) -> Result<(), (bool, String)> {
+    let url = connect(repo_path).map_err(|e| (false, e))?;
+    let branch = host_branch(&config::host_name().map_err(|e| (true, e))?);
+    let refspec = format!("{}:refs/heads/{}", BRANCH, branch);
+    let args: &[&str] = if force {
+        &["push", "--quiet", "--force", REMOTE, &refspec]
+    } else {
+        &["push", "--quiet", REMOTE, &refspec]
+    };
+
+    if let Err(e) = git::git(repo_path, args) {
+        // a remote that cannot even be fetched from is unreachable
+        git::git(repo_path, &["fetch", "--quiet", REMOTE]).map_err(|_| (false, e.clone()))?;
+        if !force && diverged(repo_path, &format!("{}/{}", REMOTE, branch)) {
+            return Err((
+                true,
+                "Remote has backups this machine does not have. Run `confsync pull` to resolve them, \
+                 or `confsync backup --force` to overwrite the remote."
+                    .into(),
+            ));
+        }
+        return Err((true, e));
+    }
+    // keep the remote-tracking ref current so unpushed snapshots can be counted offline
+    let _ = git::git(repo_path, &["fetch", "--quiet", REMOTE, &branch]);
+    let (level, verb) = if force { ("warn", "Force pushed") } else { ("info", "Pushed") };
+    write_log(level, "PUSH", &format!("{} {} to {}", verb, branch, url), Some(profile.to_string()))
+        .map_err(|e| (true, e))?;
+    Ok(())
+}
+
+/// Snapshots on the local branch this host has not pushed, as of the last fetch
+pub fn unpushed(profile: &str) -> Result<usize, String> {
+    let repo_path = repo_path(profile)?;
+    if git::rev_parse(&repo_path, "HEAD").is_none() {
+        return Ok(0);
+    }
+    let remote_ref = host_ref(&config::host_name()?);
+    let range = match git::rev_parse(&repo_path, &remote_ref) {
+        Some(_) => format!("{}..HEAD", remote_ref),
+        None => "HEAD".to_string(),
+    };
+    git::git(&repo_path, &["rev-list", "--count", &range])?
+        .parse()
+        .map_err(|e| format!("Failed to count unpushed snapshots: {}", e))
+}
+
+/// True if the fetched remote branch has commits the local branch does not contain
+fn diverged(repo_path: &Path, remote_ref: &str) -> bool {
+    git::rev_parse(repo_path, remote_ref).is_some() && !git::is_ancestor(repo_path, remote_ref, "HEAD")
+}
+
+/// Result of a pull
+#[derive(Debug, Default)]
+pub struct PullReport {
+    /// commits fetched from the remote
+    pub commits: usize,
+    /// set when local and remote had diverged and were merged
+    pub merge: Option<MergeReport>,
+}
+
+/// Fetch this host's backups, or those `host` pushed, from the remote into the local branch.
+/// Fast-forwards when possible; diverged histories are merged alias by alias,
+/// asking `choose` for every alias changed on both machines.
+pub fn pull(
+    profile: &str,
+    host: Option<&str>,
+    choose: &mut dyn FnMut(&str) -> Result<Resolution, String>,
+) -> Result<PullReport, String> {
+    let repo_path = repo_path(profile)?;
+    let url = connect(&repo_path)?;
+    git::git(&repo_path, &["fetch", "--quiet", REMOTE])?;
+
+    let remote_ref = match host {
+        Some(host) => host_ref(host),
+        None => host_ref(&config::host_name()?),
+    };
+    if git::rev_parse(&repo_path, &remote_ref).is_none() {
+        return match host {
+            Some(host) => Err(format!("No backups of {} on the remote; see `confsync hosts`", host)),
+            // nothing pushed yet
+            None => Ok(PullReport::default()),
+        };
+    }
+    let commits: usize = git::git(&repo_path, &["rev-list", "--count", &format!("HEAD..{}", remote_ref)])?
+        .parse()
+        .map_err(|e| format!("Failed to count new commits: {}", e))?;
+    if commits == 0 {
+        return Ok(PullReport::default());
+    }
+    // nothing is taken from the remote unless its signature checks out
+    verify_ref(&repo_path, &remote_ref, "Remote backups")?;
+
+    let merge = if git::is_ancestor(&repo_path, "HEAD", &remote_ref) {
+        git::git_as_user(&repo_path, &["merge", "--ff-only", "--quiet", &remote_ref])?;
+        None
+    } else {
+        let report = merge::merge_remote(&repo_path, &remote_ref, choose)?;
+        write_log(
+            "info",
+            "PULL",
+            &format!(
+                "Merged diverged backups: {} taken, {} resolved",
+                report.taken.len(),
+                report.conflicts.len()
+            ),
+            Some(profile.to_string()),
+        )?;
+        Some(report)
+    };
+    perms::restrict(&repo_path)?;
+    write_log("info", "PULL", &format!("Pulled {} commits from {}", commits, url), Some(profile.to_string()))?;
+    Ok(PullReport { commits, merge })
+}
+
+/// Check the signed manifest of the snapshot at `rev` against the trust list
+fn verify_ref(repo_path: &Path, rev: &str, what: &str) -> Result<(), String> {
+    let index = match git::show(repo_path, rev, store::ALIASES_FILE)? {
+        Some(contents) => store::parse_index(&String::from_utf8_lossy(&vault::decode_at(repo_path, store::ALIASES_FILE, contents)?))?,
+        None => store::AliasIndex::default(),
+    };
+    let manifest = git::show(repo_path, rev, signing::MANIFEST_FILE)?
+        .map(|contents| vault::decode_at(repo_path, signing::MANIFEST_FILE, contents))
+        .transpose()?;
+    signing::check(manifest.as_deref(), &index, &load_config()?.signing, what)?;
+    Ok(())
+}
+
+/// What `delete remote` would remove
+#[derive(Debug)]
+pub struct RemoteBranch {
+    pub url: String,
+    pub branch: String,
+    /// last commit on the remote branch (`<hash> <subject>`)
+    pub head: String,
+    pub commits: usize,
+}
+
+/// Describe this host's backup branch on the remote, or None if nothing was pushed
+pub fn remote_branch(profile: &str) -> Result<Option<RemoteBranch>, String> {
+    let repo_path = repo_path(profile)?;
+    let url = connect(&repo_path)?;
+    git::git(&repo_path, &["fetch", "--quiet", "--prune", REMOTE])?;
+
+    let branch = host_branch(&config::host_name()?);
+    let remote_ref = format!("{}/{}", REMOTE, branch);
+    if git::rev_parse(&repo_path, &remote_ref).is_none() {
+        return Ok(None);
+    }
+    let head = git::git(&repo_path, &["log", "-1", "--format=%h %s", &remote_ref])?;
+    let commits = git::git(&repo_path, &["rev-list", "--count", &remote_ref])?
+        .parse()
+        .map_err(|e| format!("Failed to count remote commits: {}", e))?;
+    Ok(Some(RemoteBranch { url, branch, head, commits }))
+}
+
+/// Delete this host's backup branch on the remote. The local repository is not touched.
+/// Remotes that refuse to delete their default branch get an empty commit
+/// replacing its history instead. Returns true if the branch was emptied rather than deleted.
+pub fn delete_branch(profile: &str) -> Result<bool, String> {
+    let repo_path = repo_path(profile)?;
+    let url = connect(&repo_path)?;
+    let branch = host_branch(&config::host_name()?);
+    if git::git(&repo_path, &["push", "--quiet", REMOTE, "--delete", &branch]).is_ok() {
+        write_log("warn", "DELETE", &format!("Deleted branch {} on {}", branch, url), Some(profile.to_string()))?;
+        return Ok(false);
+    }
+
+    let empty_tree = git::git(&repo_path, &["hash-object", "-t", "tree", "/dev/null"])?;
+    let empty = git::git_as_user(&repo_path, &["commit-tree", &empty_tree, "-m", "Delete backups"])?;
+    git::git(&repo_path, &["push", "--quiet", "--force", REMOTE, &format!("{}:refs/heads/{}", empty, branch)])?;
+    // forget the old remote state so the next push is not reported as diverged
+    let _ = git::git(&repo_path, &["fetch", "--quiet", REMOTE]);
+    write_log("warn", "DELETE", &format!("Emptied branch {} on {}", branch, url), Some(profile.to_string()))?;
+    Ok(true)
+}
+
+/// A host with backups on the remote
+#[derive(Debug)]
+pub struct Host {
+    pub name: String,
+    /// last backup of the host (`<hash> <date>`)
+    pub head: String,
+}
+
+/// Hosts that pushed backups to the remote, sorted by name
+pub fn hosts(profile: &str) -> Result<Vec<Host>, String> {
+    let repo_path = repo_path(profile)?;
+    connect(&repo_path)?;
+    git::git(&repo_path, &["fetch", "--quiet", "--prune", REMOTE])?;
+    fetched_hosts(&repo_path)
+}
+
+/// Hosts among the remote-tracking refs of the last fetch
+fn fetched_hosts(repo_path: &Path) -> Result<Vec<Host>, String> {
+    let prefix = format!("refs/remotes/{}/{}", REMOTE, HOSTS_PREFIX);
+    let refs = git::git(
+        repo_path,
+        &["for-each-ref", "--format=%(refname) %(objectname:short) %(committerdate:format:%Y-%m-%d %H:%M:%S)", &prefix],
+    )?;
+    Ok(refs
+        .lines()
+        .filter_map(|line| {
+            let (refname, head) = line.split_once(' ')?;
+            let name = refname.strip_prefix(&prefix)?.to_string();
+            Some(Host { name, head: head.to_string() })
+        })
+        .collect())
+}
+
+/// Create a repository at `repo_path` from the backups a host pushed to `url`.
+/// Without `host`, this machine's backups are used, or those of the only host on the remote.
+/// Returns the host the backups came from.
+pub fn clone(repo_path: &Path, url: &str, host: Option<&str>) -> Result<String, String> {
+    if git::is_repo(repo_path) {
+        return Err(format!(
+            "Repository {} already exists. Remove it with `confsync delete local --force` first.",
+            repo_path.display()
+        ));
+    }
+    perms::create_dir(repo_path).map_err(|e| format!("Failed to create repository: {}", e))?;
+
+    let result = clone_into(repo_path, url, host);
+    if result.is_err() {
+        // leave nothing behind that would make the next attempt refuse
+        let _ = fs::remove_dir_all(repo_path.join(".git"));
+        let _ = fs::remove_file(repo_path.join(".gitignore"));
+    }
+    result
+}
+
+fn clone_into(repo_path: &Path, url: &str, host: Option<&str>) -> Result<String, String> {
+    git::init(repo_path)?;
+    git::set_remote(repo_path, REMOTE, url)?;
+    git::git(repo_path, &["fetch", "--quiet", REMOTE])?;
+
+    let hosts: Vec<String> = fetched_hosts(repo_path)?.into_iter().map(|h| h.name).collect();
+    if hosts.is_empty() {
+        return Err(format!("{} has no confsync backups", url));
+    }
+    let host = match host {
+        Some(host) if hosts.iter().any(|h| h == host) => host.to_string(),
+        Some(host) => return Err(format!("No backups from host {}. Hosts: {}", host, hosts.join(", "))),
+        None => {
+            let current = config::host_name()?;
+            if hosts.contains(&current) {
+                current
+            } else if hosts.len() == 1 {
+                hosts[0].clone()
+            } else {
+                return Err(format!("Remote has backups of several hosts ({}). Choose one with --host.", hosts.join(", ")));
+            }
+        }
+    };
+    git::git(repo_path, &["reset", "--quiet", "--hard", &host_ref(&host)])?;
+    // git writes the working tree with the default umask
+    perms::restrict(repo_path)?;
+    Ok(host)
+}
+
+/// Write the latest backup of another host into a cache directory laid out like the
+/// repository (aliases.toml and store/), so it can be restored from like a local backup.
+pub fn checkout_host(profile: &str, host: &str) -> Result<PathBuf, String> {
+    let repo_path = repo_path(profile)?;
+    connect(&repo_path)?;
+    git::git(&repo_path, &["fetch", "--quiet", REMOTE])?;
+
+    let remote_ref = host_ref(host);
+    if git::rev_parse(&repo_path, &remote_ref).is_none() {
+        return Err(format!("No backups from host {}. See `confsync hosts`.", host));
+    }
+    let project_dirs = ProjectDirs::from("", "", "confsync")
+        .ok_or_else(|| "Failed to find cache directory".to_string())?;
+    let dir = project_dirs.cache_dir().join(profile).join("hosts").join(host);
+    if dir.exists() {
+        fs::remove_dir_all(&dir).map_err(|e| format!("Failed to clear {}: {}", dir.display(), e))?;
+    }
+
+    let aliases = git::show(&repo_path, &remote_ref, store::ALIASES_FILE)?
+        .ok_or_else(|| format!("Host {} has no backups", host))?;
+    let index = store::parse_index(&String::from_utf8_lossy(&vault::decode_at(&repo_path, store::ALIASES_FILE, aliases.clone())?))?;
+    perms::create_dir(&dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+    perms::write(dir.join(store::ALIASES_FILE), &aliases)
+        .map_err(|e| format!("Failed to write {}: {}", store::ALIASES_FILE, e))?;
+    for file in [vault::VAULT_FILE, signing::MANIFEST_FILE] {
+        if let Some(contents) = git::show(&repo_path, &remote_ref, file)? {
+            perms::write(dir.join(file), contents).map_err(|e| format!("Failed to write {}: {}", file, e))?;
+        }
+    }
+    for record in index.aliases.values() {
+        let path = format!("{}/{}/{}", store::STORE_DIR, record.key, store::CONTENT_FILE);
+        if let Some(content) = git::show(&repo_path, &remote_ref, &path)? {
+            let key_dir = store::key_dir(&dir, &record.key);
+            perms::create_dir(&key_dir).map_err(|e| format!("Failed to create directory: {}", e))?;
+            perms::write(store::content_path(&dir, &record.key), content)
+                .map_err(|e| format!("Failed to write {}: {}", path, e))?;
+        }
+    }
+    Ok(dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::Config, repo};
+    use std::sync::{Mutex, MutexGuard};
+
+    /// Tests that point the config and data directories at their own sandbox run one at a time
+    static SANDBOX: Mutex<()> = Mutex::new(());
+
+    /// Make `dir` the home of this test, with a config pushing to `repo_url` as host `laptop`
+    fn sandbox(dir: &Path, repo_url: &Path) -> MutexGuard<'static, ()> {
+        let guard = SANDBOX.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let _ = fs::remove_dir_all(dir);
+        std::env::set_var("HOME", dir);
+        std::env::set_var("XDG_CONFIG_HOME", dir.join("config"));
+        std::env::set_var("XDG_DATA_HOME", dir.join("data"));
+        // other tests may have requests in flight there while this directory is removed
+        std::env::set_var("XDG_CACHE_HOME", std::env::temp_dir().join("confsync-test-cache"));
+        let mut config = Config::default();
+        config.storage.repo_url = repo_url.display().to_string();
+        config.storage.host = Some("laptop".into());
+        config::save_config(&config).unwrap();
+        guard
+    }
+
+    fn commit_file(repo_path: &Path, name: &str, contents: &str) {
+        fs::write(repo_path.join(name), contents).unwrap();
+        git::commit_all(repo_path, &format!("Backup: {}", name)).unwrap();
+    }
+
+    #[test]
+    fn push_and_pull_go_through_this_hosts_branch() {
+        let dir = std::env::temp_dir().join(format!("confsync-remote-sync-{}", std::process::id()));
+        let remote = dir.join("remote.git");
+        let _sandbox = sandbox(&dir, &remote);
+        let profile = "sync";
+        let repo_path = repo::init_repo(profile).unwrap();
+        commit_file(&repo_path, "one", "1");
+
+        assert!(matches!(push(profile, false).unwrap(), Push::Pushed));
+        let branch = format!("refs/heads/{}", host_branch("laptop"));
+        assert_eq!(git::git(&remote, &["rev-parse", &branch]).unwrap(), git::git(&repo_path, &["rev-parse", "HEAD"]).unwrap());
+        assert_eq!(unpushed(profile).unwrap(), 0);
+        assert_eq!(pull(profile, None, &mut |_| Ok(Resolution::Mine)).unwrap().commits, 0);
+
+        // a newer backup of this host pushed from elsewhere is fast-forwarded
+        git::git(&dir, &["clone", "--quiet", "--branch", &host_branch("laptop"), remote.to_str().unwrap(), "other"]).unwrap();
+        let other = dir.join("other");
+        commit_file(&other, "two", "2");
+        git::git(&other, &["push", "--quiet", REMOTE, &format!("HEAD:{}", branch)]).unwrap();
+        let report = pull(profile, None, &mut |_| Ok(Resolution::Mine)).unwrap();
+        assert_eq!(report.commits, 1);
+        assert!(report.merge.is_none());
+        assert_eq!(fs::read_to_string(repo_path.join("two")).unwrap(), "2");
+
+        // pushing over backups this machine does not have needs --force
+        commit_file(&other, "three", "3");
+        git::git(&other, &["push", "--quiet", REMOTE, &format!("HEAD:{}", branch)]).unwrap();
+        commit_file(&repo_path, "four", "4");
+        assert!(push(profile, false).unwrap_err().contains("confsync pull"));
+        assert!(queued(profile).unwrap().is_none());
+        assert!(matches!(push(profile, true).unwrap(), Push::Pushed));
+
+        assert!(pull(profile, Some("desktop"), &mut |_| Ok(Resolution::Mine)).unwrap_err().contains("desktop"));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn queued_push_is_retried_and_cleared() {
+        let dir = std::env::temp_dir().join(format!("confsync-remote-queue-{}", std::process::id()));
+        // a remote on a drive that is not mounted yet
+        let drive = dir.join("drive");
+        let _sandbox = sandbox(&dir, &drive.join("remote.git"));
+        let profile = "queue";
+        let repo_path = repo::init_repo(profile).unwrap();
+        commit_file(&repo_path, "one", "1");
+        assert!(matches!(push(profile, false).unwrap(), Push::Queued(_)));
+        commit_file(&repo_path, "two", "2");
+        assert!(matches!(push(profile, false).unwrap(), Push::Queued(_)));
+        assert_eq!(queued(profile).unwrap().unwrap().attempts, 2);
+        assert_eq!(retry_queued(profile).unwrap(), None);
+        assert_eq!(queued(profile).unwrap().unwrap().attempts, 3);
+
+        fs::create_dir_all(&drive).unwrap();
+        let pending = unpushed(profile).unwrap();
+        assert_eq!(retry_queued(profile).unwrap(), Some(pending));
+        assert!(queued(profile).unwrap().is_none());
+        assert_eq!(unpushed(profile).unwrap(), 0);
+        // nothing queued, nothing to retry
+        assert_eq!(retry_queued(profile).unwrap(), None);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
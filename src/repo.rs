@@ -1,44 +1,53 @@
 use chrono::Local;
 use directories::ProjectDirs;
 use std::fs;
-use std::io::Write;
 use std::path::PathBuf;
 
-use crate::ops::write_log;
+use crate::{backend, format, git, perms, signing, vault};
+use crate::ops::{log_head_path, write_log};
 
 /// Initialize a new repository directory for the given profile.
-/// Simply creates the directory if it does not exist.
+/// Creates the directory as a git repository and stamps new ones with the format version.
 pub fn init_repo(profile: &str) -> Result<PathBuf, String> {
     let project_dirs = ProjectDirs::from("", "", "confsync")
         .ok_or_else(|| "Failed to find config directory".to_string())?;
     let repo_path = project_dirs.data_dir().join(profile);
-    fs::create_dir_all(&repo_path)
+    perms::create_dir(&repo_path)
         .map_err(|e| format!("Failed to create repository: {}", e))?;
+    // directories made before permissions were enforced are tightened too
+    for dir in [project_dirs.data_dir(), &repo_path] {
+        if let Some(loose) = perms::check(dir)? {
+            loose.fix()?;
+        }
+    }
+    // other backends keep only logs and locks in the data directory
+    if backend::is_local(profile)? {
+        format::stamp_new_repo(&repo_path)?;
+        git::init(&repo_path)?;
+    }
     write_log("info", "INIT", "Initialized repository", Some(profile.to_string()))?;
     Ok(repo_path)
 }
 
-/// Record a commit message. A simple history.log file is used.
+/// Record a backup: the snapshot is signed, the message and signer go to the history index
+/// and, for the local backend, everything is committed to git.
 pub fn commit(profile: &str, message: &str) -> Result<(), String> {
-    let project_dirs = ProjectDirs::from("", "", "confsync")
-        .ok_or_else(|| "Failed to find config directory".to_string())?;
-    let repo_path = project_dirs.data_dir().join(profile);
-    if !repo_path.exists() {
+    let backend = backend::open(profile)?;
+    if backend.local_root().is_some_and(|root| !root.exists()) {
         return Err("Repository does not exist".into());
     }
-    let log_file = repo_path.join("history.log");
-    let mut file = fs::OpenOptions::new()
-        .append(true)
-        .create(true)
-        .open(&log_file)
-        .map_err(|e| format!("Failed to open history log: {}", e))?;
     let timestamp = Local::now().format("%Y-%m-%d %H:%M:%S");
-    writeln!(file, "[{}] {}", timestamp, message)
-        .map_err(|e| format!("Failed to write history: {}", e))?;
+    backend.record_history(&format!("[{}] {} (signed by {})", timestamp, message, signing::signer()?))?;
+    signing::sign(&*backend)?;
+    let commit = match backend.local_root() {
+        // commit messages are not encrypted; history.log keeps the real one
+        Some(root) => git::commit_all(root, vault::commit_message(root, message))?,
+        None => None,
+    };
     write_log(
         "info",
         "COMMIT",
-        &format!("Message recorded: {}", message),
+        &format!("Message recorded: {} ({})", message, commit.as_deref().unwrap_or("no commit")),
         Some(profile.to_string()),
     )?;
     Ok(())
@@ -53,20 +62,14 @@ pub fn delete_repo(profile: &str) -> Result<(), String> {
         fs::remove_dir_all(&repo_path)
             .map_err(|e| format!("Failed to delete repository: {}", e))?;
     }
+    // the log went with the repository; a new one starts a new chain
+    let _ = fs::remove_file(log_head_path(profile));
     write_log("info", "DELETE", "Repository deleted", Some(profile.to_string()))?;
     Ok(())
 }
 
 /// Read commit messages from history log.
+#[allow(dead_code)]
 pub fn list_history(profile: &str) -> Result<Vec<String>, String> {
-    let project_dirs = ProjectDirs::from("", "", "confsync")
-        .ok_or_else(|| "Failed to find config directory".to_string())?;
-    let repo_path = project_dirs.data_dir().join(profile);
-    let log_file = repo_path.join("history.log");
-    if !log_file.exists() {
-        return Ok(Vec::new());
-    }
-    let content = fs::read_to_string(&log_file)
-        .map_err(|e| format!("Failed to read history log: {}", e))?;
-    Ok(content.lines().map(|s| s.to_string()).collect())
+    backend::open(profile)?.history()
 }
@@ -0,0 +1,166 @@
+use directories::BaseDirs;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+use crate::config::Config;
+use crate::{crypto, signing};
+
+/// Private keys, credential stores and tokens that should never be stored in the clear.
+/// `~/` is the home directory, `*` matches within a path component, `**` across them;
+/// patterns without a `/` match the file name anywhere.
+const CATALOG: &[&str] = &[
+    "~/.ssh/id_*",
+    "~/.ssh/*_key",
+    "~/.gnupg/private-keys-v1.d/**",
+    "~/.gnupg/secring.gpg",
+    "~/.aws/credentials",
+    "~/.azure/**",
+    "~/.config/gcloud/**",
+    "~/.kube/config",
+    "~/.docker/config.json",
+    "~/.config/gh/hosts.yml",
+    "~/.netrc",
+    "~/.git-credentials",
+    "~/.pgpass",
+    "~/.password-store/**",
+    "~/.local/share/keyrings/**",
+    "~/.mozilla/firefox/*/logins.json",
+    "~/.mozilla/firefox/*/key4.db",
+    "*.pem",
+    "*.key",
+    "*.p12",
+    "*.pfx",
+    "*.kdbx",
+];
+
+/// `[sensitive]` in config.toml
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct SensitiveConfig {
+    /// patterns added to the built-in catalog, same syntax, e.g. `~/.config/rclone/rclone.conf`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub paths: Vec<String>,
+    /// aliases stored as they are although they match, set by `add --allow-sensitive`
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub allow: BTreeSet<String>,
+}
+
+impl SensitiveConfig {
+    pub fn is_default(&self) -> bool {
+        self.paths.is_empty() && self.allow.is_empty()
+    }
+}
+
+/// The pattern `path` matches, or what it is for confsync's own key files.
+/// Public keys (`*.pub`) never match.
+pub fn matches(path: &Path, config: &Config) -> Result<Option<String>, String> {
+    let own = [
+        (crypto::identity_path()?, "confsync identity"),
+        (signing::key_path(&config.signing)?, "confsync signing key"),
+        (config.secrets.path()?, "confsync secrets file"),
+    ];
+    if let Some((_, what)) = own.iter().find(|(own, _)| own == path) {
+        return Ok(Some(what.to_string()));
+    }
+    let home = BaseDirs::new().map(|dirs| dirs.home_dir().to_path_buf()).unwrap_or_default();
+    catalog_match(path, &home, &config.sensitive.paths)
+}
+
+/// The first pattern of the catalog or of `extra` that `path` matches, `~/` being `home`
+fn catalog_match(path: &Path, home: &Path, extra: &[String]) -> Result<Option<String>, String> {
+    if path.extension().is_some_and(|extension| extension == "pub") {
+        return Ok(None);
+    }
+    let text = path.to_string_lossy();
+    for pattern in CATALOG.iter().copied().chain(extra.iter().map(String::as_str)) {
+        if glob(pattern, home)?.is_match(&text) {
+            return Ok(Some(pattern.to_string()));
+        }
+    }
+    Ok(None)
+}
+
+/// Tracked aliases that match and are neither encrypted nor allowed, with the pattern
+pub fn unprotected(files: &[(String, PathBuf)], config: &Config) -> Result<Vec<(String, String)>, String> {
+    let mut found = Vec::new();
+    for (alias, path) in files {
+        if config.encryption.aliases.contains(alias) || config.sensitive.allow.contains(alias) {
+            continue;
+        }
+        if let Some(pattern) = matches(path, config)? {
+            found.push((alias.clone(), pattern));
+        }
+    }
+    Ok(found)
+}
+
+/// Regex of a catalog pattern
+fn glob(pattern: &str, home: &Path) -> Result<Regex, String> {
+    let expanded = match pattern.strip_prefix("~/") {
+        Some(rest) => format!("{}/{}", home.display(), rest),
+        None => pattern.to_string(),
+    };
+    let mut regex = String::from(if expanded.contains('/') { "^" } else { "(^|/)" });
+    let mut rest = expanded.as_str();
+    while let Some(c) = rest.chars().next() {
+        if let Some(after) = rest.strip_prefix("**/") {
+            regex.push_str("(.*/)?");
+            rest = after;
+        } else if rest == "/**" {
+            // the directory itself and everything in it
+            regex.push_str("(/.*)?");
+            rest = "";
+        } else if let Some(after) = rest.strip_prefix("**") {
+            regex.push_str(".*");
+            rest = after;
+        } else {
+            match c {
+                '*' => regex.push_str("[^/]*"),
+                '?' => regex.push_str("[^/]"),
+                _ => regex.push_str(&regex::escape(&c.to_string())),
+            }
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+    regex.push('$');
+    Regex::new(&regex).map_err(|e| format!("Invalid sensitive path pattern {}: {}", pattern, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matched(path: &str) -> Option<String> {
+        catalog_match(Path::new(path), Path::new("/home/me"), &["~/.config/rclone/**".to_string()]).unwrap()
+    }
+
+    #[test]
+    fn private_keys_match_but_public_keys_do_not() {
+        assert_eq!(matched("/home/me/.ssh/id_ed25519").as_deref(), Some("~/.ssh/id_*"));
+        assert_eq!(matched("/home/me/.ssh/id_rsa").as_deref(), Some("~/.ssh/id_*"));
+        assert_eq!(matched("/home/me/.ssh/id_ed25519.pub"), None);
+        assert_eq!(matched("/home/me/.ssh/config"), None);
+        // `*` stays within one path component
+        assert_eq!(matched("/home/me/.ssh/old/id_rsa"), None);
+        // the pattern is anchored at the home directory
+        assert_eq!(matched("/home/other/.ssh/id_rsa"), None);
+    }
+
+    #[test]
+    fn double_star_covers_the_directory_and_everything_below() {
+        assert_eq!(matched("/home/me/.password-store").as_deref(), Some("~/.password-store/**"));
+        assert_eq!(matched("/home/me/.password-store/mail.gpg").as_deref(), Some("~/.password-store/**"));
+        assert_eq!(matched("/home/me/.password-store/work/vpn.gpg").as_deref(), Some("~/.password-store/**"));
+        assert_eq!(matched("/home/me/.password-store-backup"), None);
+        assert_eq!(matched("/home/me/.config/rclone/rclone.conf").as_deref(), Some("~/.config/rclone/**"));
+    }
+
+    #[test]
+    fn file_name_patterns_match_anywhere() {
+        assert_eq!(matched("/etc/ssl/private/server.key").as_deref(), Some("*.key"));
+        assert_eq!(matched("/home/me/certs/client.pem").as_deref(), Some("*.pem"));
+        assert_eq!(matched("/home/me/keys.txt"), None);
+        assert_eq!(matched("/home/me/.netrc").as_deref(), Some("~/.netrc"));
+    }
+}
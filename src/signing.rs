@@ -0,0 +1,387 @@
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::OsRng;
+use directories::ProjectDirs;
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::audit::{self, Head};
+use crate::backend::{Backend, HISTORY_LOG};
+use crate::config::{self, load_config, save_config};
+use crate::store::{self, AliasIndex};
+use crate::ui::{self, printer};
+use crate::{crypto, perms, vault};
+
+/// Signed list of the aliases of a snapshot and their content hashes
+pub const MANIFEST_FILE: &str = "manifest.toml";
+
+/// Default host key, next to config.toml
+const KEY_FILE: &str = "signing.key";
+
+const KEY_PREFIX: &str = "ed25519:";
+
+/// `[signing]` in config.toml
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct SigningConfig {
+    /// host key snapshots are signed with [default: signing.key next to this config]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key: Option<PathBuf>,
+    /// public keys whose snapshots are accepted, by name
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub trusted: BTreeMap<String, String>,
+    /// refuse unsigned snapshots and snapshots signed by keys not in `trusted`
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub require_signed: bool,
+}
+
+impl SigningConfig {
+    pub fn is_default(&self) -> bool {
+        self.key.is_none() && self.trusted.is_empty() && !self.require_signed
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Manifest {
+    /// host the snapshot was taken on
+    pub host: String,
+    pub created: String,
+    /// public key of the signing host
+    pub signer: String,
+    /// hex encoded ed25519 signature of `payload()`
+    pub signature: String,
+    /// number of history.log records when signed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub history_records: Option<usize>,
+    /// hash of the last history.log record when signed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub history_head: Option<String>,
+    #[serde(default)]
+    pub aliases: BTreeMap<String, ManifestEntry>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ManifestEntry {
+    /// storage key
+    pub key: String,
+    /// sha256 of the content, as recorded in aliases.toml
+    pub hash: String,
+    /// content is stored encrypted
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub encrypted: bool,
+}
+
+impl Manifest {
+    /// The bytes that are signed
+    fn payload(&self) -> Vec<u8> {
+        let mut payload = format!(
+            "confsync snapshot v1\nhost {}\ncreated {}\nsigner {}\n",
+            self.host, self.created, self.signer
+        );
+        if let Some(head) = self.history() {
+            payload.push_str(&format!("history {} {}\n", head.records, head.hash));
+        }
+        for (alias, entry) in &self.aliases {
+            let encryption = if entry.encrypted { "encrypted" } else { "plain" };
+            payload.push_str(&format!("{}\t{}\t{}\t{}\n", alias, entry.key, entry.hash, encryption));
+        }
+        payload.into_bytes()
+    }
+
+    /// End of history.log when the snapshot was signed
+    pub fn history(&self) -> Option<Head> {
+        Some(Head { records: self.history_records?, hash: self.history_head.clone()? })
+    }
+}
+
+/// Who signed a snapshot, as far as the trust list knows
+#[derive(Debug)]
+pub enum Verdict {
+    Trusted { name: String },
+    Untrusted { host: String, signer: String },
+    Unsigned,
+}
+
+/// Host key file: `signing.key`, or `signing.key` in the config directory
+pub fn key_path(config: &SigningConfig) -> Result<PathBuf, String> {
+    if let Some(path) = &config.key {
+        return Ok(path.clone());
+    }
+    let project_dirs = ProjectDirs::from("", "", "confsync")
+        .ok_or_else(|| "Failed to find config directory".to_string())?;
+    Ok(project_dirs.config_dir().join(KEY_FILE))
+}
+
+/// `ed25519:<hex>`
+pub fn public_key(key: &VerifyingKey) -> String {
+    format!("{}{}", KEY_PREFIX, vault::to_hex(key.as_bytes()))
+}
+
+/// Short form of a public key for messages
+pub fn fingerprint(public_key: &str) -> String {
+    public_key.chars().take(KEY_PREFIX.len() + 16).collect()
+}
+
+/// Check that `public_key` is an `ed25519:<hex>` key
+pub fn parse_public_key(public_key: &str) -> Result<VerifyingKey, String> {
+    let bytes = public_key
+        .strip_prefix(KEY_PREFIX)
+        .ok_or_else(|| format!("Invalid public key {}: expected {}<hex>", public_key, KEY_PREFIX))
+        .and_then(vault::from_hex)?;
+    let bytes: [u8; 32] = bytes.try_into().map_err(|_| format!("Invalid public key {}", public_key))?;
+    VerifyingKey::from_bytes(&bytes).map_err(|e| format!("Invalid public key {}: {}", public_key, e))
+}
+
+/// The host key, created on first use. A new key is added to the trust list under the host name,
+/// so backups call this before the config is stored.
+pub fn host_key() -> Result<SigningKey, String> {
+    let path = key_path(&load_config()?.signing)?;
+    if path.exists() {
+        return read_key(&path);
+    }
+    let key = create_key(&path)?;
+    let public_key = public_key(&key.verifying_key());
+    let host = config::host_name()?;
+    let mut config = load_config()?;
+    let name = match config.signing.trusted.get(&host) {
+        Some(existing) if existing != &public_key => format!("{}-{}", host, &public_key[KEY_PREFIX.len()..KEY_PREFIX.len() + 8]),
+        _ => host,
+    };
+    config.signing.trusted.insert(name, public_key.clone());
+    save_config(&config)?;
+    printer(
+        format!("Created signing key {}. Trust it on your other machines: add it to [signing.trusted]", path.display()).as_str(),
+        ui::MessageType::Info,
+    );
+    printer(&public_key, ui::MessageType::Default);
+    Ok(key)
+}
+
+fn create_key(path: &Path) -> Result<SigningKey, String> {
+    let mut seed = [0u8; 32];
+    OsRng.fill_bytes(&mut seed);
+    let key = SigningKey::from_bytes(&seed);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
+    }
+    // readable by the owner only, from the moment it exists
+    let mut file = perms::create_new(path)
+        .map_err(|e| format!("Failed to create signing key {}: {}", path.display(), e))?;
+    let created = chrono::Local::now().format("%Y-%m-%dT%H:%M:%S%:z");
+    writeln!(file, "# created: {}\n# public key: {}\n{}", created, public_key(&key.verifying_key()), vault::to_hex(&seed))
+        .map_err(|e| format!("Failed to write signing key: {}", e))?;
+    Ok(key)
+}
+
+fn read_key(path: &Path) -> Result<SigningKey, String> {
+    let contents = fs::read_to_string(path).map_err(|e| format!("Failed to read signing key {}: {}", path.display(), e))?;
+    let seed = contents
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty() && !line.starts_with('#'))
+        .ok_or_else(|| format!("No key in {}", path.display()))?;
+    let seed: [u8; 32] = vault::from_hex(seed)
+        .ok()
+        .and_then(|seed| seed.try_into().ok())
+        .ok_or_else(|| format!("Invalid signing key in {}", path.display()))?;
+    Ok(SigningKey::from_bytes(&seed))
+}
+
+/// Sign the current state of the backend: every alias with its storage key, content hash
+/// and encryption, and the end of the history index.
+pub fn sign(backend: &dyn Backend) -> Result<(), String> {
+    let key = host_key()?;
+    // records from before hashes were kept: hash what is stored and record it,
+    // since verification requires every record to carry the signed hash
+    if store::read_index(backend)?.aliases.values().any(|record| record.hash.is_none()) {
+        store::update_index(backend, &mut |index| {
+            for (alias, record) in index.aliases.iter_mut().filter(|(_, record)| record.hash.is_none()) {
+                let hash = match backend.get(&store::content_key(&record.key))? {
+                    Some(data) if record.encrypted => format!("{:x}", Sha256::digest(crypto::decrypt(&data)?)),
+                    Some(data) => format!("{:x}", Sha256::digest(&data)),
+                    None => return Err(format!("{} has no stored content to sign; back it up again or delete it", alias)),
+                };
+                record.hash = Some(hash);
+            }
+            Ok(())
+        })?;
+    }
+    // the index and history are read after the manifest, so a host that signed in between
+    // makes this one sign again over what it wrote
+    backend.update(MANIFEST_FILE, &mut |_| {
+        let index = store::read_index(backend)?;
+        // anchors the history chain, so truncating it shows
+        let history = audit::head(&String::from_utf8_lossy(&backend.get(HISTORY_LOG)?.unwrap_or_default()));
+        let manifest = signed(&index, &key, config::host_name()?, history)?;
+        let contents = toml::to_string(&manifest).map_err(|e| format!("Failed to serialize manifest: {}", e))?;
+        Ok(contents.into_bytes())
+    })
+}
+
+/// Manifest of `index` signed with `key`
+fn signed(index: &AliasIndex, key: &SigningKey, host: String, history: Head) -> Result<Manifest, String> {
+    let mut aliases = BTreeMap::new();
+    for (alias, record) in &index.aliases {
+        let hash = record.hash.clone().ok_or_else(|| format!("{} has no recorded hash to sign", alias))?;
+        aliases.insert(alias.clone(), ManifestEntry { key: record.key.clone(), hash, encrypted: record.encrypted });
+    }
+    let mut manifest = Manifest {
+        host,
+        created: chrono::Local::now().format("%Y-%m-%dT%H:%M:%S%:z").to_string(),
+        signer: public_key(&key.verifying_key()),
+        signature: String::new(),
+        history_records: Some(history.records),
+        history_head: Some(history.hash),
+        aliases,
+    };
+    manifest.signature = vault::to_hex(&key.sign(&manifest.payload()).to_bytes());
+    Ok(manifest)
+}
+
+/// Who signs this host's snapshots, for the history index
+pub fn signer() -> Result<String, String> {
+    let key = host_key()?;
+    Ok(format!("{} {}", config::host_name()?, fingerprint(&public_key(&key.verifying_key()))))
+}
+
+pub fn parse_manifest(contents: &[u8]) -> Result<Manifest, String> {
+    toml::from_str(&String::from_utf8_lossy(contents)).map_err(|e| format!("Failed to parse {}: {}", MANIFEST_FILE, e))
+}
+
+/// Check the signature of a snapshot and that `index` is what was signed.
+/// Fails when the snapshot was changed after signing.
+pub fn verify(manifest: Option<&[u8]>, index: &AliasIndex, trusted: &BTreeMap<String, String>) -> Result<Verdict, String> {
+    let Some(manifest) = manifest else {
+        return Ok(Verdict::Unsigned);
+    };
+    let manifest = parse_manifest(manifest)?;
+    let signature: [u8; 64] = vault::from_hex(&manifest.signature)
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| format!("Invalid signature in {}", MANIFEST_FILE))?;
+    parse_public_key(&manifest.signer)?
+        .verify(&manifest.payload(), &Signature::from_bytes(&signature))
+        .map_err(|_| "Snapshot signature does not match: it was changed after it was signed".to_string())?;
+
+    for (alias, record) in &index.aliases {
+        let Some(entry) = manifest.aliases.get(alias) else {
+            return Err(format!("{} was added after the snapshot was signed", alias));
+        };
+        // a record without its hash would let any content through
+        if entry.key != record.key || record.hash.as_deref() != Some(entry.hash.as_str()) || entry.encrypted != record.encrypted {
+            return Err(format!("{} was changed after the snapshot was signed", alias));
+        }
+    }
+    if let Some(alias) = manifest.aliases.keys().find(|alias| index.get(alias).is_none()) {
+        return Err(format!("{} was removed after the snapshot was signed", alias));
+    }
+
+    Ok(match trusted.iter().find(|(_, key)| **key == manifest.signer) {
+        Some((name, _)) => Verdict::Trusted { name: name.clone() },
+        None => Verdict::Untrusted { host: manifest.host, signer: manifest.signer },
+    })
+}
+
+/// Verify a snapshot against a trust list. Unsigned and untrusted snapshots fail with
+/// `require_signed` and are reported as warnings otherwise. `what` names the snapshot in messages.
+pub fn check(manifest: Option<&[u8]>, index: &AliasIndex, signing: &SigningConfig, what: &str) -> Result<Verdict, String> {
+    let verdict = verify(manifest, index, &signing.trusted).map_err(|e| format!("{}: {}", what, e))?;
+    let (problem, accept) = match &verdict {
+        Verdict::Trusted { .. } => return Ok(verdict),
+        Verdict::Unsigned => (format!("{} are not signed", what), "unset signing.require_signed"),
+        Verdict::Untrusted { host, signer } => (
+            format!("{} are signed by {} with untrusted key {}", what, host, signer),
+            "add the key to [signing.trusted]",
+        ),
+    };
+    if signing.require_signed {
+        return Err(format!("{}; {} to accept them", problem, accept));
+    }
+    printer(&problem, ui::MessageType::Warning);
+    Ok(verdict)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::AliasRecord;
+
+    fn record(key: &str, hash: Option<&str>) -> AliasRecord {
+        AliasRecord {
+            key: key.to_string(),
+            source: PathBuf::from(format!("/home/user/.{}", key)),
+            file_name: format!(".{}", key),
+            hash: hash.map(str::to_string),
+            encrypted: false,
+            created: "2026-01-01 00:00:00".to_string(),
+            updated: "2026-01-01 00:00:00".to_string(),
+        }
+    }
+
+    /// A signed manifest of `index` and the trust list holding its key
+    fn signed_snapshot(index: &AliasIndex) -> (Vec<u8>, BTreeMap<String, String>) {
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let history = Head { records: 0, hash: String::new() };
+        let manifest = signed(index, &key, "laptop".to_string(), history).unwrap();
+        let trusted = BTreeMap::from([("laptop".to_string(), public_key(&key.verifying_key()))]);
+        (toml::to_string(&manifest).unwrap().into_bytes(), trusted)
+    }
+
+    fn index() -> AliasIndex {
+        let mut index = AliasIndex::default();
+        index.aliases.insert("zsh".to_string(), record("zsh", Some("aa")));
+        index.aliases.insert("git".to_string(), record("git", Some("bb")));
+        index
+    }
+
+    #[test]
+    fn untouched_snapshot_verifies() {
+        let index = index();
+        let (manifest, trusted) = signed_snapshot(&index);
+        assert!(matches!(verify(Some(&manifest), &index, &trusted), Ok(Verdict::Trusted { .. })));
+    }
+
+    #[test]
+    fn stripped_hash_fails() {
+        let mut index = index();
+        let (manifest, trusted) = signed_snapshot(&index);
+        // as if `hash` was deleted from aliases.toml to swap the content
+        let contents = store::parse_index(&toml::to_string(&index).unwrap().replace("hash = \"aa\"\n", "")).unwrap();
+        assert!(contents.get("zsh").unwrap().hash.is_none());
+        assert!(verify(Some(&manifest), &contents, &trusted).is_err());
+
+        index.aliases.get_mut("zsh").unwrap().hash = Some("cc".to_string());
+        assert!(verify(Some(&manifest), &index, &trusted).is_err());
+    }
+
+    #[test]
+    fn flipped_encryption_fails() {
+        let mut index = index();
+        let (manifest, trusted) = signed_snapshot(&index);
+        index.aliases.get_mut("git").unwrap().encrypted = true;
+        assert!(verify(Some(&manifest), &index, &trusted).is_err());
+    }
+
+    #[test]
+    fn added_and_removed_aliases_fail() {
+        let mut index = index();
+        let (manifest, trusted) = signed_snapshot(&index);
+        index.aliases.insert("vim".to_string(), record("vim", Some("dd")));
+        assert!(verify(Some(&manifest), &index, &trusted).is_err());
+
+        let mut index = self::index();
+        index.aliases.remove("git");
+        assert!(verify(Some(&manifest), &index, &trusted).is_err());
+    }
+
+    #[test]
+    fn edited_manifest_fails() {
+        let index = index();
+        let (manifest, trusted) = signed_snapshot(&index);
+        let edited = String::from_utf8(manifest).unwrap().replace("host = \"laptop\"", "host = \"desktop\"");
+        assert!(verify(Some(edited.as_bytes()), &index, &trusted).is_err());
+    }
+}
@@ -0,0 +1,182 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::backend::Backend;
+use crate::{perms, vault};
+
+/// Directory holding one sub directory per storage key
+pub const STORE_DIR: &str = "store";
+
+/// Metadata record of every stored alias
+pub const ALIASES_FILE: &str = "aliases.toml";
+
+/// Stored bytes of an alias, independent of the live file name
+pub const CONTENT_FILE: &str = "content";
+
+/// Backup timestamps of an alias
+pub const HISTORY_FILE: &str = "history.cmt";
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AliasRecord {
+    /// stable directory name under `store/`; never changes once assigned
+    pub key: String,
+    /// path the content was last taken from
+    pub source: PathBuf,
+    /// file name at the time of the last backup
+    pub file_name: String,
+    /// sha256 of the stored content (of the plaintext, for encrypted aliases)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+    /// content is an age file, decrypted on restore
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub encrypted: bool,
+    pub created: String,
+    pub updated: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct AliasIndex {
+    #[serde(default)]
+    pub aliases: BTreeMap<String, AliasRecord>,
+    /// new keys are random, since the index of an encrypted repository is the only place
+    /// that ties them to aliases
+    #[serde(skip)]
+    pub random_keys: bool,
+}
+
+impl AliasIndex {
+    pub fn get(&self, alias: &str) -> Option<&AliasRecord> {
+        self.aliases.get(alias)
+    }
+
+    /// Record a backup of `source` under `alias`, assigning a storage key on first use.
+    /// Returns the storage key.
+    pub fn record(&mut self, alias: &str, source: &Path) -> Result<String, String> {
+        let file_name = source
+            .file_name()
+            .and_then(|n| n.to_str())
+            .ok_or_else(|| "Failed to get file name".to_string())?
+            .to_string();
+        let timestamp = chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string();
+
+        if let Some(record) = self.aliases.get_mut(alias) {
+            record.source = source.to_path_buf();
+            record.file_name = file_name;
+            record.updated = timestamp;
+            return Ok(record.key.clone());
+        }
+
+        let key = self.new_key(alias);
+        self.aliases.insert(
+            alias.to_string(),
+            AliasRecord {
+                key: key.clone(),
+                source: source.to_path_buf(),
+                file_name,
+                hash: None,
+                encrypted: false,
+                created: timestamp.clone(),
+                updated: timestamp,
+            },
+        );
+        Ok(key)
+    }
+
+    /// Derive a file-system safe key from the alias, unique within the index.
+    /// Encrypted repositories get a random one instead.
+    pub fn new_key(&self, alias: &str) -> String {
+        if self.random_keys {
+            return vault::random_name();
+        }
+        let base: String = alias
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+            .collect();
+        let base = if base.is_empty() { "alias".to_string() } else { base };
+        let taken = |key: &str| self.aliases.values().any(|r| r.key == key);
+
+        let mut key = base.clone();
+        let mut n = 2;
+        while taken(&key) {
+            key = format!("{}-{}", base, n);
+            n += 1;
+        }
+        key
+    }
+}
+
+/// Backend key of the stored content of a storage key
+pub fn content_key(key: &str) -> String {
+    format!("{}/{}/{}", STORE_DIR, key, CONTENT_FILE)
+}
+
+/// Backend key of the backup timestamps of a storage key
+pub fn history_key(key: &str) -> String {
+    format!("{}/{}/{}", STORE_DIR, key, HISTORY_FILE)
+}
+
+/// Load the alias index from a backend (empty if none was written yet)
+pub fn read_index(backend: &dyn Backend) -> Result<AliasIndex, String> {
+    let mut index = match backend.get(ALIASES_FILE)? {
+        Some(contents) => parse_index(&String::from_utf8_lossy(&contents))?,
+        None => AliasIndex::default(),
+    };
+    index.random_keys = backend.get(vault::VAULT_FILE)?.is_some();
+    Ok(index)
+}
+
+/// Apply `change` to the stored alias index. Where other hosts share the backend,
+/// it runs again on their newer index if one was written in between.
+pub fn update_index(backend: &dyn Backend, change: &mut dyn FnMut(&mut AliasIndex) -> Result<(), String>) -> Result<(), String> {
+    let random_keys = backend.get(vault::VAULT_FILE)?.is_some();
+    backend.update(ALIASES_FILE, &mut |contents| {
+        let mut index = match contents {
+            Some(contents) => parse_index(&String::from_utf8_lossy(&contents))?,
+            None => AliasIndex::default(),
+        };
+        index.random_keys = random_keys;
+        change(&mut index)?;
+        let toml_string = toml::to_string_pretty(&index)
+            .map_err(|e| format!("Failed to serialize alias index: {}", e))?;
+        Ok(toml_string.into_bytes())
+    })
+}
+
+/// Load the alias index of a repository (empty if none was written yet)
+pub fn load_index(repo_path: &Path) -> Result<AliasIndex, String> {
+    let path = repo_path.join(ALIASES_FILE);
+    let mut index = match path.exists() {
+        true => {
+            let contents = fs::read(&path).map_err(|e| format!("Failed to read alias index: {}", e))?;
+            parse_index(&String::from_utf8_lossy(&vault::decode_at(repo_path, ALIASES_FILE, contents)?))?
+        }
+        false => AliasIndex::default(),
+    };
+    index.random_keys = repo_path.join(vault::VAULT_FILE).exists();
+    Ok(index)
+}
+
+pub fn parse_index(contents: &str) -> Result<AliasIndex, String> {
+    toml::from_str(contents).map_err(|e| format!("Failed to parse alias index: {}", e))
+}
+
+pub fn save_index(repo_path: &Path, index: &AliasIndex) -> Result<(), String> {
+    let toml_string = toml::to_string_pretty(index)
+        .map_err(|e| format!("Failed to serialize alias index: {}", e))?;
+    perms::write(repo_path.join(ALIASES_FILE), vault::encode_at(repo_path, ALIASES_FILE, toml_string.into_bytes())?)
+        .map_err(|e| format!("Failed to write alias index: {}", e))
+}
+
+pub fn key_dir(repo_path: &Path, key: &str) -> PathBuf {
+    repo_path.join(STORE_DIR).join(key)
+}
+
+pub fn content_path(repo_path: &Path, key: &str) -> PathBuf {
+    key_dir(repo_path, key).join(CONTENT_FILE)
+}
+
+pub fn history_path(repo_path: &Path, key: &str) -> PathBuf {
+    key_dir(repo_path, key).join(HISTORY_FILE)
+}
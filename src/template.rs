@@ -0,0 +1,263 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::config::{self, Config};
+
+/// `[templates]` in config.toml
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct TemplateConfig {
+    /// aliases stored as templates and rendered for each host on restore
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub aliases: BTreeSet<String>,
+    /// variables of every host, e.g. `email = "me@example.com"`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub vars: BTreeMap<String, String>,
+    /// variables of single hosts, over `vars`: `[templates.hosts.work-laptop] email = "..."`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub hosts: BTreeMap<String, BTreeMap<String, String>>,
+}
+
+impl TemplateConfig {
+    pub fn is_default(&self) -> bool {
+        self.aliases.is_empty() && self.vars.is_empty() && self.hosts.is_empty()
+    }
+}
+
+/// Variables of `host`: `hostname`, `os`, `arch`, `user` and `home`, then the custom ones
+pub fn vars(config: &Config, host: &str) -> BTreeMap<String, String> {
+    let user = ["USER", "USERNAME", "LOGNAME"].iter().find_map(|name| std::env::var(name).ok()).unwrap_or_default();
+    let home = directories::BaseDirs::new().map(|dirs| dirs.home_dir().display().to_string()).unwrap_or_default();
+    let mut vars = BTreeMap::from([
+        ("hostname".to_string(), host.to_string()),
+        ("os".to_string(), std::env::consts::OS.to_string()),
+        ("arch".to_string(), std::env::consts::ARCH.to_string()),
+        ("user".to_string(), user),
+        ("home".to_string(), home),
+    ]);
+    vars.extend(config.templates.vars.clone());
+    if let Some(host_vars) = config.templates.hosts.get(host) {
+        vars.extend(host_vars.clone());
+    }
+    vars
+}
+
+/// A tracked file stored as a template
+pub struct Template<'a> {
+    alias: &'a str,
+    vars: BTreeMap<String, String>,
+}
+
+impl<'a> Template<'a> {
+    /// `alias` rendered for this host, if it is a template
+    pub fn of(config: &Config, alias: &'a str) -> Result<Option<Self>, String> {
+        if !config.templates.aliases.contains(alias) {
+            return Ok(None);
+        }
+        Ok(Some(Self::for_host(config, alias, &config::host_name_of(config)?)))
+    }
+
+    /// `alias` rendered for another host; only its name and variables change, not `os` or `user`
+    pub fn for_host(config: &Config, alias: &'a str, host: &str) -> Self {
+        Template { alias, vars: vars(config, host) }
+    }
+
+    /// The output for this host. `{{ name }}` is a variable, `{% if %}`, `{% elif %}`,
+    /// `{% else %}` and `{% endif %}` choose lines; anything else in braces, like
+    /// `{{secret:name}}` placeholders, is kept as it is.
+    pub fn render(&self, data: &[u8]) -> Result<Vec<u8>, String> {
+        let text = std::str::from_utf8(data)
+            .map_err(|_| format!("{} is not a text file; templates need one", self.alias))?;
+        let mut out = String::with_capacity(text.len());
+        // one frame per open `if`: (enclosing output on, a branch was taken, this branch is on, else seen)
+        let mut frames: Vec<(bool, bool, bool, bool)> = Vec::new();
+        let on = |frames: &[(bool, bool, bool, bool)]| frames.last().is_none_or(|frame| frame.0 && frame.2);
+
+        let mut rest = text;
+        while let Some(start) = rest.find("{{").into_iter().chain(rest.find("{%")).min() {
+            let line = text[..text.len() - rest.len() + start].matches('\n').count() + 1;
+            let (before, tag) = rest.split_at(start);
+            if tag.starts_with("{{") {
+                let Some(end) = tag.find("}}") else {
+                    break;
+                };
+                let name = tag[2..end].trim();
+                if on(&frames) {
+                    out.push_str(before);
+                    if is_name(name) {
+                        let value = self.vars.get(name).ok_or_else(|| self.error(line, &format!("unknown variable {}", name)))?;
+                        out.push_str(value);
+                    } else {
+                        out.push_str(&tag[..end + 2]);
+                    }
+                }
+                rest = &tag[end + 2..];
+                continue;
+            }
+
+            let end = tag.find("%}").ok_or_else(|| self.error(line, "`{%` without `%}`"))?;
+            let directive = tag[2..end].trim();
+            let mut after = &tag[end + 2..];
+            // a directive alone on its line takes the line with it
+            let consumed = text.len() - rest.len();
+            let line_start = match before.rfind('\n') {
+                Some(i) => Some(i + 1),
+                None if consumed == 0 || text[..consumed].ends_with('\n') => Some(0),
+                None => None,
+            };
+            let line_end = after.find('\n').unwrap_or(after.len());
+            let before = match line_start {
+                Some(line_start) if before[line_start..].trim().is_empty() && after[..line_end].trim().is_empty() => {
+                    after = &after[(line_end + 1).min(after.len())..];
+                    &before[..line_start]
+                }
+                _ => before,
+            };
+            if on(&frames) {
+                out.push_str(before);
+            }
+
+            let (keyword, expression) = directive.split_once(char::is_whitespace).unwrap_or((directive, ""));
+            match (keyword, frames.last_mut()) {
+                ("if", _) => {
+                    let enclosing = on(&frames);
+                    let taken = enclosing && self.condition(expression, line)?;
+                    frames.push((enclosing, taken, taken, false));
+                }
+                ("elif", Some(frame)) if !frame.3 => {
+                    let taken = !frame.1 && frame.0 && self.condition(expression, line)?;
+                    frame.1 |= taken;
+                    frame.2 = taken;
+                }
+                ("else", Some(frame)) if !frame.3 => {
+                    frame.2 = !frame.1;
+                    frame.3 = true;
+                }
+                ("endif", Some(_)) => {
+                    frames.pop();
+                }
+                ("elif" | "else" | "endif", _) => return Err(self.error(line, &format!("`{}` without a matching `if`", keyword))),
+                _ => return Err(self.error(line, &format!("unknown directive `{}`", keyword))),
+            }
+            rest = after;
+        }
+        if !frames.is_empty() {
+            return Err(format!("Template {}: `if` without `endif`", self.alias));
+        }
+        out.push_str(rest);
+        Ok(out.into_bytes())
+    }
+
+    /// `name`, `not name`, `name == "value"`, `name != "value"`, joined with `and` / `or`.
+    /// Unknown variables are empty here, so hosts without a custom variable can test for it.
+    fn condition(&self, expression: &str, line: usize) -> Result<bool, String> {
+        for any in expression.split(" or ") {
+            let mut all = true;
+            for term in any.split(" and ") {
+                all &= self.term(term.trim(), line)?;
+            }
+            if all {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    fn term(&self, term: &str, line: usize) -> Result<bool, String> {
+        if let Some(negated) = term.strip_prefix("not ") {
+            return Ok(!self.term(negated.trim(), line)?);
+        }
+        let value = |name: &str| -> Result<&str, String> {
+            if !is_name(name) {
+                return Err(self.error(line, &format!("invalid variable name `{}`", name)));
+            }
+            Ok(self.vars.get(name).map_or("", String::as_str))
+        };
+        for (operator, equal) in [("==", true), ("!=", false)] {
+            if let Some((name, literal)) = term.split_once(operator) {
+                let literal = literal.trim();
+                let quoted = literal.len() >= 2
+                    && (literal.starts_with('"') && literal.ends_with('"') || literal.starts_with('\'') && literal.ends_with('\''));
+                if !quoted {
+                    return Err(self.error(line, &format!("expected a quoted value after `{}`", operator)));
+                }
+                return Ok((value(name.trim())? == &literal[1..literal.len() - 1]) == equal);
+            }
+        }
+        Ok(!value(term)?.is_empty())
+    }
+
+    fn error(&self, line: usize, message: &str) -> String {
+        format!("Template {} line {}: {}", self.alias, line, message)
+    }
+}
+
+/// Variable names: letters, digits, `_` and `-`
+fn is_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(text: &str, vars: &[(&str, &str)]) -> Result<String, String> {
+        let vars = vars.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect();
+        let template = Template { alias: "test", vars };
+        template.render(text.as_bytes()).map(|out| String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn nested_branches() {
+        let text = "\
+{% if os == \"linux\" %}
+{% if work %}
+linux work
+{% elif home %}
+linux home
+{% else %}
+linux other
+{% endif %}
+{% elif os == \"macos\" %}
+mac
+{% else %}
+other
+{% endif %}
+";
+        assert_eq!(render(text, &[("os", "linux"), ("work", "1")]).unwrap(), "linux work\n");
+        assert_eq!(render(text, &[("os", "linux"), ("home", "1")]).unwrap(), "linux home\n");
+        assert_eq!(render(text, &[("os", "linux")]).unwrap(), "linux other\n");
+        assert_eq!(render(text, &[("os", "macos"), ("work", "1")]).unwrap(), "mac\n");
+        assert_eq!(render(text, &[("os", "windows")]).unwrap(), "other\n");
+    }
+
+    #[test]
+    fn directive_lines_are_removed() {
+        let text = "a\n  {% if x %}  \nb\n{% endif %}\nc {% if x %}d{% endif %}\n";
+        assert_eq!(render(text, &[("x", "1")]).unwrap(), "a\nb\nc d\n");
+        assert_eq!(render(text, &[]).unwrap(), "a\nc \n");
+    }
+
+    #[test]
+    fn variables_are_filled() {
+        assert_eq!(render("name = {{ user }}\n", &[("user", "me")]).unwrap(), "name = me\n");
+    }
+
+    #[test]
+    fn unknown_variable_is_an_error() {
+        let e = render("one\nname = {{ user }}\n", &[]).unwrap_err();
+        assert!(e.contains("line 2") && e.contains("unknown variable user"), "{}", e);
+    }
+
+    #[test]
+    fn unclosed_directive_is_an_error() {
+        assert!(render("{% if x\n", &[("x", "1")]).unwrap_err().contains("without `%}`"));
+        assert!(render("{% if x %}\n", &[("x", "1")]).unwrap_err().contains("without `endif`"));
+        assert!(render("{% endif %}\n", &[]).unwrap_err().contains("without a matching `if`"));
+    }
+
+    #[test]
+    fn other_braces_are_kept() {
+        let text = "token = {{secret:api_token}}\n{{ not a name }}\n";
+        assert_eq!(render(text, &[]).unwrap(), text);
+    }
+}
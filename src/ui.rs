@@ -37,6 +37,53 @@ pub fn printer(message: &str, message_type: MessageType) {
 }
 
 
+/// Print one line of git output, styled like the body of `MessageType::Git`
+pub fn print_git_line(line: &str) {
+    println!("{}", line.bright_black().italic());
+}
+
+/// Ask a question on the terminal and return the trimmed answer.
+/// Fails when stdin is not a terminal, so scripts never hang on a prompt.
+pub fn prompt(question: &str) -> Result<String, String> {
+    use std::io::{BufRead, IsTerminal, Write};
+    if !std::io::stdin().is_terminal() {
+        return Err("Input required but stdin is not a terminal".into());
+    }
+    print!("{} ", question.yellow());
+    std::io::stdout().flush().map_err(|e| format!("Failed to write prompt: {}", e))?;
+    let mut answer = String::new();
+    std::io::stdin()
+        .lock()
+        .read_line(&mut answer)
+        .map_err(|e| format!("Failed to read answer: {}", e))?;
+    Ok(answer.trim().to_string())
+}
+
+/// Like `prompt`, without echoing the answer (passphrases)
+pub fn prompt_secret(question: &str) -> Result<String, String> {
+    use std::io::{BufRead, IsTerminal, Write};
+    if !std::io::stdin().is_terminal() {
+        return Err("Input required but stdin is not a terminal".into());
+    }
+    print!("{} ", question.yellow());
+    std::io::stdout().flush().map_err(|e| format!("Failed to write prompt: {}", e))?;
+
+    let mut term: libc::termios = unsafe { std::mem::zeroed() };
+    let echo_off = unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut term) } == 0 && {
+        let mut silent = term;
+        silent.c_lflag &= !libc::ECHO;
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &silent) == 0 }
+    };
+    let mut answer = String::new();
+    let result = std::io::stdin().lock().read_line(&mut answer);
+    if echo_off {
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &term) };
+    }
+    println!();
+    result.map_err(|e| format!("Failed to read answer: {}", e))?;
+    Ok(answer.trim_end_matches(['\r', '\n']).to_string())
+}
+
 /// Styles a file system path.
 pub fn style_path(path: &str) -> String {
     // Split the path into its components.
@@ -123,16 +170,20 @@ pub fn print_table(name: &str, value: &str, style: Option<&str>) {
 }
 
 
-// pub fn _print_progress_bar(message: &str) -> ProgressBar {
-//     let pb = ProgressBar::new(100);
-//     pb.set_style(
-//         ProgressStyle::with_template("{msg} [{bar:40.cyan/blue}] {percent}%")
-//             .unwrap()
-//             .progress_chars("##-"),
-//     );
-//     pb.set_message(message);
-//     pb
-// }
+/// Progress bar over `len` items. Hidden under --quiet so output stays deterministic.
+pub fn progress_bar(len: usize, message: &str, quiet: bool) -> ProgressBar {
+    if quiet {
+        return ProgressBar::hidden();
+    }
+    let pb = ProgressBar::new(len as u64);
+    pb.set_style(
+        ProgressStyle::with_template("{msg} [{bar:40.cyan/blue}] {pos}/{len}")
+            .unwrap()
+            .progress_chars("##-"),
+    );
+    pb.set_message(message.to_string());
+    pb
+}
 
 pub fn _run_with_spinner<F, T>(message: &str, task: F) -> Result<T, String>
 where
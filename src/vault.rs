@@ -0,0 +1,397 @@
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use std::sync::Mutex;
+
+use crate::backend::Backend;
+use crate::config::load_config;
+use crate::format::FORMAT_FILE;
+use crate::ui;
+
+/// Wrapped data key and KDF parameters; stays plaintext so any machine can unlock the repository
+pub const VAULT_FILE: &str = "vault.toml";
+
+/// Objects that are never encrypted: needed before unlocking, or local to this machine
+const PLAIN_FILES: [&str; 3] = [VAULT_FILE, FORMAT_FILE, "log.txt"];
+
+/// Start of every encrypted object, followed by the nonce and the ciphertext.
+/// The object key is authenticated with it, so sealed objects cannot be swapped between keys.
+const MAGIC: &[u8] = b"CSVAULT2";
+
+/// Git commit message of every snapshot of an encrypted repository. Commit messages are
+/// not encrypted, so the real one is only kept in the sealed history.log.
+const NEUTRAL_MESSAGE: &str = "Encrypted backup";
+
+const NONCE_LEN: usize = 24;
+
+/// Environment variable read for the passphrase unless `encryption.passphrase_env` names another
+const DEFAULT_PASSPHRASE_ENV: &str = "CONFSYNC_PASSPHRASE";
+
+/// Read by `change-passphrase` for the new passphrase when there is no terminal
+const NEW_PASSPHRASE_ENV: &str = "CONFSYNC_NEW_PASSPHRASE";
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct VaultFile {
+    pub kdf: String,
+    /// argon2id memory cost in KiB
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+    /// hex encoded
+    pub salt: String,
+    /// data key encrypted with the passphrase-derived key, nonce first, hex encoded
+    pub wrapped_key: String,
+    /// identifies the data key, so vaults of the same repository match across passphrases
+    pub key_id: String,
+}
+
+/// Data key of the repository unlocked in this run, by vault salt; asked for at most once
+static UNLOCKED: Mutex<Option<(String, [u8; 32])>> = Mutex::new(None);
+
+/// Backend whose objects are encrypted with the repository's data key
+pub struct VaultBackend {
+    inner: Box<dyn Backend>,
+    key: [u8; 32],
+}
+
+impl Backend for VaultBackend {
+    fn name(&self) -> &'static str {
+        self.inner.name()
+    }
+
+    fn put(&self, key: &str, data: &[u8]) -> Result<(), String> {
+        if PLAIN_FILES.contains(&key) {
+            return self.inner.put(key, data);
+        }
+        self.inner.put(key, &seal(&self.key, key, data)?)
+    }
+
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>, String> {
+        match self.inner.get(key)? {
+            Some(data) if PLAIN_FILES.contains(&key) => Ok(Some(data)),
+            Some(data) => open(&self.key, key, data).map(Some).map_err(|e| format!("{}: {}", key, e)),
+            None => Ok(None),
+        }
+    }
+
+    fn update(&self, key: &str, change: &mut dyn FnMut(Option<Vec<u8>>) -> Result<Vec<u8>, String>) -> Result<(), String> {
+        if PLAIN_FILES.contains(&key) {
+            return self.inner.update(key, change);
+        }
+        self.inner.update(key, &mut |data| {
+            let data = data.map(|data| open(&self.key, key, data).map_err(|e| format!("{}: {}", key, e))).transpose()?;
+            seal(&self.key, key, &change(data)?)
+        })
+    }
+
+    fn list(&self, prefix: &str) -> Result<Vec<String>, String> {
+        self.inner.list(prefix)
+    }
+
+    fn delete(&self, key: &str) -> Result<(), String> {
+        self.inner.delete(key)
+    }
+
+    fn local_root(&self) -> Option<&Path> {
+        self.inner.local_root()
+    }
+}
+
+/// Wrap a backend holding a vault so its objects are decrypted on read and encrypted on write
+pub fn wrap(inner: Box<dyn Backend>) -> Result<Box<dyn Backend>, String> {
+    match read_vault(&*inner)? {
+        Some(vault) => {
+            let key = unlock(&vault)?;
+            Ok(Box::new(VaultBackend { inner, key }))
+        }
+        None => Ok(inner),
+    }
+}
+
+pub fn read_vault(backend: &dyn Backend) -> Result<Option<VaultFile>, String> {
+    match backend.get(VAULT_FILE)? {
+        Some(contents) => parse_vault(&contents).map(Some),
+        None => Ok(None),
+    }
+}
+
+pub fn parse_vault(contents: &[u8]) -> Result<VaultFile, String> {
+    toml::from_str(&String::from_utf8_lossy(contents)).map_err(|e| format!("Failed to parse {}: {}", VAULT_FILE, e))
+}
+
+/// Decrypt the object `key`, read straight from a repository directory or a git revision of it.
+/// Plain bytes only pass through in repositories without a vault.
+pub fn decode_at(repo_path: &Path, key: &str, data: Vec<u8>) -> Result<Vec<u8>, String> {
+    let path = repo_path.join(VAULT_FILE);
+    if PLAIN_FILES.contains(&key) || (!path.exists() && !is_sealed(&data)) {
+        return Ok(data);
+    }
+    let contents = fs::read(&path)
+        .map_err(|e| format!("Repository data is encrypted but {} is unreadable: {}", VAULT_FILE, e))?;
+    let data_key = unlock(&parse_vault(&contents)?)?;
+    open(&data_key, key, data).map_err(|e| format!("{}: {}", key, e))
+}
+
+/// Encrypt the object `key`, about to be written straight into a repository directory that has a vault
+pub fn encode_at(repo_path: &Path, key: &str, data: Vec<u8>) -> Result<Vec<u8>, String> {
+    let path = repo_path.join(VAULT_FILE);
+    if PLAIN_FILES.contains(&key) || !path.exists() {
+        return Ok(data);
+    }
+    let contents = fs::read(&path).map_err(|e| format!("Failed to read {}: {}", VAULT_FILE, e))?;
+    let data_key = unlock(&parse_vault(&contents)?)?;
+    seal(&data_key, key, &data)
+}
+
+/// Git commit message for `message` in the repository: a neutral one if it has a vault
+pub fn commit_message<'a>(repo_path: &Path, message: &'a str) -> &'a str {
+    if repo_path.join(VAULT_FILE).exists() {
+        NEUTRAL_MESSAGE
+    } else {
+        message
+    }
+}
+
+/// Random storage key, so the names under `store/` say nothing about the aliases
+pub fn random_name() -> String {
+    let mut bytes = [0u8; 8];
+    OsRng.fill_bytes(&mut bytes);
+    to_hex(&bytes)
+}
+
+/// Create a vault with a new random data key. Returns the vault and the data key.
+pub fn create(passphrase: &str) -> Result<(VaultFile, [u8; 32]), String> {
+    let key: [u8; 32] = XChaCha20Poly1305::generate_key(&mut OsRng).into();
+    let vault = wrap_key(&key, passphrase)?;
+    Ok((vault, key))
+}
+
+/// Wrap the data key with a new passphrase; the objects themselves are unchanged
+pub fn wrap_key(key: &[u8; 32], passphrase: &str) -> Result<VaultFile, String> {
+    let mut salt = [0u8; 16];
+    OsRng.fill_bytes(&mut salt);
+    let mut vault = VaultFile {
+        kdf: "argon2id".to_string(),
+        memory_kib: 64 * 1024,
+        iterations: 3,
+        parallelism: 1,
+        salt: to_hex(&salt),
+        wrapped_key: String::new(),
+        key_id: to_hex(&Sha256::digest(key)[..8]),
+    };
+    let kek = derive(&vault, passphrase)?;
+    vault.wrapped_key = to_hex(&encrypt(&kek, b"", key)?);
+    Ok(vault)
+}
+
+/// Serialized form of a vault, as stored in `vault.toml`
+pub fn vault_contents(vault: &VaultFile) -> Result<String, String> {
+    toml::to_string_pretty(vault).map_err(|e| format!("Failed to serialize {}: {}", VAULT_FILE, e))
+}
+
+/// Unwrap the data key with a passphrase
+pub fn unwrap_key(vault: &VaultFile, passphrase: &str) -> Result<[u8; 32], String> {
+    let kek = derive(vault, passphrase)?;
+    let key = decrypt(&kek, b"", &from_hex(&vault.wrapped_key)?).map_err(|_| "Wrong passphrase".to_string())?;
+    key.try_into().map_err(|_| format!("Invalid data key in {}", VAULT_FILE))
+}
+
+/// Data key of the vault, asking for the passphrase on first use
+pub fn unlock(vault: &VaultFile) -> Result<[u8; 32], String> {
+    let mut unlocked = UNLOCKED.lock().map_err(|_| "Vault lock poisoned".to_string())?;
+    if let Some((salt, key)) = unlocked.as_ref() {
+        if *salt == vault.salt {
+            return Ok(*key);
+        }
+    }
+    let key = unwrap_key(vault, &passphrase("Repository passphrase:")?)?;
+    *unlocked = Some((vault.salt.clone(), key));
+    Ok(key)
+}
+
+/// Keep a data key unlocked for the rest of the run, e.g. after the vault was created or rewrapped
+pub fn remember(vault: &VaultFile, key: [u8; 32]) {
+    if let Ok(mut unlocked) = UNLOCKED.lock() {
+        *unlocked = Some((vault.salt.clone(), key));
+    }
+}
+
+/// Passphrase from `encryption.passphrase_env` (default CONFSYNC_PASSPHRASE),
+/// the output of `encryption.passphrase_command`, or a prompt
+pub fn passphrase(question: &str) -> Result<String, String> {
+    if let Some(passphrase) = configured_passphrase()? {
+        return Ok(passphrase);
+    }
+    let env = passphrase_env()?;
+    ui::prompt_secret(question).map_err(|e| format!("{}. Set {} or encryption.passphrase_command.", e, env))
+}
+
+fn passphrase_env() -> Result<String, String> {
+    Ok(load_config()?.encryption.passphrase_env.unwrap_or_else(|| DEFAULT_PASSPHRASE_ENV.to_string()))
+}
+
+/// Passphrase from the environment or the passphrase command, if either is set up
+fn configured_passphrase() -> Result<Option<String>, String> {
+    if let Ok(passphrase) = std::env::var(passphrase_env()?) {
+        return Ok(Some(passphrase));
+    }
+    if let Some(command) = load_config()?.encryption.passphrase_command {
+        let output = Command::new("sh")
+            .args(["-c", &command])
+            .output()
+            .map_err(|e| format!("Failed to run passphrase command: {}", e))?;
+        if !output.status.success() {
+            return Err(format!("Passphrase command failed: {}", String::from_utf8_lossy(&output.stderr).trim()));
+        }
+        return Ok(Some(String::from_utf8_lossy(&output.stdout).trim_end_matches(['\r', '\n']).to_string()));
+    }
+    Ok(None)
+}
+
+/// A new passphrase: CONFSYNC_NEW_PASSPHRASE, the configured source if `configured` is set, or typed twice
+pub fn new_passphrase(configured: bool) -> Result<String, String> {
+    let from_config = if configured { configured_passphrase()? } else { None };
+    let passphrase = match std::env::var(NEW_PASSPHRASE_ENV).ok().or(from_config) {
+        Some(passphrase) => passphrase,
+        None => {
+            let first = ui::prompt_secret("New passphrase:")
+                .map_err(|e| format!("{}. Set {}.", e, NEW_PASSPHRASE_ENV))?;
+            if ui::prompt_secret("Repeat the new passphrase:")? != first {
+                return Err("Passphrases do not match".into());
+            }
+            first
+        }
+    };
+    if passphrase.is_empty() {
+        return Err("Passphrase is empty".into());
+    }
+    Ok(passphrase)
+}
+
+fn derive(vault: &VaultFile, passphrase: &str) -> Result<[u8; 32], String> {
+    if vault.kdf != "argon2id" {
+        return Err(format!("Unsupported key derivation {}", vault.kdf));
+    }
+    let params = Params::new(vault.memory_kib, vault.iterations, vault.parallelism, Some(32))
+        .map_err(|e| format!("Invalid key derivation parameters: {}", e))?;
+    let mut kek = [0u8; 32];
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(passphrase.as_bytes(), &from_hex(&vault.salt)?, &mut kek)
+        .map_err(|e| format!("Failed to derive key: {}", e))?;
+    Ok(kek)
+}
+
+/// Seal the object `key`: MAGIC || nonce || ciphertext, with the key as associated data
+pub fn seal(data_key: &[u8; 32], key: &str, data: &[u8]) -> Result<Vec<u8>, String> {
+    let mut sealed = MAGIC.to_vec();
+    sealed.extend(encrypt(data_key, key.as_bytes(), data)?);
+    Ok(sealed)
+}
+
+/// Open the object `key` written by `seal`. Anything else is refused: in a repository
+/// with a vault, plain data was put there by someone without the key.
+pub fn open(data_key: &[u8; 32], key: &str, data: Vec<u8>) -> Result<Vec<u8>, String> {
+    let Some(body) = data.strip_prefix(MAGIC) else {
+        return Err("Not encrypted, but the repository is: it was replaced outside confsync".into());
+    };
+    decrypt(data_key, key.as_bytes(), body)
+}
+
+/// nonce || ciphertext, with a random nonce
+fn encrypt(key: &[u8; 32], aad: &[u8], msg: &[u8]) -> Result<Vec<u8>, String> {
+    let cipher = XChaCha20Poly1305::new(Key::from_slice(key));
+    let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let ciphertext = cipher.encrypt(&nonce, Payload { msg, aad }).map_err(|_| "Failed to encrypt".to_string())?;
+    let mut sealed = nonce.to_vec();
+    sealed.extend(ciphertext);
+    Ok(sealed)
+}
+
+fn decrypt(key: &[u8; 32], aad: &[u8], body: &[u8]) -> Result<Vec<u8>, String> {
+    if body.len() < NONCE_LEN {
+        return Err("Encrypted data is truncated".into());
+    }
+    let (nonce, msg) = body.split_at(NONCE_LEN);
+    XChaCha20Poly1305::new(Key::from_slice(key))
+        .decrypt(XNonce::from_slice(nonce), Payload { msg, aad })
+        .map_err(|_| "Failed to decrypt: data was modified, moved or uses another key".to_string())
+}
+
+/// True for objects written through a vault
+pub fn is_sealed(data: &[u8]) -> bool {
+    data.starts_with(MAGIC)
+}
+
+/// True for objects that stay plaintext in an encrypted repository
+pub fn is_plain_file(key: &str) -> bool {
+    PLAIN_FILES.contains(&key)
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub fn from_hex(hex: &str) -> Result<Vec<u8>, String> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return Err("Invalid hex value".into());
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|_| "Invalid hex value".to_string()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: [u8; 32] = [3; 32];
+
+    #[test]
+    fn sealed_object_opens_under_its_key() {
+        let sealed = seal(&KEY, "store/a/content", b"secret").unwrap();
+        assert!(is_sealed(&sealed));
+        assert_eq!(open(&KEY, "store/a/content", sealed).unwrap(), b"secret");
+    }
+
+    #[test]
+    fn sealed_object_moved_to_another_key_fails() {
+        let sealed = seal(&KEY, "store/a/content", b"secret").unwrap();
+        assert!(open(&KEY, "store/b/content", sealed.clone()).is_err());
+        assert!(open(&KEY, "aliases.toml", sealed).is_err());
+    }
+
+    #[test]
+    fn plain_objects_are_refused() {
+        assert!(open(&KEY, "aliases.toml", b"[aliases]\n".to_vec()).is_err());
+    }
+
+    #[test]
+    fn wrapped_key_unwraps_with_its_passphrase_only() {
+        let mut vault = wrap_key(&KEY, "correct horse").unwrap();
+        // keep the test fast; the parameters are part of the vault file
+        vault.memory_kib = 8;
+        vault.iterations = 1;
+        let kek = derive(&vault, "correct horse").unwrap();
+        vault.wrapped_key = to_hex(&encrypt(&kek, b"", &KEY).unwrap());
+        assert_eq!(unwrap_key(&vault, "correct horse").unwrap(), KEY);
+        assert!(unwrap_key(&vault, "wrong").is_err());
+    }
+
+    #[test]
+    fn sealed_backend_keeps_the_contract() {
+        let root = std::env::temp_dir().join(format!("confsync-vault-backend-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        let backend = VaultBackend { inner: Box::new(crate::backend::LocalBackend::new(root.clone())), key: KEY };
+        crate::backend::tests::contract(&backend);
+        let stored = std::fs::read(root.join("history.log")).unwrap();
+        assert!(is_sealed(&stored));
+        let _ = std::fs::remove_dir_all(&root);
+    }
+}